@@ -1,13 +1,97 @@
+use std::fmt::{self, Display};
+
 use crate::{
-    opcode::OpCode,
+    object::{Function, Object},
+    opcode::{OpCode, OPCODE_SET_VERSION},
     value::{Value, ValueArray},
 };
 
-#[derive(Default)]
+// Layout of a serialized chunk, all integers little-endian:
+//
+//   header    "LOXC", format version (u8), opcode set version (u8)
+//   chunk     code length (u32), code bytes
+//             constant count (u32), tagged constants
+//             line run count (u32), runs of (line (i32), length (u32))
+//             local count (u32), locals of (name, slot (u32), start (u32),
+//             end (u32))
+//             upvalue count (u32), upvalue names
+//
+// Function constants carry their name, arity and upvalue count followed by
+// their own chunk, without a header.
+const MAGIC: &[u8; 4] = b"LOXC";
+const FORMAT_VERSION: u8 = 2;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_FUNCTION: u8 = 6;
+
+// How deep function constants may nest, so that reading them can't exhaust
+// the stack.
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    BadMagic,
+    UnsupportedFormat(u8),
+    OpcodeSetMismatch(u8),
+    UnexpectedEnd,
+    InvalidTag(u8),
+    InvalidString,
+    LineCountMismatch,
+    TooDeeplyNested,
+    TrailingBytes,
+}
+
+impl Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::BadMagic => write!(f, "Not a compiled Lox file."),
+            DeserializeError::UnsupportedFormat(v) => {
+                write!(f, "Unsupported file format version {}.", v)
+            }
+            DeserializeError::OpcodeSetMismatch(v) => write!(
+                f,
+                "Compiled for opcode set version {}, expected {}.",
+                v, OPCODE_SET_VERSION
+            ),
+            DeserializeError::UnexpectedEnd => write!(f, "Unexpected end of file."),
+            DeserializeError::InvalidTag(tag) => write!(f, "Invalid constant tag {}.", tag),
+            DeserializeError::InvalidString => write!(f, "Invalid UTF-8 in string constant."),
+            DeserializeError::LineCountMismatch => {
+                write!(f, "Line table doesn't match the code length.")
+            }
+            DeserializeError::TooDeeplyNested => {
+                write!(f, "Functions nested more than {} deep.", MAX_FUNCTION_DEPTH)
+            }
+            DeserializeError::TrailingBytes => write!(f, "Unexpected data after chunk."),
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+/// A named local variable, for debuggers. The variable lives in stack slot
+/// `slot` of its frame while executing code in `start..end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalVariable {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: ValueArray,
     pub lines: Vec<i32>,
+    pub locals: Vec<LocalVariable>,
+    // Names of the function's upvalues by index, for debuggers.
+    pub upvalues: Vec<String>,
 }
 
 impl Chunk {
@@ -42,8 +126,240 @@ impl Chunk {
     pub fn free_chunk(&mut self) {
         self.code.clear();
         self.lines.clear();
+        self.locals.clear();
+        self.upvalues.clear();
         self.constants.free_value_array();
     }
+
+    /// Encodes the chunk, including nested function chunks, in the `.loxc`
+    /// format.
+    ///
+    /// Panics if the constant pool holds a runtime object such as a closure,
+    /// which the compiler never emits.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(OPCODE_SET_VERSION);
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, DeserializeError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(DeserializeError::BadMagic);
+        }
+
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len(),
+        };
+
+        let format = reader.read_u8()?;
+        if format != FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedFormat(format));
+        }
+
+        let opcode_set = reader.read_u8()?;
+        if opcode_set != OPCODE_SET_VERSION {
+            return Err(DeserializeError::OpcodeSetMismatch(opcode_set));
+        }
+
+        let chunk = Chunk::read_from(&mut reader, 0)?;
+        if reader.position != bytes.len() {
+            return Err(DeserializeError::TrailingBytes);
+        }
+
+        Ok(chunk)
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        write_u32(bytes, self.code.len());
+        bytes.extend_from_slice(&self.code);
+
+        write_u32(bytes, self.constants.values.len());
+        for constant in &self.constants.values {
+            write_constant(bytes, constant);
+        }
+
+        let mut runs: Vec<(i32, usize)> = Vec::new();
+        for &line in &self.lines {
+            match runs.last_mut() {
+                Some((last, length)) if *last == line => *length += 1,
+                _ => runs.push((line, 1)),
+            }
+        }
+        write_u32(bytes, runs.len());
+        for (line, length) in runs {
+            bytes.extend_from_slice(&line.to_le_bytes());
+            write_u32(bytes, length);
+        }
+
+        write_u32(bytes, self.locals.len());
+        for local in &self.locals {
+            write_string(bytes, &local.name);
+            write_u32(bytes, local.slot);
+            write_u32(bytes, local.start);
+            write_u32(bytes, local.end);
+        }
+
+        write_u32(bytes, self.upvalues.len());
+        for name in &self.upvalues {
+            write_string(bytes, name);
+        }
+    }
+
+    fn read_from(reader: &mut Reader, depth: usize) -> Result<Chunk, DeserializeError> {
+        let mut chunk = Chunk::new();
+
+        let code_length = reader.read_u32()?;
+        chunk.code = reader.take(code_length)?.to_vec();
+
+        let constant_count = reader.read_u32()?;
+        for _ in 0..constant_count {
+            let constant = read_constant(reader, depth)?;
+            chunk.add_constant(constant);
+        }
+
+        let run_count = reader.read_u32()?;
+        for _ in 0..run_count {
+            let line = i32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+            let length = reader.read_u32()?;
+            // There is one line per byte of code, checked before allocating.
+            if length > chunk.code.len() - chunk.lines.len() {
+                return Err(DeserializeError::LineCountMismatch);
+            }
+            chunk.lines.extend(std::iter::repeat_n(line, length));
+        }
+        if chunk.lines.len() != chunk.code.len() {
+            return Err(DeserializeError::LineCountMismatch);
+        }
+
+        let local_count = reader.read_u32()?;
+        for _ in 0..local_count {
+            chunk.locals.push(LocalVariable {
+                name: reader.read_string()?,
+                slot: reader.read_u32()?,
+                start: reader.read_u32()?,
+                end: reader.read_u32()?,
+            });
+        }
+
+        let upvalue_count = reader.read_u32()?;
+        for _ in 0..upvalue_count {
+            chunk.upvalues.push(reader.read_string()?);
+        }
+
+        Ok(chunk)
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_constant(bytes: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => bytes.push(TAG_NIL),
+        Value::Boolean(false) => bytes.push(TAG_FALSE),
+        Value::Boolean(true) => bytes.push(TAG_TRUE),
+        Value::Number(v) => {
+            bytes.push(TAG_NUMBER);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::Float(v) => {
+            bytes.push(TAG_FLOAT);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::Object(object) => match &**object {
+            Object::String(v) => {
+                bytes.push(TAG_STRING);
+                write_string(bytes, &v.chars);
+            }
+            Object::Function(function) => {
+                bytes.push(TAG_FUNCTION);
+                match &function.name {
+                    Some(name) => {
+                        bytes.push(1);
+                        write_string(bytes, name);
+                    }
+                    None => bytes.push(0),
+                }
+                write_u32(bytes, function.arity);
+                write_u32(bytes, function.upvalue_count);
+                function.chunk.write_to(bytes);
+            }
+            _ => panic!("Can't serialize a {} constant.", value.type_name()),
+        },
+    }
+}
+
+fn read_constant(reader: &mut Reader, depth: usize) -> Result<Value, DeserializeError> {
+    let value = match reader.read_u8()? {
+        TAG_NIL => Value::new_nil(),
+        TAG_FALSE => Value::new_bool(false),
+        TAG_TRUE => Value::new_bool(true),
+        TAG_NUMBER => Value::new_number(i64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+        TAG_FLOAT => Value::new_float(f64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+        TAG_STRING => Value::new_obj_string(reader.read_string()?),
+        TAG_FUNCTION => {
+            let name = match reader.read_u8()? {
+                0 => None,
+                _ => Some(reader.read_string()?),
+            };
+            let arity = reader.read_u32()?;
+            let upvalue_count = reader.read_u32()?;
+            if depth >= MAX_FUNCTION_DEPTH {
+                return Err(DeserializeError::TooDeeplyNested);
+            }
+            let chunk = Chunk::read_from(reader, depth + 1)?;
+            Value::new_obj_function(Function {
+                arity,
+                upvalue_count,
+                chunk,
+                name,
+            })
+        }
+        tag => return Err(DeserializeError::InvalidTag(tag)),
+    };
+
+    Ok(value)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DeserializeError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DeserializeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<usize, DeserializeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, DeserializeError> {
+        let length = self.read_u32()?;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| DeserializeError::InvalidString)
+    }
 }
 
 #[cfg(test)]
@@ -106,7 +422,7 @@ mod tests {
                 "Write constant did not emit a constant operation, found: `{:?}`",
                 OpCode::try_from(chunk.code[chunk.code.len() - 2]),
             );
-            i = i + 1;
+            i += 1;
         }
 
         assert_eq!(
@@ -116,4 +432,125 @@ mod tests {
             OpCode::try_from(chunk.code[chunk.code.len() - 4]),
         );
     }
+
+    fn sample_chunk() -> Chunk {
+        let mut inner = Chunk::new();
+        inner.write_constant(Value::from("inner"), 2);
+        inner.write_chunk(OpCode::OpReturn as u8, 2);
+        inner.upvalues.push("x".to_string());
+
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::Number(-7), 1);
+        chunk.write_constant(Value::Float(2.5), 1);
+        chunk.write_constant(Value::from("lox"), 1);
+        chunk.write_constant(Value::Nil, 3);
+        chunk.write_constant(Value::Boolean(true), 3);
+        chunk.write_constant(
+            Value::new_obj_function(Function {
+                arity: 2,
+                upvalue_count: 1,
+                chunk: inner,
+                name: Some("f".to_string()),
+            }),
+            4,
+        );
+        chunk.write_chunk(OpCode::OpReturn as u8, 4);
+        chunk.locals.push(LocalVariable {
+            name: "a".to_string(),
+            slot: 1,
+            start: 2,
+            end: 6,
+        });
+        chunk
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let chunk = sample_chunk();
+
+        let bytes = chunk.serialize();
+        let decoded = Chunk::deserialize(&bytes).unwrap();
+
+        assert_eq!(decoded.code, chunk.code);
+        assert_eq!(decoded.lines, chunk.lines);
+        assert_eq!(decoded.locals, chunk.locals);
+        assert_eq!(
+            format!("{:?}", decoded.constants.values),
+            format!("{:?}", chunk.constants.values)
+        );
+
+        let function = decoded.constants.values[5].as_function();
+        assert_eq!((function.arity, function.upvalue_count), (2, 1));
+        assert_eq!(function.chunk.lines, vec![2, 2, 2]);
+        assert_eq!(function.chunk.upvalues, vec!["x"]);
+        assert_eq!(decoded.serialize(), bytes);
+    }
+
+    #[test]
+    fn deserialize_rejects_bad_input() {
+        let bytes = sample_chunk().serialize();
+
+        assert_eq!(
+            Chunk::deserialize(b"LUA!").unwrap_err(),
+            DeserializeError::BadMagic
+        );
+
+        let mut other_opcodes = bytes.clone();
+        other_opcodes[5] = OPCODE_SET_VERSION + 1;
+        assert_eq!(
+            Chunk::deserialize(&other_opcodes).unwrap_err(),
+            DeserializeError::OpcodeSetMismatch(OPCODE_SET_VERSION + 1)
+        );
+
+        assert_eq!(
+            Chunk::deserialize(&bytes[..bytes.len() - 1]).unwrap_err(),
+            DeserializeError::UnexpectedEnd
+        );
+    }
+
+    #[test]
+    fn deserialize_rejects_line_runs_longer_than_the_code() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::OpReturn as u8, 1);
+        let bytes = chunk.serialize();
+
+        // The length of the only line run, the last field before the empty
+        // local and upvalue lists.
+        let run_length = bytes.len() - 12;
+        let mut long_run = bytes.clone();
+        long_run[run_length..run_length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Chunk::deserialize(&long_run).unwrap_err(),
+            DeserializeError::LineCountMismatch
+        );
+
+        let mut short_run = bytes.clone();
+        short_run[run_length..run_length + 4].copy_from_slice(&0_u32.to_le_bytes());
+        assert_eq!(
+            Chunk::deserialize(&short_run).unwrap_err(),
+            DeserializeError::LineCountMismatch
+        );
+    }
+
+    #[test]
+    fn deserialize_limits_function_nesting() {
+        let nested = |depth: usize| {
+            let mut chunk = Chunk::new();
+            for _ in 0..depth {
+                let function = Function {
+                    chunk,
+                    ..Function::new()
+                };
+                chunk = Chunk::new();
+                chunk.add_constant(Value::new_obj_function(function));
+            }
+            chunk.serialize()
+        };
+
+        assert!(Chunk::deserialize(&nested(MAX_FUNCTION_DEPTH)).is_ok());
+        assert_eq!(
+            Chunk::deserialize(&nested(MAX_FUNCTION_DEPTH + 1)).unwrap_err(),
+            DeserializeError::TooDeeplyNested
+        );
+    }
 }
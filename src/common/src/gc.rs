@@ -0,0 +1,454 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::{self, Debug},
+    mem,
+    ops::Deref,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+use crate::{
+    object::{ObjRef, Object},
+    value::{Value, ValueTypeError},
+};
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+struct GcBox<T> {
+    marked: Cell<bool>,
+    value: T,
+}
+
+/// A pointer to an object owned by a `Heap`.
+///
+/// The pointer is only valid while the object is reachable from the roots
+/// handed to the collector, or until the heap itself is dropped. Only
+/// `Heap::alloc`, which is unsafe, creates one; values that leave the VM are
+/// wrapped in a `Handle` instead.
+pub struct Gc<T> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T> Gc<T> {
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    fn is_marked(&self) -> bool {
+        unsafe { self.ptr.as_ref() }.marked.get()
+    }
+
+    fn set_marked(&self, marked: bool) {
+        unsafe { self.ptr.as_ref() }.marked.set(marked)
+    }
+}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+// Objects can refer to themselves, so only the address is printed.
+impl<T> Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Gc({:p})", self.ptr)
+    }
+}
+
+// The objects of a heap, shared with the handles into it so that none is
+// freed while a handle can still reach it.
+#[derive(Default)]
+struct Arena {
+    objects: RefCell<Vec<Gc<Object>>>,
+    // The objects held by handles, with how many handles hold each.
+    pinned: RefCell<HashMap<NonNull<GcBox<Object>>, usize>>,
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for object in self.objects.get_mut().drain(..) {
+            unsafe { drop(Box::from_raw(object.ptr.as_ptr())) };
+        }
+    }
+}
+
+/// A value held outside of the VM, for example by the host or a debugger.
+///
+/// Until it is dropped, the value is a root of every collection, and the
+/// objects of its heap outlive the heap itself. The value itself only goes
+/// back into the heap it came from, through `Heap::value_of`, so that no
+/// pointer into the heap outlives the handle.
+pub struct Handle {
+    value: Value,
+    // Only values that point into a heap have one.
+    arena: Option<Rc<Arena>>,
+}
+
+impl Handle {
+    fn new(value: Value, arena: &Rc<Arena>) -> Self {
+        match &value {
+            Value::Object(ObjRef::Heap(object)) => {
+                *arena.pinned.borrow_mut().entry(object.ptr).or_insert(0) += 1;
+                Handle {
+                    value,
+                    arena: Some(arena.clone()),
+                }
+            }
+            _ => Handle { value, arena: None },
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.value.type_name()
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Self {
+        match &self.arena {
+            Some(arena) => Handle::new(self.value.clone(), arena),
+            None => Handle {
+                value: self.value.clone(),
+                arena: None,
+            },
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let (Value::Object(ObjRef::Heap(object)), Some(arena)) = (&self.value, &self.arena) {
+            let mut pinned = arena.pinned.borrow_mut();
+            if let Some(count) = pinned.get_mut(&object.ptr) {
+                *count -= 1;
+                if *count == 0 {
+                    pinned.remove(&object.ptr);
+                }
+            }
+        }
+    }
+}
+
+impl Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+// Only conversions from and to types that can't point into the heap.
+macro_rules! impl_from_for_handle {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Handle {
+                fn from(value: $t) -> Self {
+                    Handle {
+                        value: Value::from(value),
+                        arena: None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_handle!(bool, i64, f64, String, &str, ());
+
+macro_rules! impl_try_from_handle {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Handle> for $t {
+                type Error = ValueTypeError;
+
+                fn try_from(handle: Handle) -> Result<Self, Self::Error> {
+                    <$t>::try_from(handle.value.clone())
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_handle!(
+    bool,
+    i64,
+    f64,
+    String,
+    (),
+    Option<bool>,
+    Option<i64>,
+    Option<f64>,
+    Option<String>
+);
+
+/// Owns every object that can take part in a reference cycle: closures,
+/// upvalues, classes, instances and bound methods. Strings, functions and
+/// natives can't refer back to these, so they stay reference counted.
+///
+/// Collection is mark-and-sweep. The owner marks its roots with
+/// `mark_value` and `mark_object`, then calls `collect` to trace everything
+/// reachable from them and the values held by handles, and free the rest.
+pub struct Heap {
+    arena: Rc<Arena>,
+    gray_stack: Vec<Gc<Object>>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    growth_factor: usize,
+    stress: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            arena: Rc::default(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            growth_factor: GC_HEAP_GROW_FACTOR,
+            stress: false,
+        }
+    }
+
+    /// Moves `object` onto the heap. This never collects, callers are expected
+    /// to check `should_collect` first while all their values are rooted.
+    ///
+    /// # Safety
+    ///
+    /// The returned pointer, and every value holding it, must not be used
+    /// once the object is freed: by a `collect` it wasn't reachable in, or
+    /// when both the heap and its handles are dropped.
+    pub unsafe fn alloc(&mut self, object: Object) -> Gc<Object> {
+        let boxed = Box::new(GcBox {
+            marked: Cell::new(false),
+            value: object,
+        });
+        let gc = Gc {
+            ptr: NonNull::from(Box::leak(boxed)),
+        };
+
+        self.bytes_allocated += mem::size_of::<GcBox<Object>>();
+        self.arena.objects.borrow_mut().push(gc);
+        gc
+    }
+
+    /// Keeps `value` alive until the returned handle is dropped.
+    pub fn pin(&self, value: Value) -> Handle {
+        Handle::new(value, &self.arena)
+    }
+
+    /// The value held by `handle`, unless it points into another heap.
+    pub fn value_of(&self, handle: &Handle) -> Option<Value> {
+        match &handle.arena {
+            Some(arena) if !Rc::ptr_eq(arena, &self.arena) => None,
+            _ => Some(handle.value.clone()),
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// After a collection the next one is triggered once the heap has grown
+    /// by this factor.
+    pub fn set_growth_factor(&mut self, growth_factor: usize) {
+        self.growth_factor = growth_factor.max(1);
+    }
+
+    /// Collects on every allocation. Meant for tests that shake out objects
+    /// which aren't properly rooted.
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.arena.objects.borrow().len()
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if let Value::Object(ObjRef::Heap(object)) = value {
+            self.mark_object(*object);
+        }
+    }
+
+    pub fn mark_object(&mut self, object: Gc<Object>) {
+        if object.is_marked() {
+            return;
+        }
+
+        object.set_marked(true);
+        self.gray_stack.push(object);
+    }
+
+    /// Traces from the marked roots and frees every object that wasn't
+    /// reached.
+    ///
+    /// # Safety
+    ///
+    /// Every object the caller still uses must be marked or held by a
+    /// handle, anything else is freed.
+    pub unsafe fn collect(&mut self) {
+        let pinned: Vec<_> = self.arena.pinned.borrow().keys().copied().collect();
+        for ptr in pinned {
+            self.mark_object(Gc { ptr });
+        }
+
+        self.trace_references();
+        self.sweep();
+
+        self.next_gc = (self.bytes_allocated * self.growth_factor).max(GC_INITIAL_THRESHOLD);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(object) = self.gray_stack.pop() {
+            self.blacken_object(object);
+        }
+    }
+
+    fn blacken_object(&mut self, object: Gc<Object>) {
+        match &*object {
+            Object::Closure(v) => {
+                for upvalue in &v.upvalues {
+                    self.mark_object(*upvalue);
+                }
+            }
+            Object::Upvalue(v) => {
+                if let Some(closed) = &v.borrow().closed {
+                    self.mark_value(closed);
+                }
+            }
+            Object::Class(v) => {
+                for method in v.borrow().methods.values() {
+                    self.mark_value(method);
+                }
+            }
+            Object::Instance(v) => {
+                let instance = v.borrow();
+                self.mark_object(instance.class);
+                for field in instance.fields.values() {
+                    self.mark_value(field);
+                }
+            }
+            Object::BoundMethod(v) => {
+                self.mark_value(&v.receiver);
+                self.mark_object(v.method);
+            }
+            Object::String(_) | Object::Function(_) | Object::Native(_) => {}
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut freed = 0;
+        self.arena.objects.borrow_mut().retain(|object| {
+            if object.is_marked() {
+                object.set_marked(false);
+                return true;
+            }
+
+            unsafe { drop(Box::from_raw(object.ptr.as_ptr())) };
+            freed += 1;
+            false
+        });
+
+        self.bytes_allocated -= freed * mem::size_of::<GcBox<Object>>();
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Class, Instance};
+    use std::cell::RefCell;
+
+    fn class(heap: &mut Heap) -> Gc<Object> {
+        // Safety: each test only uses its objects while they are reachable.
+        unsafe { heap.alloc(Object::Class(RefCell::new(Class::new("A".to_string())))) }
+    }
+
+    fn instance(heap: &mut Heap, class: Gc<Object>) -> Gc<Object> {
+        unsafe { heap.alloc(Object::Instance(RefCell::new(Instance::new(class)))) }
+    }
+
+    #[test]
+    fn frees_unreachable_cycles() {
+        let mut heap = Heap::new();
+
+        let class = class(&mut heap);
+        let kept = instance(&mut heap, class);
+        let dropped = instance(&mut heap, class);
+        dropped
+            .as_instance()
+            .borrow_mut()
+            .fields
+            .insert("me".to_string(), Value::new_obj_gc(dropped));
+
+        heap.mark_object(kept);
+        unsafe { heap.collect() };
+
+        assert_eq!(heap.object_count(), 2);
+        assert_eq!(
+            kept.as_instance().borrow().class.as_class().borrow().name,
+            "A"
+        );
+    }
+
+    #[test]
+    fn handles_root_their_values() {
+        let mut heap = Heap::new();
+
+        let class = class(&mut heap);
+        let instance = instance(&mut heap, class);
+        let handle = heap.pin(Value::new_obj_gc(instance));
+        let copy = handle.clone();
+
+        drop(handle);
+        unsafe { heap.collect() };
+        assert_eq!(heap.object_count(), 2);
+
+        drop(copy);
+        unsafe { heap.collect() };
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    #[test]
+    fn handles_outlive_their_heap() {
+        let mut heap = Heap::new();
+        let class = class(&mut heap);
+        let handle = heap.pin(Value::new_obj_gc(class));
+
+        drop(heap);
+
+        assert_eq!(format!("{:?}", handle), "A");
+    }
+
+    #[test]
+    fn handles_only_go_back_into_their_heap() {
+        let mut heap = Heap::new();
+        let class = class(&mut heap);
+        let handle = heap.pin(Value::new_obj_gc(class));
+
+        assert!(heap.value_of(&handle).unwrap().is_class());
+        assert!(Heap::new().value_of(&handle).is_none());
+        assert!(Heap::new().value_of(&Handle::from(1_i64)).unwrap().is_int());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    object::{LoxString, Object},
+    value::Value,
+};
+
+thread_local! {
+    // Strings drop their own entry, so everything in here is alive.
+    static STRINGS: RefCell<HashMap<Rc<str>, Weak<Object>>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the string object for `chars`, creating it if no live string has
+/// the same contents. Equal strings are therefore always the same object.
+pub fn intern(chars: &str) -> Rc<Object> {
+    STRINGS.with(|strings| {
+        let mut strings = strings.borrow_mut();
+        if let Some(string) = strings.get(chars).and_then(Weak::upgrade) {
+            return string;
+        }
+
+        let chars: Rc<str> = Rc::from(chars);
+        let string = Rc::new(Object::String(LoxString::new(
+            chars.clone(),
+            hash_string(&chars),
+        )));
+        strings.insert(chars, Rc::downgrade(&string));
+        string
+    })
+}
+
+pub(crate) fn release(chars: &str) {
+    // The table is already gone if this runs while the thread shuts down.
+    let _ = STRINGS.try_with(|strings| {
+        strings.borrow_mut().remove(chars);
+    });
+}
+
+// FNV-1a.
+pub fn hash_string(chars: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in chars.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A map key for an interned string. Hashing reuses the hash stored in the
+/// string and equality is pointer comparison.
+#[derive(Debug, Clone)]
+pub struct StringKey(Rc<Object>);
+
+impl StringKey {
+    pub fn new(chars: &str) -> Self {
+        StringKey(intern(chars))
+    }
+
+    pub fn from_value(value: &Value) -> Self {
+        assert!(value.is_string());
+        StringKey(value.as_rc().clone())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_string()
+    }
+}
+
+impl Hash for StringKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.as_lox_string().hash);
+    }
+}
+
+impl PartialEq for StringKey {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for StringKey {}
+
+/// Passes a precomputed `u64` hash straight through.
+#[derive(Default)]
+pub struct PrehashedHasher(u64);
+
+impl Hasher for PrehashedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+}
+
+pub type StringMap<V> = HashMap<StringKey, V, BuildHasherDefault<PrehashedHasher>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_strings_share_one_object() {
+        let a = intern("lox");
+        let b = intern(&format!("{}{}", "lo", "x"));
+        let c = intern("clox");
+
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn dropped_strings_leave_the_table() {
+        let chars = "only used by this test";
+        drop(intern(chars));
+
+        STRINGS.with(|strings| assert!(!strings.borrow().contains_key(chars)));
+    }
+
+    #[test]
+    // Keys only hash and compare the string, which never changes.
+    #[allow(clippy::mutable_key_type)]
+    fn string_keys_find_interned_strings() {
+        let mut map = StringMap::default();
+        map.insert(StringKey::new("answer"), 42);
+
+        assert_eq!(
+            map.get(&StringKey::from_value(&Value::from("answer"))),
+            Some(&42)
+        );
+        assert_eq!(map.get(&StringKey::new("other")), None);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod gc;
+pub mod intern;
 pub mod object;
 pub mod value;
 pub mod chunk;
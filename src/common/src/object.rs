@@ -1,4 +1,231 @@
+use std::{cell::RefCell, collections::HashMap, ops::Deref, rc::Rc};
+
+use crate::{chunk::Chunk, gc::Gc, intern, value::Value};
+
 #[derive(Debug)]
 pub enum Object {
-    String(String),
+    String(LoxString),
+    Function(Function),
+    Closure(Closure),
+    Upvalue(RefCell<Upvalue>),
+    Class(RefCell<Class>),
+    Instance(RefCell<Instance>),
+    BoundMethod(BoundMethod),
+    Native(Native),
+}
+
+/// How a `Value` refers to an object. Strings, functions and natives can't
+/// form cycles and are reference counted; everything else lives on a `Heap`.
+#[derive(Debug, Clone)]
+pub enum ObjRef {
+    Shared(Rc<Object>),
+    Heap(Gc<Object>),
+}
+
+impl ObjRef {
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        match (this, other) {
+            (ObjRef::Shared(a), ObjRef::Shared(b)) => Rc::ptr_eq(a, b),
+            (ObjRef::Heap(a), ObjRef::Heap(b)) => Gc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Deref for ObjRef {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        match self {
+            ObjRef::Shared(v) => v,
+            ObjRef::Heap(v) => v,
+        }
+    }
+}
+
+impl Object {
+    pub fn as_lox_string(&self) -> &LoxString {
+        match self {
+            Object::String(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_string(&self) -> &str {
+        &self.as_lox_string().chars
+    }
+
+    pub fn as_function(&self) -> &Function {
+        match self {
+            Object::Function(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_closure(&self) -> &Closure {
+        match self {
+            Object::Closure(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_upvalue(&self) -> &RefCell<Upvalue> {
+        match self {
+            Object::Upvalue(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_class(&self) -> &RefCell<Class> {
+        match self {
+            Object::Class(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_instance(&self) -> &RefCell<Instance> {
+        match self {
+            Object::Instance(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_bound_method(&self) -> &BoundMethod {
+        match self {
+            Object::BoundMethod(v) => v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_native(&self) -> &Native {
+        match self {
+            Object::Native(v) => v,
+            _ => panic!(),
+        }
+    }
+}
+
+/// String contents together with their hash. Only created through
+/// `intern::intern`, which keeps one object per distinct string.
+#[derive(Debug)]
+pub struct LoxString {
+    pub chars: Rc<str>,
+    pub hash: u64,
+}
+
+impl LoxString {
+    pub(crate) fn new(chars: Rc<str>, hash: u64) -> Self {
+        LoxString { chars, hash }
+    }
+}
+
+impl Drop for LoxString {
+    fn drop(&mut self) {
+        intern::release(&self.chars);
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Function {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<String>,
+}
+
+impl Function {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Object>,
+    pub upvalues: Vec<Gc<Object>>,
+}
+
+impl Closure {
+    pub fn new(function: Rc<Object>) -> Self {
+        let upvalue_count = function.as_function().upvalue_count;
+        Closure {
+            function,
+            upvalues: Vec::with_capacity(upvalue_count),
+        }
+    }
+}
+
+/// A variable captured by a closure. While open, `location` indexes the VM
+/// stack slot that still holds the value; once closed, the value is moved
+/// into `closed`.
+#[derive(Debug)]
+pub struct Upvalue {
+    pub location: usize,
+    pub closed: Option<Value>,
+}
+
+impl Upvalue {
+    pub fn new(location: usize) -> Self {
+        Upvalue {
+            location,
+            closed: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub methods: HashMap<String, Value>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Self {
+        Class {
+            name,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Gc<Object>,
+    pub fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Gc<Object>) -> Self {
+        Instance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Gc<Object>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Gc<Object>) -> Self {
+        BoundMethod { receiver, method }
+    }
+}
+
+// A function implemented by the host. The implementation itself lives in the
+// VM, `id` identifies it there.
+#[derive(Debug)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub id: usize,
+}
+
+impl Native {
+    pub fn new(name: String, arity: usize, id: usize) -> Self {
+        Native { name, arity, id }
+    }
 }
@@ -1,6 +1,10 @@
 use num_enum::TryFromPrimitive;
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+// Stored in serialized chunks. Bump it whenever opcodes are added, removed,
+// reordered or change their operands.
+pub const OPCODE_SET_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum OpCode {
     OpReturn,
@@ -33,4 +37,105 @@ pub enum OpCode {
     OpJumpIfFalse,
     OpJump,
     OpLoop,
+    OpCall,
+    OpClosure,
+    OpClosureLong,
+    OpGetUpvalue,
+    OpSetUpvalue,
+    OpCloseUpvalue,
+    OpClass,
+    OpClassLong,
+    OpGetProperty,
+    OpGetPropertyLong,
+    OpSetProperty,
+    OpSetPropertyLong,
+    OpMethod,
+    OpMethodLong,
+    OpInvoke,
+    OpInvokeLong,
+    OpInherit,
+    OpGetSuper,
+    OpGetSuperLong,
+    OpSuperInvoke,
+    OpSuperInvokeLong,
+}
+
+impl OpCode {
+    // The name used in disassembly listings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::OpReturn => "OP_RETURN",
+            OpCode::OpConstant => "OP_CONSTANT",
+            OpCode::OpConstantLong => "OP_CONSTANT_LONG",
+            OpCode::OpAdd => "OP_ADD",
+            OpCode::OpSubtract => "OP_SUBTRACT",
+            OpCode::OpMultiply => "OP_MULTIPLY",
+            OpCode::OpDivide => "OP_DIVIDE",
+            OpCode::OpNegate => "OP_NEGATE",
+            OpCode::OpNil => "OP_NIL",
+            OpCode::OpTrue => "OP_TRUE",
+            OpCode::OpFalse => "OP_FALSE",
+            OpCode::OpNot => "OP_NOT",
+            OpCode::OpEqual => "OP_EQUAL",
+            OpCode::OpGreater => "OP_GREATER",
+            OpCode::OpLess => "OP_LESS",
+            OpCode::OpPrint => "OP_PRINT",
+            OpCode::OpPop => "OP_POP",
+            OpCode::OpDefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::OpDefineGlobalLong => "OP_DEFINE_GLOBAL_LONG",
+            OpCode::OpGetGlobal => "OP_GET_GLOBAL",
+            OpCode::OpGetGlobalLong => "OP_GET_GLOBAL_LONG",
+            OpCode::OpSetGlobal => "OP_SET_GLOBAL",
+            OpCode::OpSetGlobalLong => "OP_SET_GLOBAL_LONG",
+            OpCode::OpGetLocal => "OP_GET_LOCAL",
+            OpCode::OpGetLocalLong => "OP_GET_LOCAL_LONG",
+            OpCode::OpSetLocal => "OP_SET_LOCAL",
+            OpCode::OpSetLocalLong => "OP_SET_LOCAL_LONG",
+            OpCode::OpJumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::OpJump => "OP_JUMP",
+            OpCode::OpLoop => "OP_LOOP",
+            OpCode::OpCall => "OP_CALL",
+            OpCode::OpClosure => "OP_CLOSURE",
+            OpCode::OpClosureLong => "OP_CLOSURE_LONG",
+            OpCode::OpGetUpvalue => "OP_GET_UPVALUE",
+            OpCode::OpSetUpvalue => "OP_SET_UPVALUE",
+            OpCode::OpCloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::OpClass => "OP_CLASS",
+            OpCode::OpClassLong => "OP_CLASS_LONG",
+            OpCode::OpGetProperty => "OP_GET_PROPERTY",
+            OpCode::OpGetPropertyLong => "OP_GET_PROPERTY_LONG",
+            OpCode::OpSetProperty => "OP_SET_PROPERTY",
+            OpCode::OpSetPropertyLong => "OP_SET_PROPERTY_LONG",
+            OpCode::OpMethod => "OP_METHOD",
+            OpCode::OpMethodLong => "OP_METHOD_LONG",
+            OpCode::OpInvoke => "OP_INVOKE",
+            OpCode::OpInvokeLong => "OP_INVOKE_LONG",
+            OpCode::OpInherit => "OP_INHERIT",
+            OpCode::OpGetSuper => "OP_GET_SUPER",
+            OpCode::OpGetSuperLong => "OP_GET_SUPER_LONG",
+            OpCode::OpSuperInvoke => "OP_SUPER_INVOKE",
+            OpCode::OpSuperInvokeLong => "OP_SUPER_INVOKE_LONG",
+        }
+    }
+
+    // The inverse of `name`.
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        (0..=OpCode::OpSuperInvokeLong as u8)
+            .filter_map(|byte| OpCode::try_from(byte).ok())
+            .find(|opcode| opcode.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for byte in 0..=OpCode::OpSuperInvokeLong as u8 {
+            let opcode = OpCode::try_from(byte).unwrap();
+            assert_eq!(OpCode::from_name(opcode.name()), Some(opcode));
+        }
+        assert_eq!(OpCode::from_name("OP_UNKNOWN"), None);
+    }
 }
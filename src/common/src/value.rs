@@ -1,33 +1,52 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
 use std::rc::Rc;
 
-use crate::object::Object;
+use crate::gc::Gc;
+use crate::intern;
+use crate::object::{Closure, Function, Native, ObjRef, Object};
 
 #[derive(Clone)]
 pub enum Value {
     Boolean(bool),
     Number(i64),
-    Object(Rc<Object>),
+    Float(f64),
+    Object(ObjRef),
     Nil,
 }
 
 impl Debug for Value {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Boolean(v) => {
                 if *v {
-                    print!("true");
+                    write!(f, "true")
                 } else {
-                    print!("false");
+                    write!(f, "false")
                 }
             }
-            Value::Number(v) => print!("{}", *v),
+            Value::Number(v) => write!(f, "{}", *v),
+            Value::Float(v) => write!(f, "{:?}", *v),
             Value::Object(r) => match &**r {
-                Object::String(v) => print!("{}", v),
+                Object::String(v) => write!(f, "{}", v.chars),
+                Object::Function(v) => match &v.name {
+                    Some(name) => write!(f, "<fn {}>", name),
+                    None => write!(f, "<script>"),
+                },
+                Object::Closure(v) => {
+                    write!(f, "{:?}", Value::new_obj(v.function.clone()))
+                }
+                Object::Upvalue(_) => write!(f, "upvalue"),
+                Object::Class(v) => write!(f, "{}", v.borrow().name),
+                Object::Instance(v) => {
+                    write!(f, "{} instance", v.borrow().class.as_class().borrow().name)
+                }
+                Object::BoundMethod(v) => {
+                    write!(f, "{:?}", Value::new_obj_gc(v.method))
+                }
+                Object::Native(_) => write!(f, "<native fn>"),
             },
-            Value::Nil => print!("nil"),
-        };
-        Ok(())
+            Value::Nil => write!(f, "nil"),
+        }
     }
 }
 
@@ -44,12 +63,28 @@ impl Value {
         Value::Number(val)
     }
 
+    pub fn new_float(val: f64) -> Self {
+        Value::Float(val)
+    }
+
     pub fn new_obj(obj: Rc<Object>) -> Self {
-        Value::Object(obj)
+        Value::Object(ObjRef::Shared(obj))
+    }
+
+    pub fn new_obj_gc(obj: Gc<Object>) -> Self {
+        Value::Object(ObjRef::Heap(obj))
     }
 
     pub fn new_obj_string(s: String) -> Self {
-        Value::Object(Rc::new(Object::String(s)))
+        Value::new_obj(intern::intern(&s))
+    }
+
+    pub fn new_obj_function(function: Function) -> Self {
+        Value::new_obj(Rc::new(Object::Function(function)))
+    }
+
+    pub fn new_obj_native(native: Native) -> Self {
+        Value::new_obj(Rc::new(Object::Native(native)))
     }
 
     pub fn as_bool(&self) -> bool {
@@ -66,20 +101,48 @@ impl Value {
         }
     }
 
-    pub fn as_obj(&self) -> &Rc<Object> {
+    // Integers are promoted, so this can be used on either numeric variant.
+    pub fn as_float(&self) -> f64 {
+        match self {
+            Value::Number(v) => *v as f64,
+            Value::Float(v) => *v,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_obj(&self) -> &Object {
         match self {
             Value::Object(r) => r,
             _ => panic!(),
         }
     }
 
-    pub fn as_string(&self) -> &str {
-        match &**self.as_obj() {
-            Object::String(v) => v,
-            // _ => panic!(),
+    pub fn as_rc(&self) -> &Rc<Object> {
+        match self {
+            Value::Object(ObjRef::Shared(r)) => r,
+            _ => panic!(),
         }
     }
 
+    pub fn as_gc(&self) -> Gc<Object> {
+        match self {
+            Value::Object(ObjRef::Heap(r)) => *r,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_string(&self) -> &str {
+        self.as_obj().as_string()
+    }
+
+    pub fn as_function(&self) -> &Function {
+        self.as_obj().as_function()
+    }
+
+    pub fn as_closure(&self) -> &Closure {
+        self.as_obj().as_closure()
+    }
+
     pub fn is_bool(&self) -> bool {
         matches!(self, Value::Boolean(_))
     }
@@ -89,9 +152,17 @@ impl Value {
     }
 
     pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_) | Value::Float(_))
+    }
+
+    pub fn is_int(&self) -> bool {
         matches!(self, Value::Number(_))
     }
 
+    pub fn is_float(&self) -> bool {
+        matches!(self, Value::Float(_))
+    }
+
     pub fn is_obj(&self) -> bool {
         matches!(self, Value::Object(_))
     }
@@ -110,11 +181,84 @@ impl Value {
         }
     }
 
+    pub fn is_function(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::Function(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_closure(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::Closure(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_class(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::Class(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_instance(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::Instance(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_bound_method(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::BoundMethod(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_native(&self) -> bool {
+        match self {
+            Value::Object(v) => matches!(**v, Object::Native(_)),
+            _ => false,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "bool",
+            Value::Number(_) => "int",
+            Value::Float(_) => "float",
+            Value::Nil => "nil",
+            Value::Object(v) => match **v {
+                Object::String(_) => "string",
+                Object::Function(_) | Object::Closure(_) | Object::BoundMethod(_) => "function",
+                Object::Native(_) => "native function",
+                Object::Class(_) => "class",
+                Object::Instance(_) => "instance",
+                Object::Upvalue(_) => "upvalue",
+            },
+        }
+    }
+
+    fn type_error(&self, expected: &'static str) -> ValueTypeError {
+        ValueTypeError {
+            expected,
+            found: self.type_name(),
+        }
+    }
+
     pub fn print_value(&self) {
         print!("{:?}", self)
     }
 
     pub fn values_equal(&self, other: &Self) -> bool {
+        if self.is_number() && other.is_number() {
+            if self.is_int() && other.is_int() {
+                return self.as_number() == other.as_number();
+            }
+            return self.as_float() == other.as_float();
+        }
+
         if core::mem::discriminant(self) != core::mem::discriminant(other) {
             return false;
         }
@@ -122,23 +266,157 @@ impl Value {
         match self {
             Value::Boolean(_) => self.as_bool() == other.as_bool(),
             Value::Nil => true,
-            Value::Number(_) => self.as_number() == other.as_number(),
-            Value::Object(_) => {
-                let obj1 = self.as_obj();
-                let obj2 = other.as_obj();
+            Value::Number(_) | Value::Float(_) => unreachable!(),
+            // Strings are interned, so equal strings are the same object.
+            Value::Object(obj1) => {
+                let Value::Object(obj2) = other else {
+                    unreachable!()
+                };
 
-                let tuple = (&**obj1, &**obj2);
-
-                match tuple {
-                    (Object::String(v1), Object::String(v2)) => v1 == v2,
-                    // _ => ptr::eq(obj1.as_ref(), obj2.as_ref()),
-                }
+                ObjRef::ptr_eq(obj1, obj2)
             }
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueTypeError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl Display for ValueTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expected {} but found {}.", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ValueTypeError {}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Value::new_bool(val)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::new_number(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::new_float(val)
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::new_obj_string(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Value::new_obj(intern::intern(val))
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::new_nil()
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Self {
+        match val {
+            Some(v) => v.into(),
+            None => Value::new_nil(),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(v) => Ok(v),
+            _ => Err(value.type_error("bool")),
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(v) => Ok(v),
+            _ => Err(value.type_error("int")),
+        }
+    }
+}
+
+// Integers are promoted, the same as in arithmetic.
+impl TryFrom<Value> for f64 {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(_) | Value::Float(_) => Ok(value.as_float()),
+            _ => Err(value.type_error("float")),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if value.is_string() {
+            return Ok(value.as_string().to_owned());
+        }
+
+        Err(value.type_error("string"))
+    }
+}
+
+impl TryFrom<Value> for () {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Nil => Ok(()),
+            _ => Err(value.type_error("nil")),
+        }
+    }
+}
+
+// `nil` converts to `None`. A blanket impl over `T: TryFrom<Value>` would
+// overlap with the standard library's reflexive conversion, hence the macro.
+macro_rules! impl_try_from_value_for_option {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Value> for Option<$t> {
+                type Error = ValueTypeError;
+
+                fn try_from(value: Value) -> Result<Self, Self::Error> {
+                    match value {
+                        Value::Nil => Ok(None),
+                        _ => <$t>::try_from(value).map(Some),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_value_for_option!(bool, i64, f64, String);
+
+#[derive(Debug, Default, Clone)]
 pub struct ValueArray {
     pub values: Vec<Value>,
 }
@@ -152,3 +430,48 @@ impl ValueArray {
         self.values.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_rust_values_into_values() {
+        assert!(Value::from(true).values_equal(&Value::Boolean(true)));
+        assert!(Value::from(3_i64).values_equal(&Value::Number(3)));
+        assert!(Value::from(1.5).values_equal(&Value::Float(1.5)));
+        assert_eq!(Value::from("lox").as_string(), "lox");
+        assert_eq!(Value::from("lox".to_string()).as_string(), "lox");
+        assert!(Value::from(()).is_nil());
+        assert!(Value::from(None::<i64>).is_nil());
+        assert!(Value::from(Some(2_i64)).values_equal(&Value::Number(2)));
+    }
+
+    #[test]
+    fn converts_values_into_rust_values() {
+        assert_eq!(bool::try_from(Value::Boolean(true)), Ok(true));
+        assert_eq!(i64::try_from(Value::Number(3)), Ok(3));
+        assert_eq!(f64::try_from(Value::Float(1.5)), Ok(1.5));
+        assert_eq!(f64::try_from(Value::Number(2)), Ok(2.0));
+        assert_eq!(String::try_from(Value::from("lox")), Ok("lox".to_string()));
+        assert_eq!(<()>::try_from(Value::Nil), Ok(()));
+        assert_eq!(Option::<i64>::try_from(Value::Nil), Ok(None));
+        assert_eq!(Option::<i64>::try_from(Value::Number(4)), Ok(Some(4)));
+    }
+
+    #[test]
+    fn conversion_reports_mismatched_types() {
+        assert_eq!(
+            i64::try_from(Value::Float(1.5)),
+            Err(ValueTypeError {
+                expected: "int",
+                found: "float",
+            })
+        );
+        assert_eq!(
+            String::try_from(Value::Nil).unwrap_err().to_string(),
+            "Expected string but found nil."
+        );
+        assert!(Option::<bool>::try_from(Value::Number(1)).is_err());
+    }
+}
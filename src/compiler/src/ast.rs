@@ -0,0 +1,394 @@
+use std::fmt;
+
+use crate::scanner::token::Token;
+
+/// Where a node is in the source: the byte range from its first token to its
+/// last, where the first one starts and the line of the last one.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: i32,
+    pub column: i32,
+    // Instructions are attributed to the last token read, e.g. the `)` of
+    // a call.
+    pub end_line: i32,
+}
+
+impl Span {
+    pub fn of(token: &Token) -> Self {
+        Span {
+            start: token.start,
+            end: token.end,
+            line: token.line,
+            column: token.column,
+            end_line: token.line,
+        }
+    }
+
+    // From the start of `self` to the end of `last`.
+    pub fn to(self, last: Span) -> Self {
+        Span {
+            end: last.end,
+            end_line: last.end_line,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Literal {
+    Number(i64),
+    Float(f64),
+    String(String),
+    True,
+    False,
+    Nil,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BinaryOp {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
+    Literal(Literal),
+    Variable(Token),
+    Assign(Token, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    // The span is the operator's, its jumps are attributed to it.
+    Logical(Box<Expr>, LogicalOp, Span, Box<Expr>),
+    Grouping(Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Get(Box<Expr>, Token),
+    Set(Box<Expr>, Token, Box<Expr>),
+    This(Token),
+    // `super.method`, the token is the method name.
+    Super(Token),
+    // Stands for an expression that failed to parse.
+    Invalid,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StmtKind {
+    Expression(Expr),
+    Print(Expr),
+    Var(Token, Option<Expr>),
+    Fun(Function),
+    Class(Class),
+    Block(Vec<Stmt>),
+    // `paren` closes the condition.
+    If {
+        condition: Expr,
+        paren: Span,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        paren: Span,
+        body: Box<Stmt>,
+    },
+    // `semicolon` ends the condition clause, `paren` closes the clauses.
+    For {
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        semicolon: Span,
+        increment: Option<Expr>,
+        paren: Span,
+        body: Box<Stmt>,
+    },
+    Return(Option<Expr>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    // From the name to the closing brace of the body.
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Class {
+    pub name: Token,
+    pub superclass: Option<Token>,
+    pub methods: Vec<Function>,
+}
+
+/// A `//` comment, without the line break ending it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A whole source file.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    // Every comment in the file in source order, for the formatter and the
+    // linter.
+    pub comments: Vec<Comment>,
+    // The implicit return of the script is on the line of the end of input.
+    pub end_line: i32,
+}
+
+impl Program {
+    // The comments starting in the byte range `start..end`.
+    pub fn comments_between(&self, start: usize, end: usize) -> &[Comment] {
+        let first = self.comments.partition_point(|c| c.span.start < start);
+        let last = self.comments.partition_point(|c| c.span.start < end);
+        &self.comments[first..last]
+    }
+}
+
+// Prints one node per line, children indented under their parent and each
+// node followed by where it starts, e.g. `Literal 1 [1:9]`.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer { f, depth: 0 };
+        for statement in &self.statements {
+            printer.statement(statement)?;
+        }
+        Ok(())
+    }
+}
+
+struct Printer<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    depth: usize,
+}
+
+impl Printer<'_, '_> {
+    fn line(&mut self, text: &str, span: Span) -> fmt::Result {
+        writeln!(
+            self.f,
+            "{}{} [{}:{}]",
+            "  ".repeat(self.depth),
+            text,
+            span.line,
+            span.column
+        )
+    }
+
+    fn nested(&mut self, nodes: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        self.depth += 1;
+        let result = nodes(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> fmt::Result {
+        let span = statement.span;
+        match &statement.kind {
+            StmtKind::Expression(expression) => {
+                self.line("Expression", span)?;
+                self.nested(|p| p.expression(expression))
+            }
+            StmtKind::Print(expression) => {
+                self.line("Print", span)?;
+                self.nested(|p| p.expression(expression))
+            }
+            StmtKind::Var(name, initializer) => {
+                self.line(&format!("Var {}", name.lexeme), span)?;
+                self.nested(|p| initializer.iter().try_for_each(|e| p.expression(e)))
+            }
+            StmtKind::Fun(function) => self.function("Fun", function),
+            StmtKind::Class(class) => {
+                let text = match &class.superclass {
+                    Some(superclass) => {
+                        format!("Class {} < {}", class.name.lexeme, superclass.lexeme)
+                    }
+                    None => format!("Class {}", class.name.lexeme),
+                };
+                self.line(&text, span)?;
+                self.nested(|p| {
+                    class
+                        .methods
+                        .iter()
+                        .try_for_each(|m| p.function("Method", m))
+                })
+            }
+            StmtKind::Block(statements) => {
+                self.line("Block", span)?;
+                self.nested(|p| statements.iter().try_for_each(|s| p.statement(s)))
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.line("If", span)?;
+                self.nested(|p| {
+                    p.expression(condition)?;
+                    p.statement(then_branch)?;
+                    else_branch.iter().try_for_each(|s| p.statement(s))
+                })
+            }
+            StmtKind::While {
+                condition, body, ..
+            } => {
+                self.line("While", span)?;
+                self.nested(|p| {
+                    p.expression(condition)?;
+                    p.statement(body)
+                })
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.line("For", span)?;
+                self.nested(|p| {
+                    initializer.iter().try_for_each(|s| p.statement(s))?;
+                    condition.iter().try_for_each(|e| p.expression(e))?;
+                    increment.iter().try_for_each(|e| p.expression(e))?;
+                    p.statement(body)
+                })
+            }
+            StmtKind::Return(value) => {
+                self.line("Return", span)?;
+                self.nested(|p| value.iter().try_for_each(|e| p.expression(e)))
+            }
+        }
+    }
+
+    fn function(&mut self, kind: &str, function: &Function) -> fmt::Result {
+        let params: Vec<_> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let text = format!("{} {}({})", kind, function.name.lexeme, params.join(", "));
+        self.line(&text, function.span)?;
+        self.nested(|p| function.body.iter().try_for_each(|s| p.statement(s)))
+    }
+
+    fn expression(&mut self, expression: &Expr) -> fmt::Result {
+        let span = expression.span;
+        match &expression.kind {
+            ExprKind::Literal(literal) => {
+                let text = match literal {
+                    Literal::Number(n) => n.to_string(),
+                    Literal::Float(n) => format!("{:?}", n),
+                    Literal::String(s) => format!("\"{}\"", s),
+                    Literal::True => "true".to_string(),
+                    Literal::False => "false".to_string(),
+                    Literal::Nil => "nil".to_string(),
+                };
+                self.line(&format!("Literal {}", text), span)
+            }
+            ExprKind::Variable(name) => self.line(&format!("Variable {}", name.lexeme), span),
+            ExprKind::Assign(name, value) => {
+                self.line(&format!("Assign {}", name.lexeme), span)?;
+                self.nested(|p| p.expression(value))
+            }
+            ExprKind::Unary(operator, operand) => {
+                let text = match operator {
+                    UnaryOp::Not => "!",
+                    UnaryOp::Negate => "-",
+                };
+                self.line(&format!("Unary {}", text), span)?;
+                self.nested(|p| p.expression(operand))
+            }
+            ExprKind::Binary(left, operator, right) => {
+                self.line(&format!("Binary {}", operator), span)?;
+                self.nested(|p| {
+                    p.expression(left)?;
+                    p.expression(right)
+                })
+            }
+            ExprKind::Logical(left, operator, _, right) => {
+                let text = match operator {
+                    LogicalOp::And => "and",
+                    LogicalOp::Or => "or",
+                };
+                self.line(&format!("Logical {}", text), span)?;
+                self.nested(|p| {
+                    p.expression(left)?;
+                    p.expression(right)
+                })
+            }
+            ExprKind::Grouping(inner) => {
+                self.line("Grouping", span)?;
+                self.nested(|p| p.expression(inner))
+            }
+            ExprKind::Call(callee, arguments) => {
+                self.line("Call", span)?;
+                self.nested(|p| {
+                    p.expression(callee)?;
+                    arguments.iter().try_for_each(|e| p.expression(e))
+                })
+            }
+            ExprKind::Get(object, name) => {
+                self.line(&format!("Get {}", name.lexeme), span)?;
+                self.nested(|p| p.expression(object))
+            }
+            ExprKind::Set(object, name, value) => {
+                self.line(&format!("Set {}", name.lexeme), span)?;
+                self.nested(|p| {
+                    p.expression(object)?;
+                    p.expression(value)
+                })
+            }
+            ExprKind::This(_) => self.line("This", span),
+            ExprKind::Super(method) => self.line(&format!("Super {}", method.lexeme), span),
+            ExprKind::Invalid => self.line("Invalid", span),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+        };
+        f.write_str(text)
+    }
+}
@@ -0,0 +1,827 @@
+use common::{
+    chunk::{Chunk, LocalVariable},
+    object,
+    opcode::OpCode,
+    value::Value,
+};
+use std::mem;
+
+use crate::{
+    ast::{
+        BinaryOp, Class, Expr, ExprKind, Function, Literal, LogicalOp, Program, Span, Stmt,
+        StmtKind, UnaryOp,
+    },
+    compiler::{Compiler, FunctionType, Upvalue},
+    error::{CompileError, CompileErrorKind},
+    scanner::token::{Token, TokenType},
+    symbols::{SymbolKind, Symbols},
+};
+
+/// Emits the bytecode of a parsed program, resolving each variable to a
+/// local, an upvalue or a global on the way.
+pub struct CodeGen {
+    pub errors: Vec<CompileError>,
+    // Declarations and references are only collected when set, for editors.
+    pub symbols: Option<Symbols>,
+
+    current_compiler: Compiler,
+    // Set after an error until the end of the declaration, so one mistake
+    // is only reported once.
+    panic_mode: bool,
+    // Line of the instructions emitted next.
+    line: i32,
+}
+
+impl CodeGen {
+    pub fn new() -> Self {
+        CodeGen {
+            errors: Vec::new(),
+            symbols: None,
+
+            current_compiler: Compiler::new(FunctionType::Script),
+            panic_mode: false,
+            line: 0,
+        }
+    }
+
+    pub fn generate(&mut self, program: &Program) -> object::Function {
+        for statement in &program.statements {
+            self.declaration(statement);
+        }
+
+        self.line = program.end_line;
+        self.end_compiler().0
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.current_chunk().write_chunk(byte, line);
+    }
+
+    fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
+        self.emit_byte(byte1);
+        self.emit_byte(byte2);
+    }
+
+    fn emit_loop(&mut self, loop_start: i32) {
+        self.emit_byte(OpCode::OpLoop as u8);
+
+        let offset = self.current_chunk().code.len() as i32 - loop_start + 2;
+
+        self.emit_byte(((offset >> 8) & 0xff) as u8);
+        self.emit_byte((offset & 0xff) as u8);
+    }
+
+    fn emit_jump(&mut self, instruction: u8) -> i32 {
+        self.emit_byte(instruction);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        (self.current_chunk().code.len() - 2) as i32
+    }
+
+    fn emit_operand(&mut self, op: OpCode, op_long: OpCode, operand: i32) {
+        if operand < 256 {
+            self.emit_bytes(op as u8, operand as u8);
+        } else {
+            self.emit_byte(op_long as u8);
+            self.emit_byte((operand & 0xff) as u8);
+            self.emit_byte(((operand >> 8) & 0xff) as u8);
+            self.emit_byte(((operand >> 16) & 0xff) as u8);
+        }
+    }
+
+    fn emit_return(&mut self) {
+        if self.current_compiler.function_type == FunctionType::Initializer {
+            self.emit_bytes(OpCode::OpGetLocal as u8, 0);
+        } else {
+            self.emit_byte(OpCode::OpNil as u8);
+        }
+        self.emit_byte(OpCode::OpReturn as u8);
+    }
+
+    fn make_constant(&mut self, value: Value) -> i32 {
+        self.current_chunk().add_constant(value)
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let line = self.line;
+        self.current_chunk().write_constant(value, line);
+    }
+
+    fn patch_jump(&mut self, offset: i32) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk().code.len() as i32 - offset - 2;
+
+        self.current_chunk().code[offset as usize] = ((jump >> 8) & 0xff) as u8;
+        self.current_chunk().code[(offset + 1) as usize] = (jump & 0xff) as u8;
+    }
+
+    fn init_compiler(&mut self, function_type: FunctionType, name: &Token) {
+        let enclosing = mem::replace(&mut self.current_compiler, Compiler::new(function_type));
+        self.current_compiler.enclosing = Some(Box::new(enclosing));
+        self.current_compiler.function.name = Some(name.lexeme.clone());
+    }
+
+    fn end_compiler(&mut self) -> (object::Function, Vec<Upvalue>) {
+        self.emit_return();
+        // Function bodies don't end their scope, their locals live until the
+        // end of the chunk.
+        for slot in 0..self.current_compiler.locals.len() {
+            self.record_local(slot);
+        }
+
+        let enclosing = match self.current_compiler.enclosing.take() {
+            Some(enclosing) => *enclosing,
+            None => Compiler::new(FunctionType::Script),
+        };
+
+        let compiler = mem::replace(&mut self.current_compiler, enclosing);
+        (compiler.function, compiler.upvalues)
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_compiler.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_compiler.scope_depth -= 1;
+
+        while !self.current_compiler.locals.is_empty()
+            && self
+                .current_compiler
+                .local_at(self.current_compiler.locals.len() - 1)
+                .depth
+                > self.current_compiler.scope_depth
+        {
+            let slot = self.current_compiler.locals.len() - 1;
+            if self.current_compiler.local_at(slot).is_captured {
+                self.emit_byte(OpCode::OpCloseUpvalue as u8);
+            } else {
+                self.emit_byte(OpCode::OpPop as u8);
+            }
+            self.record_local(slot);
+            self.current_compiler.locals.pop();
+        }
+    }
+
+    // Adds the local in `slot` to the chunk's debug info, as going out of
+    // scope after the last emitted instruction.
+    fn record_local(&mut self, slot: usize) {
+        let local = self.current_compiler.local_at(slot);
+        if local.depth == -1 || local.name.lexeme.is_empty() {
+            return;
+        }
+
+        let local = LocalVariable {
+            name: local.name.lexeme.clone(),
+            slot,
+            start: local.start,
+            end: self.current_compiler.function.chunk.code.len(),
+        };
+        self.current_chunk().locals.push(local);
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        &mut self.current_compiler.function.chunk
+    }
+
+    fn declaration(&mut self, statement: &Stmt) {
+        self.statement(statement);
+        self.panic_mode = false;
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        match &statement.kind {
+            StmtKind::Expression(expression) => {
+                self.expression(expression);
+                self.line = span.end_line;
+                self.emit_byte(OpCode::OpPop as u8);
+            }
+            StmtKind::Print(value) => {
+                self.expression(value);
+                self.line = span.end_line;
+                self.emit_byte(OpCode::OpPrint as u8);
+            }
+            StmtKind::Var(name, initializer) => {
+                self.var_declaration(name, initializer.as_ref(), span)
+            }
+            StmtKind::Fun(function) => self.fun_declaration(function),
+            StmtKind::Class(class) => self.class_declaration(class, span),
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.declaration(statement);
+                }
+                self.line = span.end_line;
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                paren,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.line = paren.line;
+                let then_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
+                self.emit_byte(OpCode::OpPop as u8);
+                self.statement(then_branch);
+
+                self.line = then_branch.span.end_line;
+                let else_jump = self.emit_jump(OpCode::OpJump as u8);
+
+                self.patch_jump(then_jump);
+                self.emit_byte(OpCode::OpPop as u8);
+
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            StmtKind::While {
+                condition,
+                paren,
+                body,
+            } => {
+                let loop_start = self.current_chunk().code.len() as i32;
+                self.expression(condition);
+
+                self.line = paren.line;
+                let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
+                self.emit_byte(OpCode::OpPop as u8);
+                self.statement(body);
+                self.line = body.span.end_line;
+                self.emit_loop(loop_start);
+
+                self.patch_jump(exit_jump);
+                self.emit_byte(OpCode::OpPop as u8);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                semicolon,
+                increment,
+                paren,
+                body,
+            } => self.for_statement(
+                initializer.as_deref(),
+                condition.as_ref(),
+                *semicolon,
+                increment.as_ref(),
+                *paren,
+                body,
+            ),
+            StmtKind::Return(None) => {
+                self.line = span.end_line;
+                self.emit_return();
+            }
+            StmtKind::Return(Some(value)) => {
+                self.expression(value);
+                self.line = span.end_line;
+                self.emit_byte(OpCode::OpReturn as u8);
+            }
+        }
+    }
+
+    fn for_statement(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        semicolon: Span,
+        increment: Option<&Expr>,
+        paren: Span,
+        body: &Stmt,
+    ) {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            self.statement(initializer);
+        }
+
+        let mut loop_start = self.current_chunk().code.len() as i32;
+        let mut exit_jump = -1;
+        if let Some(condition) = condition {
+            self.expression(condition);
+
+            // Jump out of the loop if the condition is false.
+            self.line = semicolon.line;
+            exit_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
+            self.emit_byte(OpCode::OpPop as u8); // Condition.
+        }
+
+        if let Some(increment) = increment {
+            self.line = semicolon.line;
+            let body_jump = self.emit_jump(OpCode::OpJump as u8);
+            let increment_start = self.current_chunk().code.len() as i32;
+            self.expression(increment);
+            self.line = increment.span.end_line;
+            self.emit_byte(OpCode::OpPop as u8);
+
+            self.line = paren.line;
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement(body);
+        self.line = body.span.end_line;
+        self.emit_loop(loop_start);
+
+        if exit_jump != -1 {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::OpPop as u8);
+        }
+        self.end_scope();
+    }
+
+    fn var_declaration(&mut self, name: &Token, initializer: Option<&Expr>, span: Span) {
+        let global = self.parse_variable(name);
+        self.record_declaration(name, SymbolKind::Variable);
+
+        match initializer {
+            Some(initializer) => self.expression(initializer),
+            None => {
+                self.line = name.line;
+                self.emit_byte(OpCode::OpNil as u8);
+            }
+        }
+
+        self.line = span.end_line;
+        self.define_variable(global);
+    }
+
+    fn fun_declaration(&mut self, function: &Function) {
+        let global = self.parse_variable(&function.name);
+        self.record_declaration(&function.name, SymbolKind::Function);
+        self.mark_initialized();
+        self.function(function, FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    // Compiles `function` into its own chunk and emits the closure that
+    // creates it, on the line of its closing brace.
+    fn function(&mut self, function: &Function, function_type: FunctionType) {
+        self.enter_symbol(&function.name);
+        self.init_compiler(function_type, &function.name);
+        self.begin_scope();
+
+        for param in &function.params {
+            self.current_compiler.function.arity += 1;
+            let constant = self.parse_variable(param);
+            self.record_declaration(param, SymbolKind::Parameter);
+            self.define_variable(constant);
+        }
+        for statement in &function.body {
+            self.declaration(statement);
+        }
+        self.exit_symbol();
+
+        self.line = function.span.end_line;
+        let (function, upvalues) = self.end_compiler();
+        let constant = self.make_constant(Value::new_obj_function(function));
+        self.emit_operand(OpCode::OpClosure, OpCode::OpClosureLong, constant);
+
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index);
+        }
+    }
+
+    fn method(&mut self, method: &Function) {
+        self.record_declaration(&method.name, SymbolKind::Method);
+        let constant = self.identifier_constant(&method.name);
+
+        let function_type = if method.name.lexeme == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(method, function_type);
+
+        self.emit_operand(OpCode::OpMethod, OpCode::OpMethodLong, constant);
+    }
+
+    fn class_declaration(&mut self, class: &Class, span: Span) {
+        let class_name = &class.name;
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable(class_name);
+        self.record_declaration(class_name, SymbolKind::Class);
+        self.enter_symbol(class_name);
+
+        self.line = class_name.line;
+        self.emit_operand(OpCode::OpClass, OpCode::OpClassLong, name_constant);
+        self.define_variable(name_constant);
+
+        if let Some(superclass) = &class.superclass {
+            self.record_reference(superclass);
+            self.named_variable(superclass, None);
+
+            self.begin_scope();
+            let super_token = self.synthetic_token("super", superclass.line);
+            self.current_compiler.add_local(&super_token);
+            self.define_variable(0);
+
+            self.named_variable(class_name, None);
+            self.line = superclass.line;
+            self.emit_byte(OpCode::OpInherit as u8);
+        }
+
+        self.named_variable(class_name, None);
+        for method in &class.methods {
+            self.method(method);
+        }
+        self.line = span.end_line;
+        self.emit_byte(OpCode::OpPop as u8);
+
+        if class.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.exit_symbol();
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        let span = expression.span;
+        match &expression.kind {
+            ExprKind::Literal(literal) => {
+                self.line = span.line;
+                match literal {
+                    Literal::Number(n) => self.emit_constant(Value::new_number(*n)),
+                    Literal::Float(n) => self.emit_constant(Value::new_float(*n)),
+                    Literal::String(s) => self.emit_constant(Value::new_obj_string(s.clone())),
+                    Literal::True => self.emit_byte(OpCode::OpTrue as u8),
+                    Literal::False => self.emit_byte(OpCode::OpFalse as u8),
+                    Literal::Nil => self.emit_byte(OpCode::OpNil as u8),
+                }
+            }
+            ExprKind::Variable(name) | ExprKind::This(name) => {
+                self.record_reference(name);
+                self.named_variable(name, None);
+            }
+            ExprKind::Assign(name, value) => {
+                self.record_reference(name);
+                self.named_variable(name, Some(value));
+            }
+            ExprKind::Unary(operator, operand) => {
+                self.expression(operand);
+                self.line = span.end_line;
+                match operator {
+                    UnaryOp::Not => self.emit_byte(OpCode::OpNot as u8),
+                    UnaryOp::Negate => self.emit_byte(OpCode::OpNegate as u8),
+                }
+            }
+            ExprKind::Binary(left, operator, right) => {
+                self.expression(left);
+                self.expression(right);
+                self.line = span.end_line;
+                self.binary(*operator);
+            }
+            ExprKind::Logical(left, LogicalOp::And, operator, right) => {
+                self.expression(left);
+                self.line = operator.line;
+                let end_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
+
+                self.emit_byte(OpCode::OpPop as u8);
+                self.expression(right);
+
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Logical(left, LogicalOp::Or, operator, right) => {
+                self.expression(left);
+                self.line = operator.line;
+                let else_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
+                let end_jump = self.emit_jump(OpCode::OpJump as u8);
+
+                self.patch_jump(else_jump);
+                self.emit_byte(OpCode::OpPop as u8);
+
+                self.expression(right);
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Call(callee, arguments) => self.call(callee, arguments, span),
+            ExprKind::Get(object, name) => {
+                self.expression(object);
+                let name_constant = self.identifier_constant(name);
+                self.line = name.line;
+                self.emit_operand(
+                    OpCode::OpGetProperty,
+                    OpCode::OpGetPropertyLong,
+                    name_constant,
+                );
+            }
+            ExprKind::Set(object, name, value) => {
+                self.expression(object);
+                let name_constant = self.identifier_constant(name);
+                self.expression(value);
+                self.line = span.end_line;
+                self.emit_operand(
+                    OpCode::OpSetProperty,
+                    OpCode::OpSetPropertyLong,
+                    name_constant,
+                );
+            }
+            ExprKind::Super(method) => self.super_(method, None, span),
+            ExprKind::Invalid => (),
+        }
+    }
+
+    fn binary(&mut self, operator: BinaryOp) {
+        match operator {
+            BinaryOp::NotEqual => self.emit_bytes(OpCode::OpEqual as u8, OpCode::OpNot as u8),
+            BinaryOp::Equal => self.emit_byte(OpCode::OpEqual as u8),
+            BinaryOp::Greater => self.emit_byte(OpCode::OpGreater as u8),
+            BinaryOp::GreaterEqual => self.emit_bytes(OpCode::OpLess as u8, OpCode::OpNot as u8),
+            BinaryOp::Less => self.emit_byte(OpCode::OpLess as u8),
+            BinaryOp::LessEqual => self.emit_bytes(OpCode::OpGreater as u8, OpCode::OpNot as u8),
+            BinaryOp::Add => self.emit_byte(OpCode::OpAdd as u8),
+            BinaryOp::Subtract => self.emit_byte(OpCode::OpSubtract as u8),
+            BinaryOp::Multiply => self.emit_byte(OpCode::OpMultiply as u8),
+            BinaryOp::Divide => self.emit_byte(OpCode::OpDivide as u8),
+        }
+    }
+
+    // Calls of a property or of a superclass method are emitted as a single
+    // invoke instruction.
+    fn call(&mut self, callee: &Expr, arguments: &[Expr], span: Span) {
+        match &callee.kind {
+            ExprKind::Get(object, name) => {
+                self.expression(object);
+                let name_constant = self.identifier_constant(name);
+                self.arguments(arguments);
+                self.line = span.end_line;
+                self.emit_operand(OpCode::OpInvoke, OpCode::OpInvokeLong, name_constant);
+                self.emit_byte(arguments.len() as u8);
+            }
+            ExprKind::Super(method) => self.super_(method, Some(arguments), span),
+            _ => {
+                self.expression(callee);
+                self.arguments(arguments);
+                self.line = span.end_line;
+                self.emit_bytes(OpCode::OpCall as u8, arguments.len() as u8);
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) {
+        for argument in arguments {
+            self.expression(argument);
+        }
+    }
+
+    fn super_(&mut self, method: &Token, arguments: Option<&[Expr]>, span: Span) {
+        let name = self.identifier_constant(method);
+
+        self.named_variable(&self.synthetic_token("this", method.line), None);
+        match arguments {
+            Some(arguments) => {
+                self.arguments(arguments);
+                self.named_variable(&self.synthetic_token("super", span.end_line), None);
+                self.line = span.end_line;
+                self.emit_operand(OpCode::OpSuperInvoke, OpCode::OpSuperInvokeLong, name);
+                self.emit_byte(arguments.len() as u8);
+            }
+            None => {
+                self.named_variable(&self.synthetic_token("super", method.line), None);
+                self.line = method.line;
+                self.emit_operand(OpCode::OpGetSuper, OpCode::OpGetSuperLong, name);
+            }
+        }
+    }
+
+    // Reads `name`, or assigns `value` to it.
+    fn named_variable(&mut self, name: &Token, value: Option<&Expr>) {
+        let get_op: OpCode;
+        let set_op: OpCode;
+        let get_op_long: OpCode;
+        let set_op_long: OpCode;
+        let mut arg = self.resolve_local(0, name);
+
+        if arg != -1 {
+            get_op = OpCode::OpGetLocal;
+            set_op = OpCode::OpSetLocal;
+            get_op_long = OpCode::OpGetLocalLong;
+            set_op_long = OpCode::OpSetLocalLong;
+        } else {
+            arg = self.resolve_upvalue(0, name);
+            if arg != -1 {
+                // Upvalue indices always fit in a single byte.
+                get_op = OpCode::OpGetUpvalue;
+                set_op = OpCode::OpSetUpvalue;
+                get_op_long = OpCode::OpGetUpvalue;
+                set_op_long = OpCode::OpSetUpvalue;
+            } else {
+                arg = self.identifier_constant(name);
+                get_op = OpCode::OpGetGlobal;
+                set_op = OpCode::OpSetGlobal;
+                get_op_long = OpCode::OpGetGlobalLong;
+                set_op_long = OpCode::OpSetGlobalLong;
+            }
+        }
+
+        match value {
+            Some(value) => {
+                self.expression(value);
+                self.line = value.span.end_line;
+                self.emit_operand(set_op, set_op_long, arg);
+            }
+            None => {
+                self.line = name.line;
+                self.emit_operand(get_op, get_op_long, arg);
+            }
+        }
+    }
+
+    fn define_variable(&mut self, global: i32) {
+        if self.current_compiler.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_operand(OpCode::OpDefineGlobal, OpCode::OpDefineGlobalLong, global);
+    }
+
+    fn parse_variable(&mut self, name: &Token) -> i32 {
+        self.declare_variable(name);
+        if self.current_compiler.scope_depth > 0 {
+            return 0;
+        }
+
+        self.identifier_constant(name)
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.current_compiler.scope_depth == 0 {
+            return;
+        }
+        let index = self.current_compiler.locals.len() - 1;
+        self.current_compiler
+            .update_local_depth_at(index, self.current_compiler.scope_depth);
+        self.current_compiler.locals[index].start = self.current_chunk().code.len();
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> i32 {
+        self.current_chunk()
+            .add_constant(Value::new_obj_string(name.lexeme.to_owned()))
+    }
+
+    fn resolve_local(&mut self, depth: usize, name: &Token) -> i32 {
+        let compiler = self.current_compiler.enclosing_at(depth).unwrap();
+        let mut i = compiler.locals.len() as i32 - 1;
+        while i >= 0 {
+            let local = compiler.local_at(i as usize);
+            if name.lexeme == local.name.lexeme {
+                if local.depth == -1 {
+                    self.error_at(
+                        name,
+                        "Can't read local variable in its own initializer.".to_string(),
+                    );
+                }
+                return i;
+            }
+
+            i -= 1;
+        }
+
+        -1
+    }
+
+    fn resolve_upvalue(&mut self, depth: usize, name: &Token) -> i32 {
+        if self.current_compiler.enclosing_at(depth + 1).is_none() {
+            return -1;
+        }
+
+        let local = self.resolve_local(depth + 1, name);
+        if local != -1 {
+            self.current_compiler
+                .enclosing_at(depth + 1)
+                .unwrap()
+                .locals[local as usize]
+                .is_captured = true;
+            return self.add_upvalue(depth, local, true, name);
+        }
+
+        let upvalue = self.resolve_upvalue(depth + 1, name);
+        if upvalue != -1 {
+            return self.add_upvalue(depth, upvalue, false, name);
+        }
+
+        -1
+    }
+
+    fn add_upvalue(&mut self, depth: usize, index: i32, is_local: bool, name: &Token) -> i32 {
+        if index > u8::MAX as i32 {
+            self.error_at(name, "Too many closure variables in function.".to_string());
+            return 0;
+        }
+
+        let compiler = self.current_compiler.enclosing_at(depth).unwrap();
+        let upvalue = compiler.add_upvalue(index as u8, is_local, &name.lexeme);
+        if upvalue > u8::MAX as i32 {
+            self.error_at(name, "Too many closure variables in function.".to_string());
+            return 0;
+        }
+
+        upvalue
+    }
+
+    fn declare_variable(&mut self, name: &Token) {
+        if self.current_compiler.scope_depth == 0 {
+            return;
+        }
+
+        let mut error_flagged: bool = false;
+        let mut i = self.current_compiler.locals.len() as i32 - 1;
+        while i >= 0 {
+            let local = self.current_compiler.local_at(i as usize);
+            if local.depth != -1 && local.depth < self.current_compiler.scope_depth {
+                break;
+            }
+
+            i -= 1;
+            if name.lexeme == local.name.lexeme {
+                error_flagged = true;
+                break;
+            }
+        }
+        if !error_flagged {
+            self.current_compiler.add_local(name);
+        } else {
+            self.error_at(
+                name,
+                "Already a variable with this name in this scope.".to_string(),
+            );
+        }
+    }
+
+    // Records `name` as declared, if symbols are collected.
+    fn record_declaration(&mut self, name: &Token, kind: SymbolKind) {
+        if name.token_type != TokenType::TokenIdentifier {
+            return;
+        }
+
+        let global = self.current_compiler.scope_depth == 0 && kind != SymbolKind::Method;
+        if let Some(symbols) = &mut self.symbols {
+            symbols.declare(name, kind, global);
+        }
+    }
+
+    // Records a use of `name`, resolved like `named_variable` does but
+    // without capturing anything.
+    fn record_reference(&mut self, name: &Token) {
+        let symbols = match &mut self.symbols {
+            Some(symbols) => symbols,
+            None => return,
+        };
+
+        let mut compiler = Some(&self.current_compiler);
+        while let Some(current) = compiler {
+            let local = current
+                .locals
+                .iter()
+                .rev()
+                .find(|l| l.name.lexeme == name.lexeme);
+            if let Some(local) = local {
+                symbols.reference(name, Some(&local.name));
+                return;
+            }
+            compiler = current.enclosing.as_deref();
+        }
+        symbols.reference(name, None);
+    }
+
+    fn enter_symbol(&mut self, name: &Token) {
+        if let Some(symbols) = &mut self.symbols {
+            symbols.enter(name);
+        }
+    }
+
+    fn exit_symbol(&mut self) {
+        if let Some(symbols) = &mut self.symbols {
+            symbols.exit();
+        }
+    }
+
+    fn synthetic_token(&self, text: &str, line: i32) -> Token {
+        Token {
+            token_type: TokenType::TokenIdentifier,
+            lexeme: text.to_string(),
+            line,
+            ..Default::default()
+        }
+    }
+
+    fn error_at(&mut self, token: &Token, message: String) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+
+        self.errors.push(CompileError {
+            kind: CompileErrorKind::Syntax,
+            line: token.line,
+            column: token.column,
+            span: token.start..token.end,
+            lexeme: token.lexeme.clone(),
+            message,
+        });
+    }
+}
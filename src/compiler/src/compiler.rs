@@ -1,27 +1,77 @@
-use crate::scanner::token::Token;
+use common::object::Function;
+
+use crate::scanner::token::{Token, TokenType};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
 
 pub struct Local {
     pub name: Token,
     pub depth: i32,
+    pub is_captured: bool,
+    // Offset of the first instruction that can see the local.
+    pub start: usize,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Upvalue {
+    pub index: u8,
+    pub is_local: bool,
 }
 
 pub struct Compiler {
+    pub enclosing: Option<Box<Compiler>>,
+    pub function: Function,
+    pub function_type: FunctionType,
+
     pub locals: Vec<Local>,
+    pub upvalues: Vec<Upvalue>,
     pub scope_depth: i32,
 }
 
 impl Compiler {
-    pub fn new() -> Self {
-        Compiler {
+    pub fn new(function_type: FunctionType) -> Self {
+        let mut compiler = Compiler {
+            enclosing: None,
+            function: Function::new(),
+            function_type,
             scope_depth: 0,
             locals: Vec::new(),
-        }
+            upvalues: Vec::new(),
+        };
+
+        // Slot zero is reserved for the function being called, or for the
+        // receiver when compiling a method.
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+        compiler.locals.push(Local {
+            name: Token {
+                token_type: TokenType::Unknown,
+                lexeme: slot_zero.to_string(),
+                line: -1,
+                ..Default::default()
+            },
+            depth: 0,
+            is_captured: false,
+            start: 0,
+        });
+
+        compiler
     }
 
     pub fn add_local(&mut self, name: &Token) {
         let local = Local {
             name: name.clone(),
             depth: -1,
+            is_captured: false,
+            start: 0,
         };
         self.locals.push(local);
     }
@@ -33,4 +83,24 @@ impl Compiler {
     pub fn update_local_depth_at(&mut self, index: usize, depth: i32) {
         self.locals[index].depth = depth;
     }
+
+    pub fn enclosing_at(&mut self, depth: usize) -> Option<&mut Compiler> {
+        let mut compiler = self;
+        for _ in 0..depth {
+            compiler = compiler.enclosing.as_deref_mut()?;
+        }
+        Some(compiler)
+    }
+
+    pub fn add_upvalue(&mut self, index: u8, is_local: bool, name: &str) -> i32 {
+        let upvalue = Upvalue { index, is_local };
+        if let Some(i) = self.upvalues.iter().position(|u| *u == upvalue) {
+            return i as i32;
+        }
+
+        self.upvalues.push(upvalue);
+        self.function.chunk.upvalues.push(name.to_string());
+        self.function.upvalue_count = self.upvalues.len();
+        (self.upvalues.len() - 1) as i32
+    }
 }
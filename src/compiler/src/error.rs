@@ -0,0 +1,38 @@
+use std::{fmt, ops::Range};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CompileErrorKind {
+    // Reported by the scanner, e.g. an unterminated string.
+    Lexical,
+    // Reported by the parser against a well-formed token.
+    Syntax,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub line: i32,
+    // 1-based, counted in characters like `Token::column`.
+    pub column: i32,
+    // Byte range of the offending token in the source.
+    pub span: Range<usize>,
+    // Offending source text, empty when the error is at the end of input.
+    pub lexeme: String,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error", self.line)?;
+
+        match self.kind {
+            CompileErrorKind::Lexical => (),
+            CompileErrorKind::Syntax if self.lexeme.is_empty() => write!(f, " at end")?,
+            CompileErrorKind::Syntax => write!(f, " at '{}'", self.lexeme)?,
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
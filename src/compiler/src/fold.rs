@@ -0,0 +1,370 @@
+use std::mem;
+
+use crate::ast::{
+    BinaryOp, Expr, ExprKind, Function, Literal, LogicalOp, Program, Span, Stmt, StmtKind, UnaryOp,
+};
+
+/// Evaluates what doesn't depend on the program running: operators applied
+/// to literals, and branches and loops on literal conditions. Whatever would
+/// fail at runtime, such as dividing an integer by zero, is left for the VM
+/// to report.
+pub fn fold(program: &mut Program) {
+    program.statements.iter_mut().for_each(statement);
+}
+
+fn statement(statement: &mut Stmt) {
+    let folded = match &mut statement.kind {
+        StmtKind::Expression(value) | StmtKind::Print(value) => {
+            expression(value);
+            None
+        }
+        StmtKind::Var(_, value) | StmtKind::Return(value) => {
+            value.iter_mut().for_each(expression);
+            None
+        }
+        StmtKind::Fun(function) => {
+            self::function(function);
+            None
+        }
+        StmtKind::Class(class) => {
+            class.methods.iter_mut().for_each(self::function);
+            None
+        }
+        StmtKind::Block(statements) => {
+            statements.iter_mut().for_each(self::statement);
+            None
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            expression(condition);
+            self::statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                self::statement(else_branch);
+            }
+
+            match literal(condition).map(truthy) {
+                Some(true) => Some(take_statement(then_branch)),
+                Some(false) => Some(match else_branch {
+                    Some(else_branch) => take_statement(else_branch),
+                    None => nothing(statement.span),
+                }),
+                None => None,
+            }
+        }
+        StmtKind::While {
+            condition,
+            paren,
+            body,
+        } => {
+            expression(condition);
+            self::statement(body);
+
+            match literal(condition).map(truthy) {
+                // A `for` without clauses loops without checking anything.
+                Some(true) => Some(Stmt {
+                    kind: StmtKind::For {
+                        initializer: None,
+                        condition: None,
+                        semicolon: *paren,
+                        increment: None,
+                        paren: *paren,
+                        body: Box::new(take_statement(body)),
+                    },
+                    span: statement.span,
+                }),
+                Some(false) => Some(nothing(statement.span)),
+                None => None,
+            }
+        }
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                self::statement(initializer);
+            }
+            condition.iter_mut().for_each(expression);
+            increment.iter_mut().for_each(expression);
+            self::statement(body);
+
+            match condition.as_ref().and_then(literal).map(truthy) {
+                Some(true) => {
+                    *condition = None;
+                    None
+                }
+                // Only the initializer runs, still in a scope of its own.
+                Some(false) => Some(Stmt {
+                    kind: StmtKind::Block(initializer.take().map(|s| *s).into_iter().collect()),
+                    span: statement.span,
+                }),
+                None => None,
+            }
+        }
+    };
+
+    if let Some(folded) = folded {
+        *statement = folded;
+    }
+}
+
+fn function(function: &mut Function) {
+    function.body.iter_mut().for_each(statement);
+}
+
+fn expression(expression: &mut Expr) {
+    let span = expression.span;
+    let value = match &mut expression.kind {
+        ExprKind::Unary(operator, operand) => {
+            self::expression(operand);
+            literal(operand).and_then(|operand| unary(*operator, operand))
+        }
+        ExprKind::Binary(left, operator, right) => {
+            self::expression(left);
+            self::expression(right);
+            match (literal(left), literal(right)) {
+                (Some(left), Some(right)) => binary(left, *operator, right),
+                _ => None,
+            }
+        }
+        ExprKind::Logical(left, operator, _, right) => {
+            self::expression(left);
+            self::expression(right);
+            // The left operand is the result when it decides the outcome,
+            // the right one isn't evaluated then.
+            if let Some(value) = literal(left) {
+                let short_circuits = truthy(value) == (*operator == LogicalOp::Or);
+                *expression = if short_circuits {
+                    take_expression(left)
+                } else {
+                    take_expression(right)
+                };
+            }
+            return;
+        }
+        ExprKind::Grouping(inner) => {
+            self::expression(inner);
+            literal(inner).cloned()
+        }
+        ExprKind::Assign(_, value) => {
+            self::expression(value);
+            None
+        }
+        ExprKind::Call(callee, arguments) => {
+            self::expression(callee);
+            arguments.iter_mut().for_each(self::expression);
+            None
+        }
+        ExprKind::Get(object, _) => {
+            self::expression(object);
+            None
+        }
+        ExprKind::Set(object, _, value) => {
+            self::expression(object);
+            self::expression(value);
+            None
+        }
+        ExprKind::Literal(_)
+        | ExprKind::Variable(_)
+        | ExprKind::This(_)
+        | ExprKind::Super(_)
+        | ExprKind::Invalid => None,
+    };
+
+    if let Some(value) = value {
+        *expression = Expr {
+            kind: ExprKind::Literal(value),
+            span,
+        };
+    }
+}
+
+fn literal(expression: &Expr) -> Option<&Literal> {
+    match &expression.kind {
+        ExprKind::Literal(literal) => Some(literal),
+        _ => None,
+    }
+}
+
+// Like `Value::is_falsey`, only `true` is truthy.
+fn truthy(literal: &Literal) -> bool {
+    *literal == Literal::True
+}
+
+fn boolean(value: bool) -> Literal {
+    if value {
+        Literal::True
+    } else {
+        Literal::False
+    }
+}
+
+fn float(literal: &Literal) -> Option<f64> {
+    match literal {
+        Literal::Number(n) => Some(*n as f64),
+        Literal::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn unary(operator: UnaryOp, operand: &Literal) -> Option<Literal> {
+    match (operator, operand) {
+        (UnaryOp::Not, operand) => Some(boolean(!truthy(operand))),
+        (UnaryOp::Negate, Literal::Number(n)) => n.checked_neg().map(Literal::Number),
+        (UnaryOp::Negate, Literal::Float(n)) => Some(Literal::Float(-n)),
+        (UnaryOp::Negate, _) => None,
+    }
+}
+
+// Evaluates the operator the way the VM does, e.g. `a >= b` as `!(a < b)`.
+fn binary(left: &Literal, operator: BinaryOp, right: &Literal) -> Option<Literal> {
+    match operator {
+        BinaryOp::Equal => Some(boolean(equal(left, right))),
+        BinaryOp::NotEqual => Some(boolean(!equal(left, right))),
+        BinaryOp::Greater => compare(left, right, i64::gt, f64::gt).map(boolean),
+        BinaryOp::GreaterEqual => compare(left, right, i64::lt, f64::lt).map(|b| boolean(!b)),
+        BinaryOp::Less => compare(left, right, i64::lt, f64::lt).map(boolean),
+        BinaryOp::LessEqual => compare(left, right, i64::gt, f64::gt).map(|b| boolean(!b)),
+        BinaryOp::Add => match (left, right) {
+            (Literal::String(a), Literal::String(b)) => {
+                Some(Literal::String(format!("{}{}", a, b)))
+            }
+            _ => arithmetic(left, right, i64::checked_add, |a, b| a + b),
+        },
+        BinaryOp::Subtract => arithmetic(left, right, i64::checked_sub, |a, b| a - b),
+        BinaryOp::Multiply => arithmetic(left, right, i64::checked_mul, |a, b| a * b),
+        // Also leaves integer division by zero to the VM.
+        BinaryOp::Divide => arithmetic(left, right, i64::checked_div, |a, b| a / b),
+    }
+}
+
+// Integers stay integers unless they overflow, which is left to the VM.
+// Otherwise both operands are promoted to floats.
+fn arithmetic(
+    left: &Literal,
+    right: &Literal,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Option<Literal> {
+    match (left, right) {
+        (Literal::Number(a), Literal::Number(b)) => int_op(*a, *b).map(Literal::Number),
+        _ => Some(Literal::Float(float_op(float(left)?, float(right)?))),
+    }
+}
+
+fn compare(
+    left: &Literal,
+    right: &Literal,
+    int_op: fn(&i64, &i64) -> bool,
+    float_op: fn(&f64, &f64) -> bool,
+) -> Option<bool> {
+    match (left, right) {
+        (Literal::Number(a), Literal::Number(b)) => Some(int_op(a, b)),
+        _ => Some(float_op(&float(left)?, &float(right)?)),
+    }
+}
+
+// Like `Value::values_equal`, numbers compare as floats unless both are
+// integers.
+fn equal(left: &Literal, right: &Literal) -> bool {
+    match (left, right) {
+        (Literal::Number(a), Literal::Number(b)) => a == b,
+        _ => match (float(left), float(right)) {
+            (Some(a), Some(b)) => a == b,
+            _ => left == right,
+        },
+    }
+}
+
+// A statement that emits nothing.
+fn nothing(span: Span) -> Stmt {
+    Stmt {
+        kind: StmtKind::Block(Vec::new()),
+        span,
+    }
+}
+
+fn take_statement(statement: &mut Box<Stmt>) -> Stmt {
+    mem::replace(statement, nothing(statement.span))
+}
+
+fn take_expression(expression: &mut Box<Expr>) -> Expr {
+    let span = expression.span;
+    mem::replace(
+        expression,
+        Expr {
+            kind: ExprKind::Invalid,
+            span,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use common::object::Function;
+    use rstest::rstest;
+
+    use crate::CompileOptions;
+
+    // The code and constants of `function` and of the functions it declares.
+    fn code(function: &Function) -> Vec<(Vec<u8>, Vec<String>)> {
+        let constants = &function.chunk.constants.values;
+        let mut code = vec![(
+            function.chunk.code.clone(),
+            constants.iter().map(|c| format!("{:?}", c)).collect(),
+        )];
+        for constant in constants.iter().filter(|c| c.is_function()) {
+            code.extend(self::code(constant.as_function()));
+        }
+        code
+    }
+
+    fn compile_unfolded(source: &str) -> Function {
+        let options = CompileOptions {
+            fold_constants: false,
+        };
+        crate::compile_with_options(source, options, &mut io::sink()).unwrap()
+    }
+
+    #[rstest]
+    #[case("print 1 + 2 * 3;", "print 7;")]
+    #[case("print -(2.5 * 2) < 1 == !false;", "print true;")]
+    #[case("print \"a\" + \"b\" + \"c\";", "print \"abc\";")]
+    #[case("print 7 / 2 >= 3.5;", "print false;")]
+    #[case("print 1 == 1.0 and nil != false;", "print true;")]
+    #[case("print nil or a;", "print a;")]
+    #[case("print a + 1 * 2;", "print a + 2;")]
+    #[case("if (true) print 1; else print 2;", "print 1;")]
+    #[case("if (1 > 2) print 1;", "")]
+    #[case("while (false) print 1;", "")]
+    #[case("while (true) print 1;", "for (;;) print 1;")]
+    #[case("for (var i = 0; false;) print i;", "{ var i = 0; }")]
+    #[case("fun f() { if (!nil) return 1; }", "fun f() { return 1; }")]
+    fn folds_constants(#[case] source: &str, #[case] folded: &str) {
+        let function = crate::compile(source).unwrap();
+
+        assert_eq!(code(&function), code(&compile_unfolded(folded)));
+        assert_ne!(code(&function), code(&compile_unfolded(source)));
+    }
+
+    // These fail or overflow at runtime, the VM still reports them.
+    #[rstest]
+    #[case("print 1 / 0;")]
+    #[case("print -\"a\";")]
+    #[case("print 1 + \"a\";")]
+    #[case("print 9223372036854775807 + 1;")]
+    #[case("print nil < 1;")]
+    fn leaves_runtime_errors_unfolded(#[case] source: &str) {
+        let function = crate::compile(source).unwrap();
+
+        assert_eq!(code(&function), code(&compile_unfolded(source)));
+    }
+}
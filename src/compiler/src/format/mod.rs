@@ -0,0 +1,94 @@
+use crate::error::CompileError;
+
+mod printer;
+
+/// Formats `source` in the canonical layout, keeping its comments. Fails on
+/// the first syntax error since there is no tree to print.
+pub fn format(source: &str) -> Result<String, CompileError> {
+    let program = crate::parse(source).map_err(|errors| errors[0].clone())?;
+    Ok(printer::print(&program, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    #[case("var  a=1 ;", "var a = 1;\n")]
+    #[case("print(1+2)*3;print -a;", "print (1 + 2) * 3;\nprint -a;\n")]
+    #[case(
+        "fun f(a,b){return a.b(c,d).e=!b;}",
+        "fun f(a, b) {\n    return a.b(c, d).e = !b;\n}\n"
+    )]
+    #[case(
+        "class A<B{init(){super.init();}m(){}}",
+        "class A < B {\n    init() {\n        super.init();\n    }\n    m() {}\n}\n"
+    )]
+    #[case(
+        "if(a)print 1;else if(b){print 2;}else{}",
+        "if (a) print 1; else if (b) {\n    print 2;\n} else {}\n"
+    )]
+    #[case(
+        "for(;;)x=x-1;for(var i=0;i<1;){}",
+        "for (;;) x = x - 1;\nfor (var i = 0; i < 1;) {}\n"
+    )]
+    #[case(
+        "while(a and b or c){a=nil;}",
+        "while (a and b or c) {\n    a = nil;\n}\n"
+    )]
+    #[case(
+        "\n\n{\n\n\nvar a;\n\n\n\nvar b;\n\n}\n\n",
+        "{\n    var a;\n\n    var b;\n}\n"
+    )]
+    fn formats_statements(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "// header\n\nvar a = 1; // one\nfun f() { // opens\n  return a + // inside\n    1;\n\n  // closing\n}\n// end",
+        "// header\n\nvar a = 1; // one\nfun f() {\n    // opens\n    return a + // inside\n        1;\n\n    // closing\n}\n// end\n"
+    )]
+    #[case(
+        "if (a) // then\n print 1;\nelse // else c\n print 2;",
+        "if (a) // then\n    print 1;\nelse // else c\n    print 2;\n"
+    )]
+    #[case("fun n(a, // p\n b) {}", "fun n(a, // p\n    b) {}\n")]
+    #[case(
+        "for (var i = 0; // start\n i < 3;) // body\n{ print i; }",
+        "for (var i = 0; // start\n    i < 3;) // body\n{\n    print i;\n}\n"
+    )]
+    #[case("f(a, // first\n b);", "f(a, // first\n    b);\n")]
+    fn keeps_comments(#[case] source: &str, #[case] expected: &str) {
+        let formatted = format(source).unwrap();
+
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[rstest]
+    #[case("var a = ;", 1, "Expect expression.")]
+    #[case("fun f() {\n  print 1;", 2, "Expect '}' after block.")]
+    #[case("a + b = c;", 1, "Invalid assignment target.")]
+    #[case("print \"open", 1, "Unterminated string.")]
+    #[case("while (a print 1;", 1, "Expect ')' after 'while'.")]
+    fn reports_the_first_error(#[case] source: &str, #[case] line: i32, #[case] message: &str) {
+        let error = format(source).unwrap_err();
+
+        assert_eq!((error.line, error.message.as_str()), (line, message));
+    }
+
+    #[test]
+    fn formatting_the_samples_is_idempotent() {
+        let samples = format!("{}/../runner/samples", env!("CARGO_MANIFEST_DIR"));
+        for entry in fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            let source = fs::read_to_string(&path).unwrap();
+
+            let formatted = format(&source).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted, "{}", path.display());
+        }
+    }
+}
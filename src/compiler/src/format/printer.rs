@@ -0,0 +1,408 @@
+use crate::ast::{
+    Comment, Expr, ExprKind, Function, LogicalOp, Program, Span, Stmt, StmtKind, UnaryOp,
+};
+
+const INDENT: &str = "    ";
+
+/// Prints a program in the canonical layout: four space indents, opening
+/// braces on the line of their statement and at most one empty line in a
+/// row. Comments stay where they are, before the token that follows them.
+pub fn print(program: &Program, source: &str) -> String {
+    let mut printer = Printer {
+        source,
+        comments: &program.comments,
+        output: String::new(),
+        depth: 0,
+        continued: false,
+        line_start: true,
+    };
+    printer.items(
+        &program.statements,
+        source.len(),
+        stmt_span,
+        Printer::statement,
+    );
+    printer.output
+}
+
+fn stmt_span(statement: &Stmt) -> Span {
+    statement.span
+}
+
+fn function_span(function: &Function) -> Span {
+    function.span
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    // The comments not printed yet.
+    comments: &'a [Comment],
+    output: String,
+    depth: usize,
+    // A comment ended the line in the middle of a statement, the rest of it
+    // is indented once more.
+    continued: bool,
+    // Nothing but the indent on the current line.
+    line_start: bool,
+}
+
+impl<'a> Printer<'a> {
+    // Statements or methods, one per line, then the comments up to `end`
+    // where their block closes. Comments before an item go on their own
+    // lines, one after it on its last line stays there.
+    fn items<T>(
+        &mut self,
+        items: &[T],
+        end: usize,
+        span: fn(&T) -> Span,
+        print: fn(&mut Self, &T),
+    ) {
+        let mut previous_end = None;
+        for (index, item) in items.iter().enumerate() {
+            let item_span = span(item);
+            self.own_line_comments(item_span.start, &mut previous_end);
+
+            self.start_line(self.blank_between(previous_end, item_span.start));
+            print(self, item);
+            previous_end = Some(item_span.end);
+
+            let next = items.get(index + 1).map_or(end, |next| span(next).start);
+            if let Some(comment) = self.comments.first().filter(|comment| {
+                comment.span.start < next && comment.span.line == item_span.end_line
+            }) {
+                self.comments = &self.comments[1..];
+                self.push(" ");
+                self.push(&comment.text);
+                previous_end = Some(comment.span.end);
+            }
+            self.end_line();
+        }
+        self.own_line_comments(end, &mut previous_end);
+    }
+
+    fn own_line_comments(&mut self, end: usize, previous_end: &mut Option<usize>) {
+        while let Some(comment) = self.comments.first().filter(|c| c.span.start < end) {
+            self.comments = &self.comments[1..];
+            self.start_line(self.blank_between(*previous_end, comment.span.start));
+            self.push(&comment.text);
+            self.end_line();
+            *previous_end = Some(comment.span.end);
+        }
+    }
+
+    // Whether an empty line separates the previous item from `start`.
+    fn blank_between(&self, previous_end: Option<usize>, start: usize) -> bool {
+        previous_end.is_some_and(|end| self.source[end..start].matches('\n').count() > 1)
+    }
+
+    fn start_line(&mut self, blank: bool) {
+        if blank {
+            self.output.push('\n');
+        }
+        self.indent();
+    }
+
+    fn end_line(&mut self) {
+        self.trim_spaces();
+        self.output.push('\n');
+        self.continued = false;
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth + self.continued as usize {
+            self.output.push_str(INDENT);
+        }
+        self.line_start = true;
+    }
+
+    // Prints `text`, which starts at byte `start` of the source, after the
+    // comments before it.
+    fn token(&mut self, text: &str, start: usize) {
+        self.comments_before(start);
+        self.push(text);
+    }
+
+    // Comments inside a statement, each ending its line.
+    fn comments_before(&mut self, start: usize) {
+        while let Some(comment) = self.comments.first().filter(|c| c.span.start < start) {
+            self.comments = &self.comments[1..];
+            if !self.line_start {
+                self.trim_spaces();
+                self.push(" ");
+            }
+            self.push(&comment.text);
+            self.end_line();
+            self.continued = true;
+            self.indent();
+        }
+    }
+
+    // Starts a new line at the depth of the statement, rather than one indent
+    // deeper, if a comment just ended the last one.
+    fn outdent(&mut self) {
+        if self.continued && self.line_start {
+            self.trim_spaces();
+            self.continued = false;
+            self.indent();
+        }
+    }
+
+    fn trim_spaces(&mut self) {
+        self.output
+            .truncate(self.output.trim_end_matches(' ').len());
+    }
+
+    // Spaces are dropped at the start of a line.
+    fn push(&mut self, text: &str) {
+        let text = if self.line_start {
+            text.trim_start()
+        } else {
+            text
+        };
+        if !text.is_empty() {
+            self.output.push_str(text);
+            self.line_start = false;
+        }
+    }
+
+    // A block from its opening brace at `open`, if known, to its closing one
+    // which ends at `end`.
+    fn block<T>(
+        &mut self,
+        open: Option<usize>,
+        items: &[T],
+        end: usize,
+        span: fn(&T) -> Span,
+        print: fn(&mut Self, &T),
+    ) {
+        if let Some(open) = open {
+            self.comments_before(open);
+            self.outdent();
+        }
+        self.push("{");
+        let close = end - 1;
+        if items.is_empty() && self.comments.first().is_none_or(|c| c.span.start >= close) {
+            self.push("}");
+            return;
+        }
+
+        self.end_line();
+        self.depth += 1;
+        self.items(items, close, span, print);
+        self.depth -= 1;
+        self.start_line(false);
+        self.push("}");
+    }
+
+    // Prints a statement from where the line already is, without ending it.
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        let semicolon = span.end - 1;
+        match &statement.kind {
+            StmtKind::Var(name, initializer) => {
+                self.token("var ", span.start);
+                self.token(&name.lexeme, name.start);
+                if let Some(value) = initializer {
+                    self.push(" = ");
+                    self.expression(value);
+                }
+                self.token(";", semicolon);
+            }
+            StmtKind::Fun(function) => {
+                self.token("fun ", span.start);
+                self.function(function);
+            }
+            StmtKind::Class(class) => {
+                self.token("class ", span.start);
+                self.token(&class.name.lexeme, class.name.start);
+                if let Some(superclass) = &class.superclass {
+                    self.push(" < ");
+                    self.token(&superclass.lexeme, superclass.start);
+                }
+                self.push(" ");
+                self.block(
+                    None,
+                    &class.methods,
+                    span.end,
+                    function_span,
+                    Self::function,
+                );
+            }
+            StmtKind::Expression(value) => {
+                self.expression(value);
+                self.token(";", semicolon);
+            }
+            StmtKind::Print(value) => {
+                // `print(x)` reads like a call, the parentheses stay next to it.
+                match value.kind {
+                    ExprKind::Grouping(_) => self.token("print", span.start),
+                    _ => self.token("print ", span.start),
+                }
+                self.expression(value);
+                self.token(";", semicolon);
+            }
+            StmtKind::Return(value) => {
+                self.token("return", span.start);
+                if let Some(value) = value {
+                    self.push(" ");
+                    self.expression(value);
+                }
+                self.token(";", semicolon);
+            }
+            StmtKind::Block(statements) => self.block(
+                Some(span.start),
+                statements,
+                span.end,
+                stmt_span,
+                Self::statement,
+            ),
+            StmtKind::If {
+                condition,
+                paren,
+                then_branch,
+                else_branch,
+            } => {
+                self.token("if (", span.start);
+                self.expression(condition);
+                self.token(") ", paren.start);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    // Once a comment breaks the statement, `else` lines up
+                    // with its `if`.
+                    if self.continued {
+                        self.end_line();
+                        self.indent();
+                    }
+                    self.push(" else ");
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition,
+                paren,
+                body,
+            } => {
+                self.token("while (", span.start);
+                self.expression(condition);
+                self.token(") ", paren.start);
+                self.statement(body);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                semicolon,
+                increment,
+                paren,
+                body,
+            } => {
+                self.token("for (", span.start);
+                match initializer {
+                    Some(initializer) => self.statement(initializer),
+                    None => self.push(";"),
+                }
+                if let Some(condition) = condition {
+                    self.push(" ");
+                    self.expression(condition);
+                }
+                self.token(";", semicolon.start);
+                if let Some(increment) = increment {
+                    self.push(" ");
+                    self.expression(increment);
+                }
+                self.token(") ", paren.start);
+                self.statement(body);
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.token(&function.name.lexeme, function.name.start);
+        self.push("(");
+        for (index, param) in function.params.iter().enumerate() {
+            if index > 0 {
+                self.push(", ");
+            }
+            self.token(&param.lexeme, param.start);
+        }
+        self.push(") ");
+        self.block(
+            None,
+            &function.body,
+            function.span.end,
+            stmt_span,
+            Self::statement,
+        );
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        let span = expression.span;
+        match &expression.kind {
+            // Literals as written, e.g. `1.50` isn't shortened.
+            ExprKind::Literal(_) => self.token(&self.source[span.start..span.end], span.start),
+            ExprKind::Variable(name) | ExprKind::This(name) => self.token(&name.lexeme, name.start),
+            ExprKind::Assign(name, value) => {
+                self.token(&name.lexeme, name.start);
+                self.push(" = ");
+                self.expression(value);
+            }
+            ExprKind::Unary(operator, operand) => {
+                let text = match operator {
+                    UnaryOp::Not => "!",
+                    UnaryOp::Negate => "-",
+                };
+                self.token(text, span.start);
+                self.expression(operand);
+            }
+            ExprKind::Binary(left, operator, right) => {
+                self.expression(left);
+                self.push(&format!(" {} ", operator));
+                self.expression(right);
+            }
+            ExprKind::Logical(left, operator, operator_span, right) => {
+                let text = match operator {
+                    LogicalOp::And => "and",
+                    LogicalOp::Or => "or",
+                };
+                self.expression(left);
+                self.push(" ");
+                self.token(text, operator_span.start);
+                self.push(" ");
+                self.expression(right);
+            }
+            ExprKind::Grouping(inner) => {
+                self.token("(", span.start);
+                self.expression(inner);
+                self.token(")", span.end - 1);
+            }
+            ExprKind::Call(callee, arguments) => {
+                self.expression(callee);
+                self.push("(");
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index > 0 {
+                        self.push(", ");
+                    }
+                    self.expression(argument);
+                }
+                self.token(")", span.end - 1);
+            }
+            ExprKind::Get(object, name) => {
+                self.expression(object);
+                self.push(".");
+                self.token(&name.lexeme, name.start);
+            }
+            ExprKind::Set(object, name, value) => {
+                self.expression(object);
+                self.push(".");
+                self.token(&name.lexeme, name.start);
+                self.push(" = ");
+                self.expression(value);
+            }
+            ExprKind::Super(method) => {
+                self.token("super.", span.start);
+                self.token(&method.lexeme, method.start);
+            }
+            // Only programs without errors are printed.
+            ExprKind::Invalid => {}
+        }
+    }
+}
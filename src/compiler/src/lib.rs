@@ -1,18 +1,352 @@
-use common::chunk::Chunk;
+use std::io::Write;
+
+use ast::Program;
+use codegen::CodeGen;
+use common::object::Function;
 use parser::Parser;
 use scanner::scanner::Scanner;
+use symbols::Symbols;
 
-mod scanner;
+pub mod ast;
+pub mod scanner;
+mod codegen;
 mod compiler;
+mod error;
+mod fold;
+mod format;
+pub mod lint;
 mod parser;
+mod symbols;
+
+pub use error::{CompileError, CompileErrorKind};
+pub use format::format;
+pub use lint::{lint, Warning};
+pub use symbols::{Analysis, Reference, Symbol, SymbolKind};
+
+/// Optional compiler passes, all enabled by default.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CompileOptions {
+    // Evaluates operators applied to literals, and drops branches and loops
+    // on literal conditions.
+    pub fold_constants: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            fold_constants: true,
+        }
+    }
+}
+
+pub fn compile(source: &str) -> Result<Function, Vec<CompileError>> {
+    let (function, errors, _) = generate(source, CompileOptions::default(), None);
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(function)
+}
+
+// Like `compile`, but also reports each error to `error_output`.
+pub fn compile_with_output(
+    source: &str,
+    error_output: &mut dyn Write,
+) -> Result<Function, Vec<CompileError>> {
+    compile_with_options(source, CompileOptions::default(), error_output)
+}
+
+// Like `compile_with_output`, with some passes turned off.
+pub fn compile_with_options(
+    source: &str,
+    options: CompileOptions,
+    error_output: &mut dyn Write,
+) -> Result<Function, Vec<CompileError>> {
+    let (function, errors, _) = generate(source, options, None);
+
+    for error in &errors {
+        // Errors are still returned if reporting fails.
+        let _ = writeln!(error_output, "{}", error);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(function)
+}
+
+// Parses `source` into its syntax tree and comments without compiling it.
+// Only syntax errors are found this way, e.g. an undeclared local is not one.
+pub fn parse(source: &str) -> Result<Program, Vec<CompileError>> {
+    let mut scanner = Scanner::with_comments(source);
+    let mut parser = Parser::new(&mut scanner);
 
-pub fn compile(source: &str) -> Result<Chunk, ()> {
+    let program = parser.parse();
+
+    if parser.had_error {
+        return Err(parser.errors);
+    }
+
+    Ok(program)
+}
+
+// Parses `source` for editors, collecting its declarations, the variable
+// references resolved to them and any errors.
+pub fn analyze(source: &str) -> Analysis {
+    let options = CompileOptions {
+        fold_constants: false,
+    };
+    let (_, errors, symbols) = generate(source, options, Some(Symbols::default()));
+    symbols.unwrap().finish(errors)
+}
+
+// Parses `source` then emits its bytecode, even past syntax errors so all of
+// the errors are found. They are returned in source order.
+fn generate(
+    source: &str,
+    options: CompileOptions,
+    symbols: Option<Symbols>,
+) -> (Function, Vec<CompileError>, Option<Symbols>) {
     let mut scanner = Scanner::new(source);
-    let mut chunk = Chunk::new();
-    let mut parser = Parser::new(&mut scanner, &mut chunk);
+    let mut parser = Parser::new(&mut scanner);
+    let mut program = parser.parse();
+
+    let mut codegen = CodeGen::new();
+    codegen.symbols = symbols;
+    let mut function = codegen.generate(&program);
+
+    let mut errors = parser.errors;
+    errors.append(&mut codegen.errors);
+    errors.sort_by_key(|error| error.span.start);
+
+    // Folding drops dead branches, so it only runs once the program as
+    // written compiled and their errors were reported.
+    if options.fold_constants && errors.is_empty() {
+        fold::fold(&mut program);
+        function = CodeGen::new().generate(&program);
+    }
+
+    (function, errors, codegen.symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_returns_ok_for_valid_source() {
+        assert!(compile("var a = 1; print a;").is_ok());
+    }
+
+    #[test]
+    fn compile_reports_error_position() {
+        let errors = compile("var a = 1;\nprint a +;").unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![CompileError {
+                kind: CompileErrorKind::Syntax,
+                line: 2,
+                column: 10,
+                span: 20..21,
+                lexeme: ";".to_string(),
+                message: "Expect expression.".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn compile_reports_one_error_per_statement() {
+        let errors = compile("print;\nvar = 1;\nprint 1").unwrap_err();
+
+        let lines: Vec<i32> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+        assert_eq!(errors[2].lexeme, "");
+        assert_eq!(
+            errors[2].to_string(),
+            "[line 3] Error at end: Expect ';' after value."
+        );
+    }
+
+    #[test]
+    fn compile_with_output_reports_errors() {
+        let mut output = Vec::new();
+
+        let errors = compile_with_output("print;\nprint 1", &mut output).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[line 1] Error at ';': Expect expression.\n[line 2] Error at end: Expect ';' after value.\n"
+        );
+    }
+
+    #[test]
+    fn compile_reports_syntax_and_scope_errors_in_source_order() {
+        let errors = compile("{ var a; var a; }\nprint ;\nreturn;").unwrap_err();
+
+        let messages: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (1, "Already a variable with this name in this scope."),
+                (2, "Expect expression."),
+                (3, "Can't return from top-level code."),
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_reports_errors_in_branches_folding_drops() {
+        let errors = compile("if (false) { var a; var a; }").unwrap_err();
+
+        assert_eq!(
+            errors[0].message,
+            "Already a variable with this name in this scope."
+        );
+    }
+
+    #[test]
+    fn parse_builds_the_syntax_tree() {
+        let program = parse("var a = -1;\nprint a.b(2)\n  + 3;").unwrap();
+
+        assert_eq!(
+            program.to_string(),
+            "Var a [1:1]\n  Unary - [1:9]\n    Literal 1 [1:10]\nPrint [2:1]\n  Binary + [2:7]\n    Call [2:7]\n      Get b [2:7]\n        Variable a [2:7]\n      Literal 2 [2:11]\n    Literal 3 [3:5]\n"
+        );
+        // The addition is emitted once its right operand is read.
+        let print = &program.statements[1];
+        assert_eq!((print.span.line, print.span.end_line), (2, 3));
+    }
+
+    #[test]
+    fn parse_keeps_comments() {
+        let program = parse("// one\nprint 1; // two\r\n").unwrap();
+
+        let comments: Vec<_> = program
+            .comments
+            .iter()
+            .map(|c| (c.text.as_str(), c.span.line, c.span.start))
+            .collect();
+        assert_eq!(comments, vec![("// one", 1, 0), ("// two", 2, 16)]);
+        assert_eq!(program.comments_between(1, 20).len(), 1);
+    }
+
+    #[test]
+    fn parse_only_reports_syntax_errors() {
+        // Reading a local in its own initializer is found by the code
+        // generator.
+        assert!(parse("{ var a = a; }").is_ok());
+        assert!(compile("{ var a = a; }").is_err());
+
+        let errors = parse("var a = ;").unwrap_err();
+        assert_eq!(errors[0].message, "Expect expression.");
+    }
+
+    #[test]
+    fn compile_reports_tokens_that_cant_continue_an_expression() {
+        let errors = compile("print 1 = 2;").unwrap_err();
+        assert_eq!(errors[0].message, "Invalid assignment target.");
+
+        let errors = compile("var x = 1\nwhile (x) x = nil;").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "[line 2] Error at 'while': Expect ';' after variable declaration."
+        );
+
+        let errors = compile("var x = 1\nfor (;;) {}").unwrap_err();
+        assert_eq!(errors[0].lexeme, "for");
+    }
+
+    #[test]
+    fn compile_reports_positions_past_non_ascii_text() {
+        let errors = compile("print \"héllo\" +;").unwrap_err();
+
+        // The span counts the two bytes of `é`, the column one character.
+        assert_eq!(errors[0].span, 16..17);
+        assert_eq!(errors[0].column, 16);
+        assert_eq!(errors[0].lexeme, ";");
+    }
+
+    #[test]
+    fn compile_reports_lexical_errors() {
+        let errors = compile("print \"abc").unwrap_err();
+
+        assert_eq!(errors[0].kind, CompileErrorKind::Lexical);
+        assert_eq!(errors[0].span, 6..10);
+        assert_eq!(
+            errors[0].to_string(),
+            "[line 1] Error: Unterminated string."
+        );
+    }
+
+    #[test]
+    fn compile_records_local_variables() {
+        let function = compile("fun f(a) {\n  { var b = a; }\n  var c;\n}").unwrap();
+        let chunk = &function.chunk.constants.values[1].as_function().chunk;
+
+        let locals: Vec<_> = chunk
+            .locals
+            .iter()
+            .map(|local| (local.name.as_str(), local.slot, local.start, local.end))
+            .collect();
+        // b is initialized by the `OP_GET_LOCAL` at 0 and popped at 2, c by
+        // the `OP_NIL` at 3.
+        assert_eq!(locals, vec![("b", 2, 2, 3), ("a", 1, 0, 6), ("c", 2, 4, 6)]);
+    }
+
+    #[test]
+    fn analyze_resolves_references_to_declarations() {
+        let source = "fun outer(a) {\n  var b = a;\n  fun inner() { return b + later; }\n}\nclass C { m() {} }\nvar later = outer;";
+        let analysis = analyze(source);
+        assert!(analysis.errors.is_empty());
+
+        let symbols: Vec<_> = analysis
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.description(), s.container))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("outer", "global function", None),
+                ("a", "parameter", Some(0)),
+                ("b", "local variable", Some(0)),
+                ("inner", "local function", Some(0)),
+                ("C", "class", None),
+                ("m", "method", Some(4)),
+                ("later", "global variable", None),
+            ]
+        );
+
+        // `b` is captured by inner, `later` is declared after its use.
+        let references: Vec<_> = analysis
+            .references
+            .iter()
+            .map(|r| (r.name.as_str(), r.line, r.symbol))
+            .collect();
+        assert_eq!(
+            references,
+            vec![
+                ("a", 2, Some(1)),
+                ("b", 3, Some(2)),
+                ("later", 3, Some(6)),
+                ("outer", 6, Some(0)),
+            ]
+        );
+    }
 
-    parser.parse();
+    #[test]
+    fn analyze_reports_errors_and_undeclared_globals() {
+        let analysis = analyze("print missing;\nvar = 1;");
 
-    Ok(chunk)
-    // !parser.had_error
-}
\ No newline at end of file
+        assert_eq!(analysis.references[0].symbol, None);
+        assert_eq!(analysis.errors.len(), 1);
+        assert_eq!(analysis.errors[0].line, 2);
+    }
+}
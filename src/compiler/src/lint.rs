@@ -0,0 +1,753 @@
+use std::{collections::HashSet, fmt};
+
+use crate::{
+    ast::{
+        BinaryOp, Expr, ExprKind, Function, Literal, LogicalOp, Program, Span, Stmt, StmtKind,
+        UnaryOp,
+    },
+    error::CompileError,
+};
+
+// Lint codes, as named in `// lox-allow(code)` comments.
+pub const UNUSED_LOCAL: &str = "unused-local";
+pub const UNUSED_GLOBAL: &str = "unused-global";
+pub const SHADOWED: &str = "shadowed";
+pub const UNREACHABLE: &str = "unreachable";
+pub const CONSTANT_CONDITION: &str = "constant-condition";
+pub const SELF_ASSIGNMENT: &str = "self-assignment";
+pub const TYPE_MISMATCH: &str = "type-mismatch";
+
+/// Something that compiles but is likely a mistake.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Warning {
+    pub code: &'static str,
+    pub line: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning({}): {}",
+            self.line, self.code, self.message
+        )
+    }
+}
+
+/// Lints `source`, returning its warnings by line. A `// lox-allow(code)`
+/// comment before a statement, or after it on its last line, silences
+/// `code` for the statement and everything in it. Fails on the first syntax
+/// error like `format`.
+pub fn lint(source: &str) -> Result<Vec<Warning>, CompileError> {
+    let program = crate::parse(source).map_err(|errors| errors[0].clone())?;
+
+    let mut linter = Linter::new(&program);
+    for statement in &program.statements {
+        statement_assignments(statement, &mut linter.assigned);
+    }
+    linter.statements(&program.statements, 0);
+    linter.unused_globals();
+
+    linter.warnings.sort_by_key(|warning| warning.line);
+    Ok(linter.warnings)
+}
+
+// Types known without running the program.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Type {
+    Nil,
+    Bool,
+    Number,
+    String,
+}
+
+impl Type {
+    fn name(self) -> &'static str {
+        match self {
+            Type::Nil => "nil",
+            Type::Bool => "a boolean",
+            Type::Number => "a number",
+            Type::String => "a string",
+        }
+    }
+}
+
+struct Variable {
+    name: String,
+    // "variable", "function", "class" or "parameter".
+    what: &'static str,
+    line: i32,
+    used: bool,
+    // Only known for variables that are never assigned after their
+    // declaration.
+    ty: Option<Type>,
+    // An allow comment covered the declaration.
+    allow_unused: bool,
+}
+
+struct Linter<'a> {
+    // For the allow comments.
+    program: &'a Program,
+    // Innermost last, empty at the top level.
+    scopes: Vec<Vec<Variable>>,
+    globals: Vec<Variable>,
+    // Names read that didn't resolve to a local.
+    global_reads: HashSet<String>,
+    // Names assigned anywhere in the file.
+    assigned: HashSet<String>,
+    // Codes allowed by each enclosing statement.
+    allowed: Vec<Vec<String>>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(program: &'a Program) -> Self {
+        Linter {
+            program,
+            scopes: Vec::new(),
+            globals: Vec::new(),
+            global_reads: HashSet::new(),
+            assigned: HashSet::new(),
+            allowed: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn is_allowed(&self, code: &str) -> bool {
+        self.allowed.iter().flatten().any(|allowed| allowed == code)
+    }
+
+    fn warn(&mut self, code: &'static str, line: i32, message: String) {
+        if !self.is_allowed(code) {
+            self.warnings.push(Warning {
+                code,
+                line,
+                message,
+            });
+        }
+    }
+
+    // The statements of a block or file, which starts at the byte offset
+    // `start`.
+    fn statements(&mut self, statements: &[Stmt], start: usize) {
+        let spans: Vec<_> = statements.iter().map(|statement| statement.span).collect();
+        let mut returned = false;
+        let mut reported = false;
+        for (index, statement) in statements.iter().enumerate() {
+            self.allowed.push(self.allowed_codes(&spans, index, start));
+            if returned && !reported {
+                self.warn(
+                    UNREACHABLE,
+                    statement.span.line,
+                    "Unreachable code after 'return'.".to_string(),
+                );
+                reported = true;
+            }
+            self.statement(statement);
+            self.allowed.pop();
+
+            returned = returned || always_returns(statement);
+        }
+    }
+
+    // The codes named by allow comments on the node at `spans[index]`: those
+    // between it and the node before it, or `start` for the first one, and
+    // the first one after it on its last line.
+    fn allowed_codes(&self, spans: &[Span], index: usize, start: usize) -> Vec<String> {
+        let span = spans[index];
+        let mut comments: Vec<_> = match index.checked_sub(1).map(|previous| spans[previous]) {
+            // A comment on the last line of the node before is its own.
+            Some(previous) => self
+                .program
+                .comments_between(previous.end, span.start)
+                .iter()
+                .filter(|comment| comment.span.line != previous.end_line)
+                .collect(),
+            None => self
+                .program
+                .comments_between(start, span.start)
+                .iter()
+                .collect(),
+        };
+        let next = spans.get(index + 1).map_or(usize::MAX, |next| next.start);
+        comments.extend(
+            self.program
+                .comments_between(span.end, next)
+                .first()
+                .filter(|comment| comment.span.line == span.end_line),
+        );
+
+        comments
+            .into_iter()
+            .filter_map(|comment| {
+                let text = comment.text.trim_start_matches('/').trim();
+                text.strip_prefix("lox-allow(")?.strip_suffix(')')
+            })
+            .flat_map(|codes| codes.split(',').map(|code| code.trim().to_string()))
+            .collect()
+    }
+
+    // Allow comments are read by `statements`, nested statements have none.
+    fn statement(&mut self, statement: &Stmt) {
+        let line = statement.span.line;
+        match &statement.kind {
+            StmtKind::Var(name, initializer) => {
+                let ty = match initializer {
+                    Some(initializer) => {
+                        self.expression(initializer, line);
+                        self.type_of(initializer)
+                    }
+                    None => Some(Type::Nil),
+                };
+                let ty = ty.filter(|_| !self.assigned.contains(&name.lexeme));
+                self.declare(&name.lexeme, "variable", line, ty);
+            }
+            StmtKind::Fun(function) => {
+                self.declare(&function.name.lexeme, "function", line, None);
+                self.function(function, line);
+            }
+            StmtKind::Class(class) => {
+                if let Some(superclass) = &class.superclass {
+                    self.read(&superclass.lexeme);
+                }
+                self.declare(&class.name.lexeme, "class", line, None);
+
+                let spans: Vec<_> = class.methods.iter().map(|method| method.span).collect();
+                for (index, method) in class.methods.iter().enumerate() {
+                    self.allowed
+                        .push(self.allowed_codes(&spans, index, statement.span.start));
+                    self.function(method, method.span.line);
+                    self.allowed.pop();
+                }
+            }
+            StmtKind::Expression(value)
+            | StmtKind::Print(value)
+            | StmtKind::Return(Some(value)) => self.expression(value, line),
+            StmtKind::Return(None) => {}
+            StmtKind::Block(statements) => {
+                self.scopes.push(Vec::new());
+                self.statements(statements, statement.span.start);
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.condition(condition, line, false);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition, body, ..
+            } => {
+                self.condition(condition, line, true);
+                self.statement(body);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.scopes.push(Vec::new());
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expression(condition, line);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment, line);
+                }
+                self.statement(body);
+                self.end_scope();
+            }
+        }
+    }
+
+    // Parameters share their scope with the body, like in the compiler.
+    fn function(&mut self, function: &Function, line: i32) {
+        self.scopes.push(Vec::new());
+        for param in &function.params {
+            self.declare(&param.lexeme, "parameter", line, None);
+        }
+        self.statements(&function.body, function.span.start);
+        self.end_scope();
+    }
+    fn declare(&mut self, name: &str, what: &'static str, line: i32, ty: Option<Type>) {
+        let unused_code = if self.scopes.is_empty() {
+            UNUSED_GLOBAL
+        } else {
+            UNUSED_LOCAL
+        };
+        let variable = Variable {
+            name: name.to_string(),
+            what,
+            line,
+            used: false,
+            ty,
+            allow_unused: self.is_allowed(unused_code),
+        };
+
+        if self.scopes.is_empty() {
+            // Globals can be redefined, which makes their type unknown.
+            match self.globals.iter_mut().find(|g| g.name == name) {
+                Some(global) => global.ty = None,
+                None => self.globals.push(variable),
+            }
+            return;
+        }
+
+        let (scope, enclosing) = self.scopes.split_last_mut().unwrap();
+
+        let shadowed = enclosing
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().find(|v| v.name == name))
+            .map(|shadowed| shadowed.line);
+        scope.push(variable);
+        if let Some(shadowed) = shadowed {
+            self.warn(
+                SHADOWED,
+                line,
+                format!(
+                    "'{}' shadows a variable declared on line {}.",
+                    name, shadowed
+                ),
+            );
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        for variable in scope {
+            if !variable.used && !variable.allow_unused && variable.what != "parameter" {
+                self.warnings.push(Warning {
+                    code: UNUSED_LOCAL,
+                    line: variable.line,
+                    message: format!("Local {} '{}' is never used.", variable.what, variable.name),
+                });
+            }
+        }
+    }
+
+    fn unused_globals(&mut self) {
+        for global in &self.globals {
+            if !self.global_reads.contains(&global.name) && !global.allow_unused {
+                self.warnings.push(Warning {
+                    code: UNUSED_GLOBAL,
+                    line: global.line,
+                    message: format!("Global {} '{}' is never used.", global.what, global.name),
+                });
+            }
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|v| v.name == name))
+    }
+
+    fn read(&mut self, name: &str) {
+        match self.resolve(name) {
+            Some(variable) => variable.used = true,
+            None => {
+                self.global_reads.insert(name.to_string());
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr, line: i32, is_loop: bool) {
+        // `while (true)` is how loops without a condition are written.
+        let infinite_loop = is_loop && condition.kind == ExprKind::Literal(Literal::True);
+        if let Some(truthy) = constant(condition).filter(|_| !infinite_loop) {
+            self.warn(
+                CONSTANT_CONDITION,
+                line,
+                format!(
+                    "Condition is always {}.",
+                    if truthy { "true" } else { "false" }
+                ),
+            );
+        }
+        self.expression(condition, line);
+    }
+
+    fn expression(&mut self, expression: &Expr, line: i32) {
+        match &expression.kind {
+            ExprKind::Literal(_) | ExprKind::This(_) | ExprKind::Super(_) | ExprKind::Invalid => {}
+            ExprKind::Variable(name) => self.read(&name.lexeme),
+            ExprKind::Grouping(inner) | ExprKind::Unary(_, inner) => self.expression(inner, line),
+            ExprKind::Assign(name, value) => {
+                if matches!(&ungrouped(value).kind, ExprKind::Variable(b) if b.lexeme == name.lexeme)
+                {
+                    self.self_assignment(line);
+                }
+                self.expression(value, line);
+            }
+            ExprKind::Set(object, name, value) => {
+                if matches!(&ungrouped(value).kind,
+                    ExprKind::Get(b, b_name) if b_name.lexeme == name.lexeme && same_place(object, b))
+                {
+                    self.self_assignment(line);
+                }
+                // Only the object of a property is read.
+                self.expression(object, line);
+                self.expression(value, line);
+            }
+            ExprKind::Binary(left, operator, right) => {
+                self.comparison(left, *operator, right, line);
+                self.expression(left, line);
+                self.expression(right, line);
+            }
+            ExprKind::Logical(left, _, _, right) => {
+                self.expression(left, line);
+                self.expression(right, line);
+            }
+            ExprKind::Call(callee, arguments) => {
+                self.expression(callee, line);
+                for argument in arguments {
+                    self.expression(argument, line);
+                }
+            }
+            ExprKind::Get(object, _) => self.expression(object, line),
+        }
+    }
+
+    fn self_assignment(&mut self, line: i32) {
+        self.warn(
+            SELF_ASSIGNMENT,
+            line,
+            "Assigning a variable to itself has no effect.".to_string(),
+        );
+    }
+
+    fn comparison(&mut self, left: &Expr, operator: BinaryOp, right: &Expr, line: i32) {
+        let (left, right) = (self.type_of(left), self.type_of(right));
+        let message = match operator {
+            BinaryOp::Equal | BinaryOp::NotEqual => match (left, right) {
+                (Some(left), Some(right)) if left != right => format!(
+                    "Comparing {} with {} is always {}.",
+                    left.name(),
+                    right.name(),
+                    operator == BinaryOp::NotEqual
+                ),
+                _ => return,
+            },
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                match [left, right]
+                    .into_iter()
+                    .flatten()
+                    .find(|ty| *ty != Type::Number)
+                {
+                    Some(ty) => format!(
+                        "Comparing {} with '{}' fails at runtime.",
+                        ty.name(),
+                        operator
+                    ),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        self.warn(TYPE_MISMATCH, line, message);
+    }
+
+    fn type_of(&mut self, expression: &Expr) -> Option<Type> {
+        match &expression.kind {
+            ExprKind::Literal(literal) => Some(match literal {
+                Literal::Nil => Type::Nil,
+                Literal::True | Literal::False => Type::Bool,
+                Literal::String(_) => Type::String,
+                Literal::Number(_) | Literal::Float(_) => Type::Number,
+            }),
+            ExprKind::Variable(name) => match self.resolve(&name.lexeme) {
+                Some(variable) => variable.ty,
+                None => self.globals.iter().find(|g| g.name == name.lexeme)?.ty,
+            },
+            ExprKind::Grouping(inner) => self.type_of(inner),
+            ExprKind::Unary(UnaryOp::Negate, _) => Some(Type::Number),
+            ExprKind::Unary(UnaryOp::Not, _) => Some(Type::Bool),
+            ExprKind::Assign(_, value) | ExprKind::Set(_, _, value) => self.type_of(value),
+            ExprKind::Binary(left, operator, right) => match operator {
+                BinaryOp::Add => self.same_type(left, right),
+                BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => Some(Type::Number),
+                _ => Some(Type::Bool),
+            },
+            // `and` and `or` give one of their operands.
+            ExprKind::Logical(left, _, _, right) => self.same_type(left, right),
+            _ => None,
+        }
+    }
+
+    // The type of both operands, for `+` which adds numbers or joins
+    // strings, `and` and `or`.
+    fn same_type(&mut self, left: &Expr, right: &Expr) -> Option<Type> {
+        let left = self.type_of(left)?;
+        Some(left).filter(|left| self.type_of(right) == Some(*left))
+    }
+}
+
+// Whether every path through `statement` returns.
+fn always_returns(statement: &Stmt) -> bool {
+    match &statement.kind {
+        StmtKind::Return(_) => true,
+        StmtKind::Block(statements) => statements.iter().any(always_returns),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
+
+// The truthiness of a condition made only of literals. Like
+// `Value::is_falsey` and the folder, only `true` is truthy.
+fn constant(expression: &Expr) -> Option<bool> {
+    match &expression.kind {
+        ExprKind::Literal(literal) => Some(*literal == Literal::True),
+        ExprKind::Grouping(inner) => constant(inner),
+        ExprKind::Unary(UnaryOp::Not, inner) => constant(inner).map(|truthy| !truthy),
+        ExprKind::Logical(left, operator, _, right) => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            Some(match operator {
+                LogicalOp::And => left && right,
+                LogicalOp::Or => left || right,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn ungrouped(expression: &Expr) -> &Expr {
+    match &expression.kind {
+        ExprKind::Grouping(inner) => ungrouped(inner),
+        _ => expression,
+    }
+}
+
+// Whether `a` and `b` name the same variable or property.
+fn same_place(a: &Expr, b: &Expr) -> bool {
+    match (&a.kind, &ungrouped(b).kind) {
+        (ExprKind::Variable(a), ExprKind::Variable(b)) => a.lexeme == b.lexeme,
+        (ExprKind::Get(a, a_name), ExprKind::Get(b, b_name)) => {
+            a_name.lexeme == b_name.lexeme && same_place(a, b)
+        }
+        (ExprKind::This(_), ExprKind::This(_)) => true,
+        _ => false,
+    }
+}
+
+// Collects every name assigned to, wherever it is declared.
+fn statement_assignments(statement: &Stmt, names: &mut HashSet<String>) {
+    let mut expressions = Vec::new();
+    let mut statements = Vec::new();
+    match &statement.kind {
+        StmtKind::Var(_, initializer) => expressions.extend(initializer),
+        StmtKind::Fun(function) => statements.extend(&function.body),
+        StmtKind::Class(class) => {
+            statements.extend(class.methods.iter().flat_map(|method| &method.body))
+        }
+        StmtKind::Block(body) => statements.extend(body),
+        StmtKind::Expression(value) | StmtKind::Print(value) => expressions.push(value),
+        StmtKind::Return(value) => expressions.extend(value),
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            expressions.push(condition);
+            statements.push(&**then_branch);
+            statements.extend(else_branch.as_deref());
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            expressions.push(condition);
+            statements.push(&**body);
+        }
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            statements.extend(initializer.as_deref());
+            expressions.extend(condition);
+            expressions.extend(increment);
+            statements.push(&**body);
+        }
+    }
+    for statement in statements {
+        statement_assignments(statement, names);
+    }
+    for expression in expressions {
+        expression_assignments(expression, names);
+    }
+}
+
+fn expression_assignments(expression: &Expr, names: &mut HashSet<String>) {
+    match &expression.kind {
+        ExprKind::Assign(name, value) => {
+            names.insert(name.lexeme.clone());
+            expression_assignments(value, names);
+        }
+        ExprKind::Set(object, _, value)
+        | ExprKind::Binary(object, _, value)
+        | ExprKind::Logical(object, _, _, value) => {
+            expression_assignments(object, names);
+            expression_assignments(value, names);
+        }
+        ExprKind::Grouping(inner) | ExprKind::Unary(_, inner) | ExprKind::Get(inner, _) => {
+            expression_assignments(inner, names)
+        }
+        ExprKind::Call(callee, arguments) => {
+            expression_assignments(callee, names);
+            for argument in arguments {
+                expression_assignments(argument, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("{ var a = 1; }", UNUSED_LOCAL, 1, "Local variable 'a' is never used.")]
+    #[case(
+        "fun f() { fun g() {} }\nf();",
+        UNUSED_LOCAL,
+        1,
+        "Local function 'g' is never used."
+    )]
+    #[case(
+        "var a = 1;\nfun f() {}\nf();",
+        UNUSED_GLOBAL,
+        1,
+        "Global variable 'a' is never used."
+    )]
+    #[case(
+        "fun f(a) {\n  var a2 = a;\n  { var a = a2; print a; }\n}\nf(1);",
+        SHADOWED,
+        3,
+        "'a' shadows a variable declared on line 1."
+    )]
+    #[case(
+        "fun f() {\n  return 1;\n  print 2;\n}\nf();",
+        UNREACHABLE,
+        3,
+        "Unreachable code after 'return'."
+    )]
+    #[case(
+        "fun f(a) {\n  if (a) return 1; else { return 2; }\n  print a;\n}\nf(1);",
+        UNREACHABLE,
+        3,
+        "Unreachable code after 'return'."
+    )]
+    #[case(
+        "if (!nil) print 1;",
+        CONSTANT_CONDITION,
+        1,
+        "Condition is always true."
+    )]
+    #[case(
+        "if (1) print 1; else print 2;",
+        CONSTANT_CONDITION,
+        1,
+        "Condition is always false."
+    )]
+    #[case(
+        "while (\"s\") print 1;",
+        CONSTANT_CONDITION,
+        1,
+        "Condition is always false."
+    )]
+    #[case(
+        "while (false or nil) print 1;",
+        CONSTANT_CONDITION,
+        1,
+        "Condition is always false."
+    )]
+    #[case(
+        "var a = 1;\na = (a);",
+        SELF_ASSIGNMENT,
+        2,
+        "Assigning a variable to itself has no effect."
+    )]
+    #[case(
+        "class A { m() { this.x = this.x; } }\nA().m();",
+        SELF_ASSIGNMENT,
+        1,
+        "Assigning a variable to itself has no effect."
+    )]
+    #[case(
+        "var x = 1;\nprint x == nil;",
+        TYPE_MISMATCH,
+        2,
+        "Comparing a number with nil is always false."
+    )]
+    #[case(
+        "print \"a\" + \"b\" != -1;",
+        TYPE_MISMATCH,
+        1,
+        "Comparing a string with a number is always true."
+    )]
+    #[case(
+        "print true < 1;",
+        TYPE_MISMATCH,
+        1,
+        "Comparing a boolean with '<' fails at runtime."
+    )]
+    fn warns(#[case] source: &str, #[case] code: &str, #[case] line: i32, #[case] message: &str) {
+        let warnings: Vec<_> = lint(source)
+            .unwrap()
+            .into_iter()
+            .map(|w| (w.code, w.line, w.message))
+            .collect();
+
+        assert_eq!(warnings, vec![(code, line, message.to_string())]);
+    }
+
+    #[rstest]
+    #[case("var a = 1;\nprint a;\na = a + 1;")]
+    #[case("var x = 1;\nx = nil;\nprint x == nil;")]
+    #[case("while (true) print 1;")]
+    #[case("fun f(unused) {}\nf(1);")]
+    #[case("fun f() { return g(); }\nfun g() { return f(); }")]
+    #[case("{ var a = 1; // lox-allow(unused-local)\n}")]
+    #[case("// lox-allow(unused-local, shadowed)\nfun f() {\n  var a;\n  { var a; }\n}\nf();")]
+    #[case("// lox-allow(unused-global)\nvar a;")]
+    fn stays_quiet(#[case] source: &str) {
+        assert_eq!(lint(source).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reports_warnings_by_line() {
+        let warnings = lint("fun f() {\n  return;\n  var a = 1;\n}").unwrap();
+
+        let lines: Vec<_> = warnings.iter().map(|w| (w.line, w.code)).collect();
+        assert_eq!(
+            lines,
+            vec![(1, UNUSED_GLOBAL), (3, UNREACHABLE), (3, UNUSED_LOCAL)]
+        );
+        assert_eq!(
+            warnings[1].to_string(),
+            "[line 3] Warning(unreachable): Unreachable code after 'return'."
+        );
+    }
+}
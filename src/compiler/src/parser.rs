@@ -1,9 +1,19 @@
-use common::{chunk::Chunk, value::Value, opcode::OpCode};
 use lazy_static::lazy_static;
 use maplit::hashmap;
 use std::collections::HashMap;
 
-use crate::{scanner::{scanner::Scanner, token::{TokenType, Token}}, compiler::Compiler};
+use crate::{
+    ast::{
+        BinaryOp, Class, Comment, Expr, ExprKind, Function, Literal, LogicalOp, Program, Span,
+        Stmt, StmtKind, UnaryOp,
+    },
+    compiler::FunctionType,
+    error::{CompileError, CompileErrorKind},
+    scanner::{
+        scanner::Scanner,
+        token::{Token, TokenType},
+    },
+};
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Precedence {
@@ -35,11 +45,12 @@ pub fn precedence_from_u8(n: u8) -> Option<Precedence> {
     }
 }
 
-pub type ParseFn = fn(&mut Parser, can_assign: bool) -> ();
+pub type PrefixFn = fn(&mut Parser, can_assign: bool) -> Expr;
+pub type InfixFn = fn(&mut Parser, left: Expr, can_assign: bool) -> Expr;
 
 pub struct ParseRule {
-    pub prefix: Option<ParseFn>,
-    pub infix: Option<ParseFn>,
+    pub prefix: Option<PrefixFn>,
+    pub infix: Option<InfixFn>,
     pub precedence: Precedence,
 }
 
@@ -49,8 +60,10 @@ lazy_static! {
             prefix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
                 Parser::grouping(parser, can_assign)
             }),
-            infix: None,
-            precedence: Precedence::None,
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::call(parser, left, can_assign)
+            }),
+            precedence: Precedence::Call,
         },
         TokenType::TokenRightParen => ParseRule {
             prefix: None,
@@ -67,19 +80,31 @@ lazy_static! {
             infix: None,
             precedence: Precedence::None,
         },
+        TokenType::TokenComma => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenDot => ParseRule {
+            prefix: None,
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::dot(parser, left, can_assign)
+            }),
+            precedence: Precedence::Call,
+        },
         TokenType::TokenMinus => ParseRule {
             prefix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
                 Parser::unary(parser, can_assign)
             }),
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Term,
         },
         TokenType::TokenPlus => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Term,
         },
@@ -90,15 +115,15 @@ lazy_static! {
         },
         TokenType::TokenSlash => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Factor,
         },
         TokenType::TokenStar => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Factor,
         },
@@ -111,43 +136,48 @@ lazy_static! {
         },
         TokenType::TokenBangEqual => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Equality,
         },
+        TokenType::TokenEqual => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
         TokenType::TokenEqualEqual => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Equality,
         },
         TokenType::TokenGreater => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Comparison,
         },
         TokenType::TokenGreaterEqual => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Comparison,
         },
         TokenType::TokenLess => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Comparison,
         },
         TokenType::TokenLessEqual => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::binary(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::binary(parser, left, can_assign)
             }),
             precedence: Precedence::Comparison,
         },
@@ -213,17 +243,56 @@ lazy_static! {
             infix: None,
             precedence: Precedence::None,
         },
+        TokenType::TokenClass => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenSuper => ParseRule {
+            prefix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
+                Parser::super_(parser, can_assign)
+            }),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenThis => ParseRule {
+            prefix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
+                Parser::this_(parser, can_assign)
+            }),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenFor => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenFun => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenReturn => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::TokenWhile => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
         TokenType::TokenAnd => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::and_(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::and_(parser, left, can_assign)
             }),
             precedence: Precedence::And,
         },
         TokenType::TokenOr => ParseRule {
             prefix: None,
-            infix: Some(|parser: &mut Parser<'_>, can_assign: bool| {
-                Parser::or_(parser, can_assign)
+            infix: Some(|parser: &mut Parser<'_>, left: Expr, can_assign: bool| {
+                Parser::or_(parser, left, can_assign)
             }),
             precedence: Precedence::Or,
         },
@@ -240,42 +309,59 @@ pub struct Parser<'a> {
     pub previous: Token,
     pub had_error: bool,
     pub panic_mode: bool,
+    pub errors: Vec<CompileError>,
+    // Comments the scanner returned, if it keeps them.
+    pub comments: Vec<Comment>,
 
     pub scanner: &'a mut Scanner<'a>,
-    pub chunk: &'a mut Chunk,
-    pub current_compiler: Compiler,
+    // What encloses the code being parsed, for the errors that depend on it
+    // such as `return` at the top level.
+    pub function_types: Vec<FunctionType>,
+    // Whether each enclosing class has a superclass.
+    pub classes: Vec<bool>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(scanner: &'a mut Scanner<'a>, chunk: &'a mut Chunk) -> Self {
+    pub fn new(scanner: &'a mut Scanner<'a>) -> Self {
         Parser {
             current: Token {
                 token_type: TokenType::Unknown,
                 lexeme: "".to_string(),
                 line: -1,
+                ..Default::default()
             },
             previous: Token {
                 token_type: TokenType::Unknown,
                 lexeme: "".to_string(),
                 line: -1,
+                ..Default::default()
             },
             had_error: false,
             panic_mode: false,
+            errors: Vec::new(),
+            comments: Vec::new(),
 
             scanner,
-            chunk,
-            current_compiler: Compiler::new(),
+            function_types: vec![FunctionType::Script],
+            classes: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) {
+    // Parses the whole source. Statements that failed to parse are kept as
+    // far as they got, with `ExprKind::Invalid` for missing expressions.
+    pub fn parse(&mut self) -> Program {
         self.advance();
 
+        let mut statements = Vec::new();
         while !self.match_token_type(TokenType::TokenEof) {
-            self.declaration();
+            statements.push(self.declaration());
         }
 
-        self.end_compiler();
+        Program {
+            statements,
+            comments: std::mem::take(&mut self.comments),
+            end_line: self.previous.line,
+        }
     }
 
     fn advance(&mut self) {
@@ -283,11 +369,14 @@ impl<'a> Parser<'a> {
 
         loop {
             self.current = self.scanner.next().unwrap();
-            if self.current.token_type != TokenType::TokenError {
-                break;
+            match self.current.token_type {
+                TokenType::TokenComment => self.comments.push(Comment {
+                    text: self.current.lexeme.trim_end().to_string(),
+                    span: Span::of(&self.current),
+                }),
+                TokenType::TokenError => self.error_at_current(self.current.lexeme.to_string()),
+                _ => break,
             }
-
-            self.error_at_current(self.current.lexeme.to_string());
         }
     }
 
@@ -312,475 +401,508 @@ impl<'a> Parser<'a> {
         true
     }
 
-    fn emit_byte(&mut self, byte: u8) {
-        let line = self.previous.line;
-        self.current_chunk().write_chunk(byte, line);
-    }
-
-    fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
-        self.emit_byte(byte1);
-        self.emit_byte(byte2);
-    }
-
-    fn emit_loop(&mut self, loop_start: i32) {
-        self.emit_byte(OpCode::OpLoop as u8);
-
-        let offset = self.current_chunk().code.len() as i32 - loop_start + 2;
-
-        self.emit_byte(((offset >> 8) & 0xff) as u8);
-        self.emit_byte((offset & 0xff) as u8);
+    // The span from `first` to the last token read.
+    fn span_from(&self, first: Span) -> Span {
+        first.to(Span::of(&self.previous))
     }
 
-    fn emit_jump(&mut self, instruction: u8) -> i32 {
-        self.emit_byte(instruction);
-        self.emit_byte(0xff);
-        self.emit_byte(0xff);
-        (self.current_chunk().code.len() - 2) as i32
-    }
-
-    fn emit_return(&mut self) {
-        self.emit_byte(OpCode::OpReturn as u8)
-    }
-
-    fn emit_constant(&mut self, value: Value) {
-        let line = self.previous.line;
-        self.current_chunk().write_constant(value, line);
-    }
-
-    fn patch_jump(&mut self, offset: i32) {
-        // -2 to adjust for the bytecode for the jump offset itself.
-        let jump = self.current_chunk().code.len() as i32 - offset - 2;
-
-        // if (jump > UINT16_MAX) {
-        // 	error("Too much code to jump over.");
-        //   }
-
-        self.current_chunk().code[offset as usize] = ((jump >> 8) & 0xff) as u8;
-        self.current_chunk().code[(offset + 1) as usize] = (jump & 0xff) as u8;
-    }
-
-    fn end_compiler(&mut self) {
-        self.emit_return();
-        // TODO:
-        // if !self.had_error {
-        //     disassemble_chunk(self.current_chunk(), "code".to_string());
-        // }
-    }
-
-    fn begin_scope(&mut self) {
-        self.current_compiler.scope_depth += 1;
-    }
-
-    fn end_scope(&mut self) {
-        self.current_compiler.scope_depth -= 1;
-
-        while !self.current_compiler.locals.is_empty()
-            && self
-                .current_compiler
-                .local_at(self.current_compiler.locals.len() - 1)
-                .depth
-                > self.current_compiler.scope_depth
-        {
-            self.emit_byte(OpCode::OpPop as u8);
-            self.current_compiler.locals.pop();
+    fn expr(&self, kind: ExprKind, first: Span) -> Expr {
+        Expr {
+            kind,
+            span: self.span_from(first),
         }
     }
 
-    fn current_chunk(&mut self) -> &mut Chunk {
-        self.chunk
-    }
-
-    fn binary(&mut self, _can_assign: bool) {
+    fn binary(&mut self, left: Expr, _can_assign: bool) -> Expr {
         let operator_type = self.previous.token_type;
         let rule = self.get_rule(operator_type);
         let precedence = precedence_from_u8(rule.precedence as u8 + 1).unwrap();
-        self.parse_precedence(precedence);
+        let right = self.parse_precedence(precedence);
+
+        let operator = match operator_type {
+            TokenType::TokenBangEqual => BinaryOp::NotEqual,
+            TokenType::TokenEqualEqual => BinaryOp::Equal,
+            TokenType::TokenGreater => BinaryOp::Greater,
+            TokenType::TokenGreaterEqual => BinaryOp::GreaterEqual,
+            TokenType::TokenLess => BinaryOp::Less,
+            TokenType::TokenLessEqual => BinaryOp::LessEqual,
+            TokenType::TokenPlus => BinaryOp::Add,
+            TokenType::TokenMinus => BinaryOp::Subtract,
+            TokenType::TokenStar => BinaryOp::Multiply,
+            _ => BinaryOp::Divide,
+        };
+        let first = left.span;
+        self.expr(
+            ExprKind::Binary(Box::new(left), operator, Box::new(right)),
+            first,
+        )
+    }
 
-        match operator_type {
-            TokenType::TokenBangEqual => {
-                self.emit_bytes(OpCode::OpEqual as u8, OpCode::OpNot as u8)
-            }
-            TokenType::TokenEqualEqual => self.emit_byte(OpCode::OpEqual as u8),
-            TokenType::TokenGreater => self.emit_byte(OpCode::OpGreater as u8),
-            TokenType::TokenGreaterEqual => {
-                self.emit_bytes(OpCode::OpLess as u8, OpCode::OpNot as u8)
-            }
-            TokenType::TokenLess => self.emit_byte(OpCode::OpLess as u8),
-            TokenType::TokenLessEqual => {
-                self.emit_bytes(OpCode::OpGreater as u8, OpCode::OpNot as u8)
-            }
-            TokenType::TokenPlus => self.emit_byte(OpCode::OpAdd as u8),
-            TokenType::TokenMinus => self.emit_byte(OpCode::OpSubtract as u8),
-            TokenType::TokenStar => self.emit_byte(OpCode::OpMultiply as u8),
-            TokenType::TokenSlash => self.emit_byte(OpCode::OpDivide as u8),
-            _ => (),
-        }
+    fn call(&mut self, left: Expr, _can_assign: bool) -> Expr {
+        let arguments = self.argument_list();
+        let first = left.span;
+        self.expr(ExprKind::Call(Box::new(left), arguments), first)
     }
 
-    fn literal(&mut self, _can_assign: bool) {
-        match self.previous.token_type {
-            TokenType::TokenFalse => self.emit_byte(OpCode::OpFalse as u8),
-            TokenType::TokenTrue => self.emit_byte(OpCode::OpTrue as u8),
-            TokenType::TokenNil => self.emit_byte(OpCode::OpNil as u8),
-            _ => (),
+    fn dot(&mut self, left: Expr, can_assign: bool) -> Expr {
+        self.consume(
+            TokenType::TokenIdentifier,
+            "Expect property name after '.'.".to_string(),
+        );
+        let name = self.previous.clone();
+        let first = left.span;
+
+        if can_assign && self.match_token_type(TokenType::TokenEqual) {
+            let value = self.expression();
+            self.expr(ExprKind::Set(Box::new(left), name, Box::new(value)), first)
+        } else {
+            self.expr(ExprKind::Get(Box::new(left), name), first)
         }
     }
 
-    fn grouping(&mut self, _can_assign: bool) {
-        self.expression();
+    fn literal(&mut self, _can_assign: bool) -> Expr {
+        let literal = match self.previous.token_type {
+            TokenType::TokenFalse => Literal::False,
+            TokenType::TokenTrue => Literal::True,
+            _ => Literal::Nil,
+        };
+        self.expr(ExprKind::Literal(literal), Span::of(&self.previous))
+    }
+
+    fn grouping(&mut self, _can_assign: bool) -> Expr {
+        let first = Span::of(&self.previous);
+        let inner = self.expression();
         self.consume(
             TokenType::TokenRightParen,
             "Expect ')' after expression.".to_string(),
         );
+        self.expr(ExprKind::Grouping(Box::new(inner)), first)
     }
 
-    fn number(&mut self, _can_assign: bool) {
-        let value: Value = Value::new_number(self.previous.lexeme.parse().unwrap());
-        self.emit_constant(value);
-    }
-
-    fn or_(&mut self, _can_assign: bool) {
-        let else_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
-        let end_jump = self.emit_jump(OpCode::OpJump as u8);
-
-        self.patch_jump(else_jump);
-        self.emit_byte(OpCode::OpPop as u8);
+    fn number(&mut self, _can_assign: bool) -> Expr {
+        let lexeme = &self.previous.lexeme;
+        let literal = if lexeme.contains('.') {
+            lexeme.parse().map(Literal::Float).ok()
+        } else {
+            lexeme.parse().map(Literal::Number).ok()
+        };
 
-        self.parse_precedence(Precedence::Or);
-        self.patch_jump(end_jump);
+        let kind = match literal {
+            Some(literal) => ExprKind::Literal(literal),
+            None => {
+                self.error("Number literal is out of range.".to_string());
+                ExprKind::Invalid
+            }
+        };
+        self.expr(kind, Span::of(&self.previous))
     }
 
-    fn string(&mut self, _can_assign: bool) {
-        let slen = self.previous.lexeme.len();
-        let value = Value::new_obj_string(self.previous.lexeme[1..slen - 1].to_string());
-        self.emit_constant(value);
+    fn or_(&mut self, left: Expr, _can_assign: bool) -> Expr {
+        self.logical(left, LogicalOp::Or, Precedence::Or)
     }
 
-    fn variable(&mut self, can_assign: bool) {
-        let prev = self.previous.to_owned();
-        self.named_variable(&prev, can_assign);
+    fn string(&mut self, _can_assign: bool) -> Expr {
+        let slen = self.previous.lexeme.len();
+        let value = self.previous.lexeme[1..slen - 1].to_string();
+        self.expr(
+            ExprKind::Literal(Literal::String(value)),
+            Span::of(&self.previous),
+        )
     }
 
-    fn named_variable(&mut self, name: &Token, can_assign: bool) {
-        let get_op: OpCode;
-        let set_op: OpCode;
-        let get_op_long: OpCode;
-        let set_op_long: OpCode;
-        let mut arg = self.resolve_local(name);
+    fn variable(&mut self, can_assign: bool) -> Expr {
+        let name = self.previous.clone();
+        let first = Span::of(&name);
 
-        if arg != -1 {
-            get_op = OpCode::OpGetLocal;
-            set_op = OpCode::OpSetLocal;
-            get_op_long = OpCode::OpGetLocalLong;
-            set_op_long = OpCode::OpSetLocalLong;
+        if can_assign && self.match_token_type(TokenType::TokenEqual) {
+            let value = self.expression();
+            self.expr(ExprKind::Assign(name, Box::new(value)), first)
         } else {
-            arg = self.identifier_constant(name);
-            get_op = OpCode::OpGetGlobal;
-            set_op = OpCode::OpSetGlobal;
-            get_op_long = OpCode::OpGetGlobalLong;
-            set_op_long = OpCode::OpSetGlobalLong;
+            self.expr(ExprKind::Variable(name), first)
         }
+    }
 
-        if can_assign && self.match_token_type(TokenType::TokenEqual) {
-            self.expression();
-            if arg < 256 {
-                self.emit_bytes(set_op as u8, arg as u8);
-            } else {
-                self.emit_byte(set_op_long as u8);
-                self.emit_byte((arg & 0xff) as u8);
-                self.emit_byte(((arg >> 8) & 0xff) as u8);
-                self.emit_byte(((arg >> 16) & 0xff) as u8);
+    fn super_(&mut self, _can_assign: bool) -> Expr {
+        let first = Span::of(&self.previous);
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class.".to_string()),
+            Some(false) => {
+                self.error("Can't use 'super' in a class with no superclass.".to_string())
             }
-        } else if arg < 256 {
-            self.emit_bytes(get_op as u8, arg as u8);
-        } else {
-            self.emit_byte(get_op_long as u8);
-            self.emit_byte((arg & 0xff) as u8);
-            self.emit_byte(((arg >> 8) & 0xff) as u8);
-            self.emit_byte(((arg >> 16) & 0xff) as u8);
+            Some(true) => (),
         }
+
+        self.consume(TokenType::TokenDot, "Expect '.' after 'super'.".to_string());
+        self.consume(
+            TokenType::TokenIdentifier,
+            "Expect superclass method name.".to_string(),
+        );
+        self.expr(ExprKind::Super(self.previous.clone()), first)
     }
 
-    fn unary(&mut self, _can_assign: bool) {
-        let operator_type = self.previous.token_type;
+    fn this_(&mut self, _can_assign: bool) -> Expr {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.".to_string());
+        }
 
-        self.parse_precedence(Precedence::Unary);
+        self.expr(
+            ExprKind::This(self.previous.clone()),
+            Span::of(&self.previous),
+        )
+    }
 
-        match operator_type {
-            TokenType::TokenBang => self.emit_byte(OpCode::OpNot as u8),
-            TokenType::TokenMinus => self.emit_byte(OpCode::OpNegate as u8),
-            _ => (),
-        }
+    fn unary(&mut self, _can_assign: bool) -> Expr {
+        let operator = match self.previous.token_type {
+            TokenType::TokenBang => UnaryOp::Not,
+            _ => UnaryOp::Negate,
+        };
+        let first = Span::of(&self.previous);
+
+        let operand = self.parse_precedence(Precedence::Unary);
+        self.expr(ExprKind::Unary(operator, Box::new(operand)), first)
     }
 
-    fn parse_precedence(&mut self, precedence: Precedence) {
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
         self.advance();
         let prefix_rule = self.get_rule(self.previous.token_type).prefix;
         let prefix_rule_fn = match prefix_rule {
             Some(rule_fn) => rule_fn,
             None => {
                 self.error("Expect expression.".to_string());
-                return;
+                return self.expr(ExprKind::Invalid, Span::of(&self.previous));
             }
         };
 
         let can_assign = precedence as u8 <= Precedence::Assignment as u8;
-        prefix_rule_fn(self, can_assign);
+        let mut expression = prefix_rule_fn(self, can_assign);
 
         while precedence as u8 <= self.get_rule(self.current.token_type).precedence as u8 {
             self.advance();
             let infix_rule_fn = self.get_rule(self.previous.token_type).infix.unwrap();
-            infix_rule_fn(self, can_assign);
+            expression = infix_rule_fn(self, expression, can_assign);
         }
 
         if can_assign && self.match_token_type(TokenType::TokenEqual) {
-            self.error("Invalid assignmet target.".to_string());
+            self.error("Invalid assignment target.".to_string());
         }
-    }
 
-    fn define_variable(&mut self, global: i32) {
-        if self.current_compiler.scope_depth > 0 {
-            self.mark_initialized();
-            return;
-        }
-        if global < 256 {
-            self.emit_bytes(OpCode::OpDefineGlobal as u8, global as u8);
-        } else {
-            self.emit_byte(OpCode::OpDefineGlobalLong as u8);
-            self.emit_byte((global & 0xff) as u8);
-            self.emit_byte(((global >> 8) & 0xff) as u8);
-            self.emit_byte(((global >> 16) & 0xff) as u8);
-        }
+        expression
     }
 
-    fn and_(&mut self, _can_assign: bool) {
-        let end_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
-
-        self.emit_byte(OpCode::OpPop as u8);
-        self.parse_precedence(Precedence::And);
+    fn and_(&mut self, left: Expr, _can_assign: bool) -> Expr {
+        self.logical(left, LogicalOp::And, Precedence::And)
+    }
 
-        self.patch_jump(end_jump);
+    fn logical(&mut self, left: Expr, operator: LogicalOp, precedence: Precedence) -> Expr {
+        let operator_span = Span::of(&self.previous);
+        let right = self.parse_precedence(precedence);
+        let first = left.span;
+        self.expr(
+            ExprKind::Logical(Box::new(left), operator, operator_span, Box::new(right)),
+            first,
+        )
     }
 
-    fn parse_variable(&mut self, error_message: String) -> i32 {
+    fn parse_variable(&mut self, error_message: String) -> Token {
         self.consume(TokenType::TokenIdentifier, error_message);
+        self.previous.clone()
+    }
 
-        self.declare_variable();
-        if self.current_compiler.scope_depth > 0 {
-            return 0;
-        }
+    fn argument_list(&mut self) -> Vec<Expr> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::TokenRightParen) {
+            loop {
+                arguments.push(self.expression());
+                if arguments.len() == 256 {
+                    self.error("Can't have more than 255 arguments.".to_string());
+                }
 
-        let prev = &self.previous.clone();
-        self.identifier_constant(prev)
-    }
+                if !self.match_token_type(TokenType::TokenComma) {
+                    break;
+                }
+            }
+        }
 
-    fn mark_initialized(&mut self) {
-        self.current_compiler.update_local_depth_at(
-            self.current_compiler.locals.len() - 1,
-            self.current_compiler.scope_depth,
+        self.consume(
+            TokenType::TokenRightParen,
+            "Expect ')' after arguments.".to_string(),
         );
+        arguments
     }
 
-    fn identifier_constant(&mut self, name: &Token) -> i32 {
-        self.current_chunk()
-            .add_constant(Value::new_obj_string(name.lexeme.to_owned()))
+    fn get_rule(&self, token_type: TokenType) -> &ParseRule {
+        &PARSER_RULES[&token_type]
     }
 
-    fn resolve_local(&mut self, name: &Token) -> i32 {
-        let mut i = self.current_compiler.locals.len() as i32 - 1;
-        while i >= 0 {
-            let local = self.current_compiler.local_at(i as usize);
-            if name.lexeme == local.name.lexeme {
-                if local.depth == -1 {
-                    self.error("Can't read local variable in its own initializer.".to_string());
-                }
-                return i;
-            }
+    fn expression(&mut self) -> Expr {
+        self.parse_precedence(Precedence::Assignment)
+    }
 
-            i -= 1;
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::TokenRightBrace) && !self.check(TokenType::TokenEof) {
+            statements.push(self.declaration());
         }
 
-        -1
+        self.consume(
+            TokenType::TokenRightBrace,
+            "Expect '}' after block.".to_string(),
+        );
+        statements
     }
 
-    fn declare_variable(&mut self) {
-        if self.current_compiler.scope_depth == 0 {
-            return;
-        }
+    fn function(&mut self, function_type: FunctionType) -> Function {
+        let name = self.previous.clone();
+        self.function_types.push(function_type);
 
-        let mut error_flagged: bool = false;
-        let name = &self.previous;
-        let mut i = self.current_compiler.locals.len() as i32 - 1;
-        while i >= 0 {
-            let local = self.current_compiler.local_at(i as usize);
-            if local.depth != -1 && local.depth < self.current_compiler.scope_depth {
-                break;
-            }
+        self.consume(
+            TokenType::TokenLeftParen,
+            "Expect '(' after function name.".to_string(),
+        );
+        let mut params = Vec::new();
+        if !self.check(TokenType::TokenRightParen) {
+            loop {
+                if params.len() == 255 {
+                    self.error_at_current("Can't have more than 255 parameters.".to_string());
+                }
+                params.push(self.parse_variable("Expect parameter name.".to_string()));
 
-            i -= 1;
-            if name.lexeme == local.name.lexeme {
-                error_flagged = true;
-                break;
+                if !self.match_token_type(TokenType::TokenComma) {
+                    break;
+                }
             }
         }
-        if !error_flagged {
-            self.current_compiler.add_local(name);
-        } else {
-            self.error("Already a variable with this name in this scope.".to_string());
+        self.consume(
+            TokenType::TokenRightParen,
+            "Expect ')' after parameters.".to_string(),
+        );
+        self.consume(
+            TokenType::TokenLeftBrace,
+            "Expect '{' before function body.".to_string(),
+        );
+        let body = self.block();
+        self.function_types.pop();
+
+        Function {
+            span: self.span_from(Span::of(&name)),
+            name,
+            params,
+            body,
         }
     }
 
-    fn get_rule(&self, token_type: TokenType) -> &ParseRule {
-        &PARSER_RULES[&token_type]
-    }
+    fn method(&mut self) -> Function {
+        self.consume(
+            TokenType::TokenIdentifier,
+            "Expect method name.".to_string(),
+        );
 
-    fn expression(&mut self) {
-        self.parse_precedence(Precedence::Assignment);
+        let function_type = if self.previous.lexeme == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(function_type)
     }
 
-    fn block(&mut self) {
-        while !self.check(TokenType::TokenRightBrace) && !self.check(TokenType::TokenEof) {
-            self.declaration();
+    fn class_declaration(&mut self) -> StmtKind {
+        self.consume(TokenType::TokenIdentifier, "Expect class name.".to_string());
+        let name = self.previous.clone();
+
+        self.classes.push(false);
+
+        let mut superclass = None;
+        if self.match_token_type(TokenType::TokenLess) {
+            self.consume(
+                TokenType::TokenIdentifier,
+                "Expect superclass name.".to_string(),
+            );
+
+            if name.lexeme == self.previous.lexeme {
+                self.error("A class can't inherit from itself.".to_string());
+            }
+
+            superclass = Some(self.previous.clone());
+            *self.classes.last_mut().unwrap() = true;
         }
 
+        self.consume(
+            TokenType::TokenLeftBrace,
+            "Expect '{' before class body.".to_string(),
+        );
+        let mut methods = Vec::new();
+        while !self.check(TokenType::TokenRightBrace) && !self.check(TokenType::TokenEof) {
+            methods.push(self.method());
+        }
         self.consume(
             TokenType::TokenRightBrace,
-            "Expect '}' after block.".to_string(),
+            "Expect '}' after class body.".to_string(),
         );
+
+        self.classes.pop();
+        StmtKind::Class(Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
+    fn fun_declaration(&mut self) -> StmtKind {
+        self.parse_variable("Expect function name.".to_string());
+        StmtKind::Fun(self.function(FunctionType::Function))
     }
 
-    fn var_declaration(&mut self) {
-        let global = self.parse_variable("Expect variable name.".to_string());
+    fn var_declaration(&mut self) -> StmtKind {
+        let name = self.parse_variable("Expect variable name.".to_string());
 
-        if self.match_token_type(TokenType::TokenEqual) {
-            self.expression();
+        let initializer = if self.match_token_type(TokenType::TokenEqual) {
+            Some(self.expression())
         } else {
-            self.emit_byte(OpCode::OpNil as u8)
-        }
+            None
+        };
         self.consume(
             TokenType::TokenSemicolon,
             "Expect ';' after variable declaration.".to_string(),
         );
 
-        self.define_variable(global);
+        StmtKind::Var(name, initializer)
     }
 
-    fn expression_statement(&mut self) {
-        self.expression();
+    fn expression_statement(&mut self) -> StmtKind {
+        let expression = self.expression();
         self.consume(
             TokenType::TokenSemicolon,
             "Expect ';' after expression.".to_string(),
         );
-        self.emit_byte(OpCode::OpPop as u8);
+        StmtKind::Expression(expression)
     }
 
-    fn for_statement(&mut self) {
-        self.begin_scope();
+    fn for_statement(&mut self) -> StmtKind {
         self.consume(
             TokenType::TokenLeftParen,
             "Expect '(' after 'for'.".to_string(),
         );
-        if self.match_token_type(TokenType::TokenSemicolon) {
+        let first = Span::of(&self.current);
+        let initializer = if self.match_token_type(TokenType::TokenSemicolon) {
             // No initializer
+            None
         } else if self.match_token_type(TokenType::TokenVar) {
-            self.var_declaration();
+            Some(self.var_declaration())
         } else {
-            self.expression_statement();
-        }
-
-        let mut loop_start = self.current_chunk().code.len() as i32;
-        let mut exit_jump = -1;
+            Some(self.expression_statement())
+        };
+        let initializer = initializer.map(|kind| {
+            Box::new(Stmt {
+                kind,
+                span: self.span_from(first),
+            })
+        });
+
+        let mut condition = None;
         if !self.match_token_type(TokenType::TokenSemicolon) {
-            self.expression();
+            condition = Some(self.expression());
             self.consume(
                 TokenType::TokenSemicolon,
                 "Expect ';' after loop condition.".to_string(),
             );
-
-            // Jump out of the loop if the condition is false.
-            exit_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
-            self.emit_byte(OpCode::OpPop as u8); // Condition.
         }
+        let semicolon = Span::of(&self.previous);
 
+        let mut increment = None;
         if !self.match_token_type(TokenType::TokenRightParen) {
-            let body_jump = self.emit_jump(OpCode::OpJump as u8);
-            let increment_start = self.current_chunk().code.len() as i32;
-            self.expression();
-            self.emit_byte(OpCode::OpPop as u8);
+            increment = Some(self.expression());
             self.consume(
                 TokenType::TokenRightParen,
                 "Expect ')' after for clauses.".to_string(),
             );
-
-            self.emit_loop(loop_start);
-            loop_start = increment_start;
-            self.patch_jump(body_jump);
         }
+        let paren = Span::of(&self.previous);
 
-        self.statement();
-        self.emit_loop(loop_start);
-
-        if exit_jump != -1 {
-            self.patch_jump(exit_jump);
-            self.emit_byte(OpCode::OpPop as u8);
+        StmtKind::For {
+            initializer,
+            condition,
+            semicolon,
+            increment,
+            paren,
+            body: Box::new(self.statement()),
         }
-        self.end_scope();
     }
 
-    fn if_statement(&mut self) {
+    fn if_statement(&mut self) -> StmtKind {
         self.consume(
             TokenType::TokenLeftParen,
             "Expect '(' after 'if'.".to_string(),
         );
-        self.expression();
+        let condition = self.expression();
         self.consume(
             TokenType::TokenRightParen,
             "Expect ')' after condition.".to_string(),
         );
+        let paren = Span::of(&self.previous);
 
-        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
-        self.emit_byte(OpCode::OpPop as u8);
-        self.statement();
-
-        let else_jump = self.emit_jump(OpCode::OpJump as u8);
-
-        self.patch_jump(then_jump);
-        self.emit_byte(OpCode::OpPop as u8);
+        let then_branch = Box::new(self.statement());
+        let else_branch = if self.match_token_type(TokenType::TokenElse) {
+            Some(Box::new(self.statement()))
+        } else {
+            None
+        };
 
-        if self.match_token_type(TokenType::TokenElse) {
-            self.statement();
+        StmtKind::If {
+            condition,
+            paren,
+            then_branch,
+            else_branch,
         }
-        self.patch_jump(else_jump);
     }
 
-    fn print_statement(&mut self) {
-        self.expression();
+    fn print_statement(&mut self) -> StmtKind {
+        let value = self.expression();
         self.consume(
             TokenType::TokenSemicolon,
             "Expect ';' after value.".to_string(),
         );
-        self.emit_byte(OpCode::OpPrint as u8);
+        StmtKind::Print(value)
+    }
+
+    fn return_statement(&mut self) -> StmtKind {
+        let function_type = *self.function_types.last().unwrap();
+        if function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.".to_string());
+        }
+
+        if self.match_token_type(TokenType::TokenSemicolon) {
+            StmtKind::Return(None)
+        } else {
+            if function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.".to_string());
+            }
+
+            let value = self.expression();
+            self.consume(
+                TokenType::TokenSemicolon,
+                "Expect ';' after return value.".to_string(),
+            );
+            StmtKind::Return(Some(value))
+        }
     }
 
-    fn while_statement(&mut self) {
-        let loop_start = self.current_chunk().code.len() as i32;
+    fn while_statement(&mut self) -> StmtKind {
         self.consume(
             TokenType::TokenLeftParen,
             "Expect '(' after 'while'.".to_string(),
         );
-        self.expression();
+        let condition = self.expression();
         self.consume(
             TokenType::TokenRightParen,
             "Expect ')' after 'while'.".to_string(),
         );
+        let paren = Span::of(&self.previous);
 
-        let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse as u8);
-        self.emit_byte(OpCode::OpPop as u8);
-        self.statement();
-        self.emit_loop(loop_start);
-
-        self.patch_jump(exit_jump);
-        self.emit_byte(OpCode::OpPop as u8);
+        StmtKind::While {
+            condition,
+            paren,
+            body: Box::new(self.statement()),
+        }
     }
 
     fn synchronize(&mut self) {
@@ -809,33 +931,57 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn declaration(&mut self) {
-        if self.match_token_type(TokenType::TokenVar) {
-            self.var_declaration();
+    fn declaration(&mut self) -> Stmt {
+        let first = Span::of(&self.current);
+        let statement = if self.match_token_type(TokenType::TokenClass) {
+            let kind = self.class_declaration();
+            Stmt {
+                kind,
+                span: self.span_from(first),
+            }
+        } else if self.match_token_type(TokenType::TokenFun) {
+            let kind = self.fun_declaration();
+            Stmt {
+                kind,
+                span: self.span_from(first),
+            }
+        } else if self.match_token_type(TokenType::TokenVar) {
+            let kind = self.var_declaration();
+            Stmt {
+                kind,
+                span: self.span_from(first),
+            }
         } else {
-            self.statement();
-        }
+            self.statement()
+        };
 
         if self.panic_mode {
             self.synchronize();
         }
+        statement
     }
 
-    fn statement(&mut self) {
-        if self.match_token_type(TokenType::TokenPrint) {
-            self.print_statement();
+    fn statement(&mut self) -> Stmt {
+        let first = Span::of(&self.current);
+        let kind = if self.match_token_type(TokenType::TokenPrint) {
+            self.print_statement()
         } else if self.match_token_type(TokenType::TokenLeftBrace) {
-            self.begin_scope();
-            self.block();
-            self.end_scope();
+            StmtKind::Block(self.block())
         } else if self.match_token_type(TokenType::TokenIf) {
-            self.if_statement();
+            self.if_statement()
+        } else if self.match_token_type(TokenType::TokenReturn) {
+            self.return_statement()
         } else if self.match_token_type(TokenType::TokenWhile) {
-            self.while_statement();
+            self.while_statement()
         } else if self.match_token_type(TokenType::TokenFor) {
-            self.for_statement();
+            self.for_statement()
         } else {
-            self.expression_statement();
+            self.expression_statement()
+        };
+
+        Stmt {
+            kind,
+            span: self.span_from(first),
         }
     }
 
@@ -844,15 +990,19 @@ impl<'a> Parser<'a> {
             return;
         }
         self.panic_mode = true;
-        print!("[line {}] Error", token.line);
 
-        match token.token_type {
-            TokenType::TokenEof => print!(" at end"),
-            TokenType::TokenError => (),
-            _ => print!(" at '{}'", token.lexeme),
-        }
-
-        println!(": {}", message);
+        let kind = match token.token_type {
+            TokenType::TokenError => CompileErrorKind::Lexical,
+            _ => CompileErrorKind::Syntax,
+        };
+        self.errors.push(CompileError {
+            kind,
+            line: token.line,
+            column: token.column,
+            span: token.start..token.end,
+            lexeme: self.scanner.source[token.start..token.end].to_string(),
+            message,
+        });
         self.had_error = true;
     }
 
@@ -1,2 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod scanner;
-pub mod token;
\ No newline at end of file
+pub mod token;
+
+pub use scanner::Scanner;
+pub use token::{Token, TokenType};
@@ -2,9 +2,14 @@ use super::token::{Token, TokenType};
 
 pub struct Scanner<'a> {
     pub source: &'a str,
+    // Byte offsets into `source`.
     pub start: i32,
     pub current: i32,
     pub line: i32,
+    pub line_start: i32,
+    // Scan `//` comments as tokens instead of skipping them, for tools that
+    // must not lose them.
+    pub keep_comments: bool,
 }
 
 impl Iterator for Scanner<'_> {
@@ -23,7 +28,7 @@ impl Iterator for Scanner<'_> {
         if c.is_alphabetic() {
             return Some(self.identifier());
         }
-        if c.is_digit(10) {
+        if c.is_ascii_digit() {
             return Some(self.number());
         }
 
@@ -37,6 +42,7 @@ impl Iterator for Scanner<'_> {
             '.' => Some(self.make_token(TokenType::TokenDot)),
             '-' => Some(self.make_token(TokenType::TokenMinus)),
             '+' => Some(self.make_token(TokenType::TokenPlus)),
+            '/' if self.keep_comments && self.peek() == '/' => Some(self.comment()),
             '/' => Some(self.make_token(TokenType::TokenSlash)),
             '*' => Some(self.make_token(TokenType::TokenStar)),
             '!' => {
@@ -84,15 +90,22 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            keep_comments: false,
+        }
+    }
+
+    pub fn with_comments(source: &'a str) -> Self {
+        Scanner {
+            keep_comments: true,
+            ..Scanner::new(source)
         }
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source
-            .chars()
-            .nth((self.current - 1) as usize)
-            .unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8() as i32;
+        c
     }
 
     fn skip_whitespace(&mut self) {
@@ -105,15 +118,12 @@ impl<'a> Scanner<'a> {
                 '\n' => {
                     self.line += 1;
                     self.advance();
+                    self.line_start = self.current;
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        // A comment goes until the end of the line.
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                '/' if self.peek_next() == '/' && !self.keep_comments => {
+                    // A comment goes until the end of the line.
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
                 _ => return,
@@ -122,29 +132,18 @@ impl<'a> Scanner<'a> {
     }
 
     fn identifier_type(&mut self) -> TokenType {
-        match self.source.chars().nth((self.start) as usize).unwrap() {
+        match self.source[self.start as usize..].chars().next().unwrap() {
             'a' => return self.check_keyword(1, 2, "nd".to_string(), TokenType::TokenAnd),
             'c' => return self.check_keyword(1, 4, "lass".to_string(), TokenType::TokenClass),
             'e' => return self.check_keyword(1, 3, "lse".to_string(), TokenType::TokenElse),
-            'f' => {
-                if self.current - self.start > 1 {
-                    match self.source.chars().nth((self.start + 1) as usize).unwrap() {
-                        'a' => {
-                            return self.check_keyword(
-                                2,
-                                3,
-                                "lse".to_string(),
-                                TokenType::TokenFalse,
-                            )
-                        }
-                        'o' => {
-                            return self.check_keyword(2, 1, "r".to_string(), TokenType::TokenFor)
-                        }
-                        'u' => {
-                            return self.check_keyword(2, 1, "n".to_string(), TokenType::TokenFun)
-                        }
-                        _ => (),
+            'f' if self.current - self.start > 1 => {
+                match self.source[self.start as usize..].chars().nth(1).unwrap() {
+                    'a' => {
+                        return self.check_keyword(2, 3, "lse".to_string(), TokenType::TokenFalse)
                     }
+                    'o' => return self.check_keyword(2, 1, "r".to_string(), TokenType::TokenFor),
+                    'u' => return self.check_keyword(2, 1, "n".to_string(), TokenType::TokenFun),
+                    _ => (),
                 }
             }
             'i' => return self.check_keyword(1, 1, "f".to_string(), TokenType::TokenIf),
@@ -153,17 +152,11 @@ impl<'a> Scanner<'a> {
             'p' => return self.check_keyword(1, 4, "rint".to_string(), TokenType::TokenPrint),
             'r' => return self.check_keyword(1, 5, "eturn".to_string(), TokenType::TokenReturn),
             's' => return self.check_keyword(1, 4, "uper".to_string(), TokenType::TokenSuper),
-            't' => {
-                if self.current - self.start > 1 {
-                    match self.source.chars().nth((self.start + 1) as usize).unwrap() {
-                        'h' => {
-                            return self.check_keyword(2, 2, "is".to_string(), TokenType::TokenThis)
-                        }
-                        'r' => {
-                            return self.check_keyword(2, 2, "ue".to_string(), TokenType::TokenTrue)
-                        }
-                        _ => (),
-                    }
+            't' if self.current - self.start > 1 => {
+                match self.source[self.start as usize..].chars().nth(1).unwrap() {
+                    'h' => return self.check_keyword(2, 2, "is".to_string(), TokenType::TokenThis),
+                    'r' => return self.check_keyword(2, 2, "ue".to_string(), TokenType::TokenTrue),
+                    _ => (),
                 }
             }
             'v' => return self.check_keyword(1, 2, "ar".to_string(), TokenType::TokenVar),
@@ -192,7 +185,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn identifier(&mut self) -> Token {
-        while self.peek().is_alphabetic() || self.peek().is_digit(10) {
+        while self.peek().is_alphabetic() || self.peek().is_ascii_digit() {
             self.advance();
         }
 
@@ -201,31 +194,34 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        return self.source.chars().nth((self.current) as usize).unwrap();
+        self.source[self.current as usize..]
+            .chars()
+            .next()
+            .unwrap_or('\0')
     }
 
     fn peek_next(&mut self) -> char {
-        if self.current + 1 >= self.source.chars().count() as i32 {
-            return '\0';
+        self.source[self.current as usize..]
+            .chars()
+            .nth(1)
+            .unwrap_or('\0')
+    }
+
+    fn comment(&mut self) -> Token {
+        while self.peek() != '\n' && !self.is_at_end() {
+            self.advance();
         }
 
-        return self
-            .source
-            .chars()
-            .nth((self.current + 1) as usize)
-            .unwrap();
+        self.make_token(TokenType::TokenComment)
     }
 
     fn string(&mut self) -> Token {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
+            let c = self.advance();
+            if c == '\n' {
                 self.line += 1;
+                self.line_start = self.current;
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -241,25 +237,25 @@ impl<'a> Scanner<'a> {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().nth((self.current) as usize).unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn number(&mut self) -> Token {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Look for a fractional part.
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consume the ".".
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -268,7 +264,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_at_end(&mut self) -> bool {
-        self.current >= self.source.chars().count() as i32
+        self.current >= self.source.len() as i32
     }
 
     fn make_token(&mut self, token_type: TokenType) -> Token {
@@ -276,6 +272,9 @@ impl<'a> Scanner<'a> {
             token_type,
             lexeme: self.source[(self.start as usize)..(self.current as usize)].to_string(),
             line: self.line,
+            column: self.column(),
+            start: self.start as usize,
+            end: self.current as usize,
         }
     }
 
@@ -284,8 +283,18 @@ impl<'a> Scanner<'a> {
             token_type: TokenType::TokenError,
             lexeme: message,
             line: self.line,
+            column: self.column(),
+            start: self.start as usize,
+            end: self.current as usize,
         }
     }
+
+    fn column(&self) -> i32 {
+        // Tokens spanning several lines (strings) report the column on the
+        // line they end on.
+        let line_start = self.line_start.min(self.start) as usize;
+        self.source[line_start..self.start as usize].chars().count() as i32 + 1
+    }
 }
 
 #[cfg(test)]
@@ -357,4 +366,21 @@ mod tests {
             expected_token, token.token_type
         );
     }
+
+    #[test]
+    fn keeps_comments_when_asked() {
+        let source = "a // one\n/ // two";
+
+        let skipped: Vec<_> = Scanner::new(source)
+            .take_while(|t| t.token_type != TokenType::TokenEof)
+            .map(|t| t.lexeme)
+            .collect();
+        let kept: Vec<_> = Scanner::with_comments(source)
+            .take_while(|t| t.token_type != TokenType::TokenEof)
+            .map(|t| t.lexeme)
+            .collect();
+
+        assert_eq!(skipped, vec!["a", "/"]);
+        assert_eq!(kept, vec!["a", "// one", "/", "// two"]);
+    }
 }
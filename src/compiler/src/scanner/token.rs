@@ -1,4 +1,4 @@
-#[derive(Debug, Eq, Copy, Clone, PartialEq, Hash)]
+#[derive(Debug, Default, Eq, Copy, Clone, PartialEq, Hash)]
 pub enum TokenType {
     // Single-character tokens.
     TokenLeftParen,
@@ -46,22 +46,25 @@ pub enum TokenType {
     TokenVar,
     TokenWhile,
 
+    // Only scanned with `Scanner::with_comments`.
+    TokenComment,
     TokenError,
     TokenEof,
 
+    #[default]
     Unknown,
 }
 
-impl Default for TokenType {
-    fn default() -> Self {
-        TokenType::Unknown
-    }
-}
-
+#[derive(Debug, Default, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: i32,
+    // 1-based, counted in characters rather than bytes.
+    pub column: i32,
+    // Byte offsets of the token in the source.
+    pub start: usize,
+    pub end: usize,
 }
 
 impl Clone for Token {
@@ -70,6 +73,9 @@ impl Clone for Token {
             token_type: self.token_type,
             lexeme: self.lexeme.clone(),
             line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
         }
     }
 
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{error::CompileError, scanner::token::Token};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+}
+
+/// A declared name.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Declared at the top level of the script.
+    pub global: bool,
+    pub line: i32,
+    pub column: i32,
+    pub span: Range<usize>,
+    // Index of the enclosing function or class.
+    pub container: Option<usize>,
+}
+
+impl Symbol {
+    // What the symbol is, e.g. "local variable".
+    pub fn description(&self) -> &'static str {
+        match (self.kind, self.global) {
+            (SymbolKind::Variable, true) => "global variable",
+            (SymbolKind::Variable, false) => "local variable",
+            (SymbolKind::Parameter, _) => "parameter",
+            (SymbolKind::Function, true) => "global function",
+            (SymbolKind::Function, false) => "local function",
+            (SymbolKind::Class, _) => "class",
+            (SymbolKind::Method, _) => "method",
+        }
+    }
+}
+
+/// A use of a variable name.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub line: i32,
+    pub column: i32,
+    pub span: Range<usize>,
+    // Index of the declaration, `None` for undeclared globals.
+    pub symbol: Option<usize>,
+}
+
+/// What `analyze` found in a source file.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+    pub errors: Vec<CompileError>,
+}
+
+/// Collects declarations and references while the parser runs.
+#[derive(Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+    // Declarations by the start of their name, to resolve locals.
+    by_start: HashMap<usize, usize>,
+    // Enclosing declarations, `None` where the name wasn't declared.
+    containers: Vec<Option<usize>>,
+}
+
+impl Symbols {
+    pub fn declare(&mut self, name: &Token, kind: SymbolKind, global: bool) {
+        self.by_start.insert(name.start, self.symbols.len());
+        self.symbols.push(Symbol {
+            name: name.lexeme.clone(),
+            kind,
+            global,
+            line: name.line,
+            column: name.column,
+            span: name.start..name.end,
+            container: self.containers.last().copied().flatten(),
+        });
+    }
+
+    // Symbols declared from now on belong to the one declared by `name`,
+    // until `exit`.
+    pub fn enter(&mut self, name: &Token) {
+        self.containers
+            .push(self.by_start.get(&name.start).copied());
+    }
+
+    pub fn exit(&mut self) {
+        self.containers.pop();
+    }
+
+    // `declaration` is the name of the local it resolved to, `None` for
+    // globals which are resolved once the whole file is known.
+    pub fn reference(&mut self, name: &Token, declaration: Option<&Token>) {
+        self.references.push(Reference {
+            name: name.lexeme.clone(),
+            line: name.line,
+            column: name.column,
+            span: name.start..name.end,
+            symbol: declaration.and_then(|token| self.by_start.get(&token.start).copied()),
+        });
+    }
+
+    pub fn finish(mut self, errors: Vec<CompileError>) -> Analysis {
+        let mut globals = HashMap::new();
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            if symbol.global {
+                globals.entry(symbol.name.as_str()).or_insert(i);
+            }
+        }
+
+        for reference in &mut self.references {
+            if reference.symbol.is_none() {
+                reference.symbol = globals.get(reference.name.as_str()).copied();
+            }
+        }
+
+        Analysis {
+            symbols: self.symbols,
+            references: self.references,
+            errors,
+        }
+    }
+}
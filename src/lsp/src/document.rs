@@ -0,0 +1,269 @@
+use std::{collections::HashMap, ops::Range};
+
+use compiler::{
+    scanner::{Scanner, TokenType},
+    Analysis, Symbol, SymbolKind,
+};
+use serde_json::{json, Value};
+
+/// Semantic token types, indexed by the numbers sent to the client.
+pub const TOKEN_TYPES: [&str; 10] = [
+    "keyword",
+    "string",
+    "number",
+    "operator",
+    "variable",
+    "parameter",
+    "function",
+    "class",
+    "method",
+    "property",
+];
+
+pub const TOKEN_MODIFIERS: [&str; 1] = ["declaration"];
+
+/// An open `.lox` file and what the compiler found in it.
+pub struct Document {
+    text: String,
+    // Byte offset of the start of each line.
+    line_starts: Vec<usize>,
+    analysis: Analysis,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let analysis = compiler::analyze(&text);
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Document {
+            text,
+            line_starts,
+            analysis,
+        }
+    }
+
+    pub fn diagnostics(&self) -> Value {
+        let diagnostics: Vec<_> = self
+            .analysis
+            .errors
+            .iter()
+            .map(|error| {
+                json!({
+                    "range": self.range(&error.span),
+                    "severity": 1,
+                    "source": "lox",
+                    "message": error.message,
+                })
+            })
+            .collect();
+        json!(diagnostics)
+    }
+
+    pub fn definition(&self, uri: &str, position: &Value) -> Value {
+        match self.symbol_at(position) {
+            Some((symbol, _)) => json!({
+                "uri": uri,
+                "range": self.range(&symbol.span),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    pub fn hover(&self, position: &Value) -> Value {
+        match self.symbol_at(position) {
+            Some((symbol, hovered)) => json!({
+                "contents": {
+                    "kind": "plaintext",
+                    "value": format!("{} {}", symbol.description(), symbol.name),
+                },
+                "range": hovered,
+            }),
+            None => Value::Null,
+        }
+    }
+
+    // Functions, classes, methods and globals, locals would only be noise.
+    pub fn symbols(&self, uri: &str) -> Value {
+        let symbols: Vec<_> = self
+            .analysis
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Class => 5,
+                    SymbolKind::Method => 6,
+                    SymbolKind::Function => 12,
+                    SymbolKind::Variable if symbol.global => 13,
+                    SymbolKind::Variable | SymbolKind::Parameter => return None,
+                };
+                let mut information = json!({
+                    "name": symbol.name,
+                    "kind": kind,
+                    "location": {
+                        "uri": uri,
+                        "range": self.range(&symbol.span),
+                    },
+                });
+                if let Some(container) = symbol.container {
+                    information["containerName"] = json!(self.analysis.symbols[container].name);
+                }
+                Some(information)
+            })
+            .collect();
+        json!(symbols)
+    }
+
+    // Encoded as the protocol wants: five numbers per token, positions
+    // relative to the previous token.
+    pub fn semantic_tokens(&self) -> Value {
+        let declarations: HashMap<usize, &Symbol> = self
+            .analysis
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.span.start, symbol))
+            .collect();
+        let references: HashMap<usize, Option<usize>> = self
+            .analysis
+            .references
+            .iter()
+            .map(|reference| (reference.span.start, reference.symbol))
+            .collect();
+
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for token in Scanner::new(&self.text) {
+            if token.token_type == TokenType::TokenEof {
+                break;
+            }
+            // The protocol can't express tokens spanning lines.
+            if token.lexeme.contains('\n') {
+                continue;
+            }
+
+            let (token_type, modifiers) = match token.token_type {
+                TokenType::TokenIdentifier => match declarations.get(&token.start) {
+                    Some(symbol) => (symbol_type(symbol.kind), 1),
+                    None => match references.get(&token.start) {
+                        Some(Some(symbol)) => (symbol_type(self.analysis.symbols[*symbol].kind), 0),
+                        Some(None) => (4, 0),
+                        None => (9, 0),
+                    },
+                },
+                TokenType::TokenString => (1, 0),
+                TokenType::TokenNumber => (2, 0),
+                TokenType::TokenMinus
+                | TokenType::TokenPlus
+                | TokenType::TokenSlash
+                | TokenType::TokenStar
+                | TokenType::TokenBang
+                | TokenType::TokenBangEqual
+                | TokenType::TokenEqual
+                | TokenType::TokenEqualEqual
+                | TokenType::TokenGreater
+                | TokenType::TokenGreaterEqual
+                | TokenType::TokenLess
+                | TokenType::TokenLessEqual => (3, 0),
+                TokenType::TokenAnd
+                | TokenType::TokenClass
+                | TokenType::TokenElse
+                | TokenType::TokenFalse
+                | TokenType::TokenFor
+                | TokenType::TokenFun
+                | TokenType::TokenIf
+                | TokenType::TokenNil
+                | TokenType::TokenOr
+                | TokenType::TokenPrint
+                | TokenType::TokenReturn
+                | TokenType::TokenSuper
+                | TokenType::TokenThis
+                | TokenType::TokenTrue
+                | TokenType::TokenVar
+                | TokenType::TokenWhile => (0, 0),
+                _ => continue,
+            };
+
+            let (line, start) = self.position(token.start);
+            let delta_start = if line == previous_line {
+                start - previous_start
+            } else {
+                start
+            };
+            data.extend([
+                line - previous_line,
+                delta_start,
+                token.lexeme.encode_utf16().count() as i32,
+                token_type,
+                modifiers,
+            ]);
+            previous_line = line;
+            previous_start = start;
+        }
+        json!({ "data": data })
+    }
+
+    // The symbol named at `position`, by a declaration or a reference, and
+    // the range of that name.
+    fn symbol_at(&self, position: &Value) -> Option<(&Symbol, Value)> {
+        let offset = self.offset(position)?;
+        let contains = |span: &Range<usize>| span.start <= offset && offset <= span.end;
+
+        let symbols = &self.analysis.symbols;
+        if let Some(symbol) = symbols.iter().find(|symbol| contains(&symbol.span)) {
+            return Some((symbol, self.range(&symbol.span)));
+        }
+
+        let reference = self
+            .analysis
+            .references
+            .iter()
+            .find(|reference| contains(&reference.span))?;
+        let hovered = self.range(&reference.span);
+        Some((&symbols[reference.symbol?], hovered))
+    }
+
+    // The compiler counts bytes, the protocol counts UTF-16 code units from
+    // the start of the line, both from 0.
+    fn position(&self, offset: usize) -> (i32, i32) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.line_starts[line]..offset]
+            .encode_utf16()
+            .count();
+        (line as i32, character as i32)
+    }
+
+    fn range(&self, span: &Range<usize>) -> Value {
+        let (start_line, start) = self.position(span.start);
+        let (end_line, end) = self.position(span.end);
+        json!({
+            "start": { "line": start_line, "character": start },
+            "end": { "line": end_line, "character": end },
+        })
+    }
+
+    // The byte offset of a protocol position, clamped to the end of its line.
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let start = *self.line_starts.get(line)?;
+
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.text.len())
+    }
+}
+
+fn symbol_type(kind: SymbolKind) -> i32 {
+    match kind {
+        SymbolKind::Variable => 4,
+        SymbolKind::Parameter => 5,
+        SymbolKind::Function => 6,
+        SymbolKind::Class => 7,
+        SymbolKind::Method => 8,
+    }
+}
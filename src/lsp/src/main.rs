@@ -0,0 +1,13 @@
+use std::{io, process};
+
+mod document;
+mod server;
+
+// Editors start the server and talk to it over stdin and stdout.
+fn main() {
+    let input = Box::new(io::stdin().lock());
+    if let Err(error) = server::serve(input, Box::new(io::stdout())) {
+        eprintln!("Language server failed: {}", error);
+        process::exit(74);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::document::{Document, TOKEN_MODIFIERS, TOKEN_TYPES};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+/// Serves the Language Server Protocol, reading JSON-RPC messages from
+/// `input` and writing responses and notifications to `output`, until the
+/// client sends `exit` or closes the input.
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> io::Result<()> {
+    let mut server = Server {
+        input,
+        output,
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+
+    while let Some(message) = server.read()? {
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+
+        // Requests have an id and get a response, notifications don't.
+        match message.get("id") {
+            Some(id) => {
+                let result = if server.shut_down {
+                    Err((INVALID_REQUEST, "The server was shut down.".to_string()))
+                } else {
+                    server.request(method, &message["params"])
+                };
+                server.respond(id, result)?;
+            }
+            None => server.notification(method, &message["params"])?,
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    // Open documents by URI.
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if method == "initialize" {
+            return Ok(capabilities());
+        }
+        if method == "shutdown" {
+            self.shut_down = true;
+            return Ok(Value::Null);
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self.documents.get(uri);
+        let result = match method {
+            "textDocument/definition" => document.map(|d| d.definition(uri, &params["position"])),
+            "textDocument/hover" => document.map(|d| d.hover(&params["position"])),
+            "textDocument/documentSymbol" => document.map(|d| d.symbols(uri)),
+            "textDocument/semanticTokens/full" => document.map(Document::semantic_tokens),
+            _ => {
+                return Err((
+                    METHOD_NOT_FOUND,
+                    format!("Unsupported method '{}'.", method),
+                ))
+            }
+        };
+        Ok(result.unwrap_or(Value::Null))
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        // Documents are synced in full, the last change holds the new text.
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri, json!([]))?;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        if let Some(text) = text {
+            let document = Document::new(text.to_string());
+            let diagnostics = document.diagnostics();
+            self.documents.insert(uri.to_string(), document);
+            self.publish_diagnostics(uri, diagnostics)?;
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Value) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn respond(&mut self, id: &Value, result: Result<Value, (i64, String)>) -> io::Result<()> {
+        let mut response = json!({ "jsonrpc": "2.0", "id": id });
+        match result {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({ "code": code, "message": message }),
+        }
+        self.send(response)
+    }
+
+    // Reads the next message, `None` at the end of the input.
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut length = None;
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if self.input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+
+        let length = length.ok_or_else(|| invalid_data("Missing Content-Length header."))?;
+        let mut content = vec![0; length];
+        self.input.read_exact(&mut content)?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|error| invalid_data(&error.to_string()))
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.output.flush()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // Full text on every change.
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS },
+                "full": true,
+            },
+        },
+        "serverInfo": { "name": "lox-lsp" },
+    })
+}
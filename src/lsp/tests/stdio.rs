@@ -0,0 +1,255 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+const URI: &str = "file:///closures.lox";
+const SOURCE: &str = "fun makeCounter() {\n  var count = 0;\n  fun counter() {\n    count = count + 1;\n    return count;\n  }\n  return counter;\n}\nvar c = makeCounter();\nprint c();";
+
+// Starts the server, sends it `messages` framed as a client would and
+// returns everything it answered once it exited.
+fn session(messages: &[Value]) -> Vec<Value> {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = server.stdin.take().unwrap();
+    for message in messages {
+        let content = message.to_string();
+        write!(
+            stdin,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )
+        .unwrap();
+    }
+    drop(stdin);
+
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut replies = Vec::new();
+    let mut header = String::new();
+    while stdout.read_line(&mut header).unwrap() > 0 {
+        let length: usize = header
+            .trim()
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        stdout.read_line(&mut header).unwrap();
+        let mut content = vec![0; length];
+        stdout.read_exact(&mut content).unwrap();
+        replies.push(serde_json::from_slice(&content).unwrap());
+        header.clear();
+    }
+
+    assert!(server.wait().unwrap().success());
+    replies
+}
+
+fn request(id: i64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn at(line: i64, character: i64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+fn response(replies: &[Value], id: i64) -> &Value {
+    let reply = replies.iter().find(|reply| reply["id"] == id).unwrap();
+    &reply["result"]
+}
+
+#[test]
+fn publishes_diagnostics_as_documents_change() {
+    let replies = session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("initialized", json!({})),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": "print 1;\nprint ;" } }),
+        ),
+        notification(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": "print 1;" }] }),
+        ),
+        request(2, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    let capabilities = &response(&replies, 1)["capabilities"];
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(
+        capabilities["semanticTokensProvider"]["legend"]["tokenTypes"][0],
+        "keyword"
+    );
+
+    let diagnostics: Vec<_> = replies
+        .iter()
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .map(|reply| &reply["params"]["diagnostics"])
+        .collect();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0][0]["message"], "Expect expression.");
+    assert_eq!(
+        diagnostics[0][0]["range"],
+        json!({ "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 7 } })
+    );
+    assert_eq!(diagnostics[1], &json!([]));
+}
+
+#[test]
+fn navigates_symbols_of_an_open_document() {
+    let replies = session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": SOURCE } }),
+        ),
+        // `count` inside counter, captured from makeCounter.
+        request(2, "textDocument/definition", at(3, 12)),
+        request(3, "textDocument/hover", at(3, 12)),
+        request(4, "textDocument/hover", at(8, 10)),
+        request(
+            5,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": URI } }),
+        ),
+        request(
+            6,
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": { "uri": URI } }),
+        ),
+        request(7, "textDocument/rename", at(3, 12)),
+        request(8, "shutdown", Value::Null),
+        request(9, "textDocument/hover", at(3, 12)),
+        notification("exit", Value::Null),
+    ]);
+
+    assert_eq!(
+        response(&replies, 2),
+        &json!({
+            "uri": URI,
+            "range": { "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 11 } },
+        })
+    );
+    assert_eq!(
+        response(&replies, 3)["contents"]["value"],
+        "local variable count"
+    );
+    assert_eq!(
+        response(&replies, 4)["contents"]["value"],
+        "global function makeCounter"
+    );
+
+    let symbols: Vec<_> = response(&replies, 5)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| {
+            (
+                symbol["name"].as_str().unwrap(),
+                symbol["kind"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        symbols,
+        vec![("makeCounter", 12), ("counter", 12), ("c", 13)]
+    );
+
+    // `fun` is a keyword at the start, `makeCounter` a function declaration
+    // right after it.
+    let data = response(&replies, 6)["data"].as_array().unwrap();
+    assert_eq!(
+        Value::from(data[..10].to_vec()),
+        json!([0, 0, 3, 0, 0, 0, 4, 11, 6, 1])
+    );
+
+    let errors: Vec<_> = replies
+        .iter()
+        .filter(|reply| reply["id"] == 7 || reply["id"] == 9)
+        .map(|reply| reply["error"]["code"].as_i64().unwrap())
+        .collect();
+    assert_eq!(errors, vec![-32601, -32600]);
+}
+
+#[test]
+fn counts_positions_in_utf16_code_units() {
+    let text = "var s = \"😀\"; print s;\nprint \"héllo😀\" +;";
+    let replies = session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": text } }),
+        ),
+        request(2, "textDocument/hover", at(0, 20)),
+        request(3, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    assert_eq!(
+        response(&replies, 2),
+        &json!({
+            "contents": { "kind": "plaintext", "value": "global variable s" },
+            "range": { "start": { "line": 0, "character": 20 }, "end": { "line": 0, "character": 21 } },
+        })
+    );
+
+    let diagnostics = replies
+        .iter()
+        .find(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .map(|reply| &reply["params"]["diagnostics"])
+        .unwrap();
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({ "start": { "line": 1, "character": 17 }, "end": { "line": 1, "character": 18 } })
+    );
+}
+
+#[test]
+fn reports_tokens_that_cant_continue_an_expression() {
+    let replies = session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": "print 1 = 2;" } }),
+        ),
+        notification(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": "var x = 1\nwhile (x) x = nil;" }] }),
+        ),
+        notification(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": URI, "version": 3 }, "contentChanges": [{ "text": "var x = 1\nfor (;;) {}" }] }),
+        ),
+        request(2, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    let messages: Vec<_> = replies
+        .iter()
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .map(|reply| {
+            reply["params"]["diagnostics"][0]["message"]
+                .as_str()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Invalid assignment target.",
+            "Expect ';' after variable declaration.",
+            "Expect ';' after variable declaration."
+        ]
+    );
+}
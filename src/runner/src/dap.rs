@@ -0,0 +1,658 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use common::{chunk::Chunk, object::Function};
+use serde_json::{json, Value};
+use vm::{DebugHandler, Debugger, PauseReason, Resume, VM};
+
+use crate::debugger::evaluate;
+
+// Programs are single threaded, this is the id of the only thread.
+const THREAD_ID: i64 = 1;
+
+// Each frame has three scopes, numbered from 1 since 0 means "no variables".
+const LOCALS: i64 = 0;
+const CLOSURE: i64 = 1;
+const GLOBALS: i64 = 2;
+
+/// Serves one debug session of the Debug Adapter Protocol, reading requests
+/// from `input` and writing responses and events to `output`.
+///
+/// The program starts once it has been launched and the client sent
+/// `configurationDone`. Output from `print` and runtime errors is sent as
+/// `output` events.
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> io::Result<()> {
+    let connection = Rc::new(RefCell::new(Connection {
+        input,
+        output,
+        seq: 0,
+        closed: false,
+    }));
+
+    let mut program = None;
+    let mut breakpoints = Vec::new();
+    let mut configured = false;
+    while program.is_none() || !configured {
+        let request = match connection.borrow_mut().read()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        let mut connection = connection.borrow_mut();
+        let result = match command(&request) {
+            "initialize" => {
+                connection.respond(&request, Ok(capabilities()))?;
+                connection.event("initialized", Value::Null)?;
+                continue;
+            }
+            "launch" => Program::load(&request["arguments"]).map(|launched| {
+                program = Some(launched);
+                Value::Null
+            }),
+            "setBreakpoints" => {
+                breakpoints = breakpoint_lines(&request);
+                Ok(breakpoints_body(&breakpoints, program.as_ref()))
+            }
+            "configurationDone" => {
+                configured = true;
+                Ok(Value::Null)
+            }
+            "threads" => Ok(threads()),
+            "disconnect" => {
+                connection.respond(&request, Ok(Value::Null))?;
+                return Ok(());
+            }
+            command => Err(unsupported(command)),
+        };
+        connection.respond(&request, result)?;
+    }
+
+    let program = program.unwrap();
+    let function = program.function.clone();
+    let mut debugger = Debugger::new(Box::new(Adapter {
+        connection: connection.clone(),
+        program,
+    }));
+    for line in breakpoints {
+        debugger.set_breakpoint(line);
+    }
+
+    let mut vm = VM::new();
+    vm.set_output(Box::new(OutputEvents::new(connection.clone(), "stdout")));
+    vm.set_error_output(Box::new(OutputEvents::new(connection.clone(), "stderr")));
+    vm.set_debugger(Some(debugger));
+    let exit_code = if vm.interpret(function).is_ok() {
+        0
+    } else {
+        70
+    };
+    // Sends any unfinished output line.
+    drop(vm);
+
+    let mut connection = connection.borrow_mut();
+    connection.event("terminated", Value::Null)?;
+    connection.event("exited", json!({ "exitCode": exit_code }))?;
+    while !connection.closed {
+        let request = match connection.read()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let result = match command(&request) {
+            "disconnect" => Ok(Value::Null),
+            "threads" => Ok(json!({ "threads": [] })),
+            _ => Err("The program has ended.".to_string()),
+        };
+        connection.respond(&request, result)?;
+        if command(&request) == "disconnect" {
+            connection.closed = true;
+        }
+    }
+    Ok(())
+}
+
+struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: i64,
+    // Set once the client disconnected, nothing is sent after that.
+    closed: bool,
+}
+
+impl Connection {
+    // Reads the next message, `None` at the end of the input.
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut length = None;
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if self.input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+
+        let length = length.ok_or_else(|| invalid_data("Missing Content-Length header."))?;
+        let mut content = vec![0; length];
+        self.input.read_exact(&mut content)?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|error| invalid_data(&error.to_string()))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+fn unsupported(command: &str) -> String {
+    format!("Unsupported request '{}'.", command)
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsEvaluateForHovers": true,
+    })
+}
+
+fn threads() -> Value {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
+
+fn breakpoint_lines(request: &Value) -> Vec<i32> {
+    request["arguments"]["breakpoints"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|breakpoint| breakpoint["line"].as_i64())
+        .map(|line| line as i32)
+        .collect()
+}
+
+// Breakpoints are verified once the program is known, on lines that have
+// code.
+fn breakpoints_body(lines: &[i32], program: Option<&Program>) -> Value {
+    let breakpoints: Vec<_> = lines
+        .iter()
+        .map(|line| {
+            let verified = program.is_none_or(|program| program.lines.contains(line));
+            json!({ "verified": verified, "line": line })
+        })
+        .collect();
+    json!({ "breakpoints": breakpoints })
+}
+
+// The launched program.
+struct Program {
+    path: String,
+    function: Function,
+    // Lines with code in any of the program's functions.
+    lines: HashSet<i32>,
+}
+
+impl Program {
+    fn load(arguments: &Value) -> Result<Program, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("Missing 'program' to launch.")?;
+
+        let function = if Path::new(path).extension().is_some_and(|e| e == "loxc") {
+            let bytes = fs::read(path).map_err(|error| error.to_string())?;
+            let chunk = Chunk::deserialize(&bytes).map_err(|error| error.to_string())?;
+            let function = Function {
+                chunk,
+                ..Function::new()
+            };
+            vm::verify(&function).map_err(|error| error.to_string())?;
+            function
+        } else {
+            let source = fs::read_to_string(path).map_err(|error| error.to_string())?;
+            compiler::compile(&source).map_err(|errors| {
+                let errors: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+                errors.join("\n")
+            })?
+        };
+
+        let mut lines = HashSet::new();
+        collect_lines(&function, &mut lines);
+        Ok(Program {
+            path: path.to_string(),
+            function,
+            lines,
+        })
+    }
+}
+
+fn collect_lines(function: &Function, lines: &mut HashSet<i32>) {
+    lines.extend(&function.chunk.lines);
+    for constant in &function.chunk.constants.values {
+        if constant.is_function() {
+            collect_lines(constant.as_function(), lines);
+        }
+    }
+}
+
+// Handles requests while the program is paused.
+struct Adapter {
+    connection: Rc<RefCell<Connection>>,
+    program: Program,
+}
+
+impl DebugHandler for Adapter {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+        let reason = match reason {
+            PauseReason::Entry => "entry",
+            PauseReason::Breakpoint => "breakpoint",
+            PauseReason::Step => "step",
+        };
+        let stopped = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if self
+            .connection
+            .borrow_mut()
+            .event("stopped", stopped)
+            .is_err()
+        {
+            return Resume::Stop;
+        }
+
+        loop {
+            let request = match self.connection.borrow_mut().read() {
+                Ok(Some(request)) => request,
+                _ => return Resume::Stop,
+            };
+
+            // The connection isn't borrowed here, evaluating may print.
+            let (result, resume) = self.handle(vm, &request);
+            let mut connection = self.connection.borrow_mut();
+            if connection.respond(&request, result).is_err() {
+                return Resume::Stop;
+            }
+            if command(&request) == "disconnect" {
+                connection.closed = true;
+            }
+            if let Some(resume) = resume {
+                return resume;
+            }
+        }
+    }
+}
+
+impl Adapter {
+    fn handle(&mut self, vm: &mut VM, request: &Value) -> (Result<Value, String>, Option<Resume>) {
+        let arguments = &request["arguments"];
+        let result = match command(request) {
+            "continue" => {
+                let body = json!({ "allThreadsContinued": true });
+                return (Ok(body), Some(Resume::Continue));
+            }
+            "next" => return (Ok(Value::Null), Some(Resume::StepOver)),
+            "stepIn" => return (Ok(Value::Null), Some(Resume::StepLine)),
+            "stepOut" => return (Ok(Value::Null), Some(Resume::StepOut)),
+            "disconnect" => return (Ok(Value::Null), Some(Resume::Stop)),
+            "threads" => Ok(threads()),
+            "setBreakpoints" => {
+                let lines = breakpoint_lines(request);
+                let debugger = vm.debugger_mut().unwrap();
+                debugger.clear_breakpoints();
+                for line in &lines {
+                    if self.program.lines.contains(line) {
+                        debugger.set_breakpoint(*line);
+                    }
+                }
+                Ok(breakpoints_body(&lines, Some(&self.program)))
+            }
+            "stackTrace" => Ok(self.stack_trace(vm, arguments)),
+            "scopes" => Ok(scopes(arguments)),
+            "variables" => Ok(variables(vm, arguments)),
+            "evaluate" => {
+                let frame = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+                let expression = arguments["expression"].as_str().unwrap_or_default();
+                evaluate(vm, frame, expression).map(
+                    |value| json!({ "result": format!("{:?}", value), "variablesReference": 0 }),
+                )
+            }
+            command => Err(unsupported(command)),
+        };
+        (result, None)
+    }
+
+    fn stack_trace(&self, vm: &VM, arguments: &Value) -> Value {
+        let call_stack = vm.call_stack();
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => call_stack.len(),
+        };
+
+        let name = Path::new(&self.program.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let frames: Vec<_> = call_stack
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, frame)| {
+                json!({
+                    "id": id,
+                    "name": frame.function.as_deref().unwrap_or("script"),
+                    "source": { "name": name, "path": self.program.path },
+                    "line": frame.line,
+                    "column": 1,
+                })
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": call_stack.len() })
+    }
+}
+
+fn scopes(arguments: &Value) -> Value {
+    let frame = arguments["frameId"].as_i64().unwrap_or(0);
+    let scope = |name: &str, kind: i64, expensive: bool| {
+        json!({
+            "name": name,
+            "variablesReference": frame * 3 + kind + 1,
+            "expensive": expensive,
+        })
+    };
+    json!({
+        "scopes": [
+            scope("Locals", LOCALS, false),
+            scope("Closure", CLOSURE, false),
+            scope("Globals", GLOBALS, true),
+        ]
+    })
+}
+
+fn variables(vm: &VM, arguments: &Value) -> Value {
+    let reference = arguments["variablesReference"].as_i64().unwrap_or(0) - 1;
+    let frame = (reference / 3) as usize;
+
+    let mut variables = match reference % 3 {
+        LOCALS => vm.locals(frame),
+        CLOSURE => vm.upvalues(frame),
+        _ => vm.globals(),
+    };
+    if reference < 0 {
+        variables.clear();
+    }
+
+    let variables: Vec<_> = variables
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name,
+                "value": format!("{:?}", value),
+                "type": value.type_name(),
+                "variablesReference": 0,
+            })
+        })
+        .collect();
+    json!({ "variables": variables })
+}
+
+// Forwards program output to the client, a line at a time.
+struct OutputEvents {
+    connection: Rc<RefCell<Connection>>,
+    category: &'static str,
+    line: Vec<u8>,
+}
+
+impl OutputEvents {
+    fn new(connection: Rc<RefCell<Connection>>, category: &'static str) -> Self {
+        OutputEvents {
+            connection,
+            category,
+            line: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let output = String::from_utf8_lossy(bytes);
+        let body = json!({ "category": self.category, "output": output });
+        self.connection.borrow_mut().event("output", body)
+    }
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            self.send(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.send(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputEvents {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Collects everything the adapter writes.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Frames `requests` as a client would send them and returns the messages
+    // the adapter answered with.
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let request = json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            write!(
+                input,
+                "Content-Length: {}\r\n\r\n{}",
+                request.len(),
+                request
+            )
+            .unwrap();
+        }
+
+        let output = SharedBuffer::default();
+        serve(Box::new(Cursor::new(input)), Box::new(output.clone())).unwrap();
+
+        let mut reader = Connection {
+            input: Box::new(Cursor::new(output.0.borrow().clone())),
+            output: Box::new(io::sink()),
+            seq: 0,
+            closed: false,
+        };
+        let mut messages = Vec::new();
+        while let Some(message) = reader.read().unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    // The first message whose `key` is `name`.
+    fn find<'a>(messages: &'a [Value], key: &str, name: &str) -> &'a Value {
+        messages
+            .iter()
+            .find(|message| message[key] == name)
+            .unwrap_or_else(|| panic!("No message with {} '{}'", key, name))
+    }
+
+    #[test]
+    fn debugs_a_program_through_breakpoints_and_steps() {
+        let path = std::env::temp_dir().join("dap_session.lox");
+        let source = "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nprint add(1, 2);";
+        fs::write(&path, source).unwrap();
+        let program = path.to_str().unwrap();
+
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "lox" })),
+            ("launch", json!({ "program": program })),
+            (
+                "setBreakpoints",
+                json!({ "breakpoints": [{ "line": 2 }, { "line": 10 }] }),
+            ),
+            ("configurationDone", Value::Null),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("scopes", json!({ "frameId": 0 })),
+            ("variables", json!({ "variablesReference": 1 })),
+            ("evaluate", json!({ "expression": "a + b", "frameId": 0 })),
+            ("next", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+            ("disconnect", Value::Null),
+        ]);
+        fs::remove_file(&path).unwrap();
+
+        let responses: Vec<_> = messages
+            .iter()
+            .filter(|message| message["type"] == "response")
+            .collect();
+        assert_eq!(responses.len(), 12);
+        assert!(responses.iter().all(|response| response["success"] == true));
+
+        let breakpoints = &responses[2]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        let frames = &responses[4]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "add");
+        assert_eq!(frames[0]["line"], 2);
+        assert_eq!(frames[1]["name"], "script");
+        assert_eq!(frames[1]["line"], 5);
+
+        let variables = &responses[6]["body"]["variables"];
+        assert_eq!(variables[0]["name"], "a");
+        assert_eq!(variables[0]["value"], "1");
+        assert_eq!(variables[1]["name"], "b");
+        assert_eq!(variables[1]["value"], "2");
+        assert_eq!(responses[7]["body"]["result"], "3");
+        assert_eq!(responses[9]["body"]["stackFrames"][0]["line"], 3);
+
+        let events: Vec<_> = messages
+            .iter()
+            .filter(|message| message["type"] == "event")
+            .map(|message| message["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "initialized",
+                "stopped",
+                "stopped",
+                "output",
+                "terminated",
+                "exited"
+            ]
+        );
+        assert_eq!(
+            find(&messages, "event", "stopped")["body"]["reason"],
+            "breakpoint"
+        );
+        assert_eq!(find(&messages, "event", "output")["body"]["output"], "3\n");
+        assert_eq!(find(&messages, "event", "exited")["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn reports_programs_that_fail_to_launch() {
+        let messages = session(&[
+            ("launch", json!({ "program": "missing.lox" })),
+            ("launch", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("disconnect", Value::Null),
+        ]);
+
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[1]["message"], "Missing 'program' to launch.");
+        assert_eq!(messages[2]["message"], "Unsupported request 'stackTrace'.");
+        assert_eq!(messages[3]["success"], true);
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{self, Write};
+
+use common::gc::Handle;
+use vm::{debug, DebugHandler, PauseReason, Resume, VM};
+
+const HELP: &str = "\
+Commands:
+  c, continue        run to the next breakpoint
+  s, step            step to the next line, entering calls
+  n, next            step to the next line, over calls
+  o, out             step out of the current function
+  si, stepi          step one instruction
+  b, break [LINE]    set a breakpoint, or list them
+  d, delete LINE     remove a breakpoint
+  bt, backtrace      show the call stack
+  f, frame N         select frame N of the backtrace
+  locals             show the selected frame's locals and upvalues
+  globals            show all globals
+  stack              show the value stack
+  p, print EXPR      evaluate EXPR in the selected frame
+  l, list            show the source around the current line
+  q, quit            stop the program";
+
+/// Evaluates `expression` while the VM is paused. Locals and upvalues of
+/// `frame`, counted like `VM::call_stack`, can be read but assignments to
+/// them are lost. In a method, `this` is its receiver.
+pub fn evaluate(vm: &mut VM, frame: usize, expression: &str) -> Result<Handle, String> {
+    // Passed in as parameters, innermost declaration wins.
+    let mut names: Vec<String> = Vec::new();
+    let mut args = Vec::new();
+    let mut receiver = None;
+    for (name, value) in vm.upvalues(frame).into_iter().chain(vm.locals(frame)) {
+        if name == "this" {
+            receiver = Some(value);
+            continue;
+        }
+        if name == "super" {
+            continue;
+        }
+        match names.iter().position(|n| *n == name) {
+            Some(i) => args[i] = value,
+            None => {
+                names.push(name);
+                args.push(value);
+            }
+        }
+    }
+
+    let expression = expression.trim().trim_end_matches(';');
+    let eval = format!("eval({}) {{ return {}; }}", names.join(", "), expression);
+    // A method's receiver is in slot 0, where `this` is compiled to look.
+    let source = match receiver {
+        Some(_) => format!("class Eval {{ {} }}", eval),
+        None => format!("fun {}", eval),
+    };
+    let script = compiler::compile(&source).map_err(|errors| errors[0].message.clone())?;
+    let function = script
+        .chunk
+        .constants
+        .values
+        .iter()
+        .find(|constant| constant.is_function())
+        .unwrap()
+        .as_function()
+        .clone();
+
+    match receiver {
+        Some(receiver) => vm.call_method(function, &receiver, &args),
+        None => vm.call_function(function, &args),
+    }
+    .map_err(|error| error.message)
+}
+
+/// A command line debugger reading from stdin.
+pub struct Console {
+    // Empty when debugging bytecode.
+    source: Vec<String>,
+    frame: usize,
+    last_resume: Resume,
+}
+
+impl Console {
+    pub fn new(source: &str) -> Self {
+        Console {
+            source: source.lines().map(str::to_string).collect(),
+            frame: 0,
+            last_resume: Resume::Continue,
+        }
+    }
+
+    fn show_location(&self, vm: &VM, reason: PauseReason) {
+        let top = &vm.call_stack()[0];
+        let function = match &top.function {
+            Some(name) => format!("{}()", name),
+            None => "script".to_string(),
+        };
+        let reason = match reason {
+            PauseReason::Entry => "on entry",
+            PauseReason::Breakpoint => "at breakpoint",
+            PauseReason::Step => "after step",
+        };
+        println!("Paused {}, line {} in {}", reason, top.line, function);
+
+        if self.last_resume == Resume::StepInstruction || self.source.is_empty() {
+            let chunk = vm.chunk(0).unwrap();
+            println!("  {}", debug::disassemble_instruction(chunk, top.offset));
+        } else {
+            self.list(top.line, 0);
+        }
+    }
+
+    // Prints the source lines within `context` lines of `line`.
+    fn list(&self, line: i32, context: i32) {
+        let first = (line - context).max(1);
+        for number in first..=line + context {
+            if let Some(text) = self.source.get(number as usize - 1) {
+                let marker = if number == line { ">" } else { " " };
+                println!("{} {:4} | {}", marker, number, text);
+            }
+        }
+    }
+
+    // Runs one command. Returns how to resume if the command does so.
+    fn command(&mut self, vm: &mut VM, line: &str) -> Option<Resume> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "" => {}
+            "c" | "continue" => return Some(Resume::Continue),
+            "s" | "step" => return Some(Resume::StepLine),
+            "n" | "next" => return Some(Resume::StepOver),
+            "o" | "out" => return Some(Resume::StepOut),
+            "si" | "stepi" => return Some(Resume::StepInstruction),
+            "q" | "quit" => return Some(Resume::Stop),
+            "b" | "break" if argument.is_empty() => {
+                let breakpoints = vm.debugger_mut().unwrap().breakpoints();
+                if breakpoints.is_empty() {
+                    println!("No breakpoints.");
+                }
+                for line in breakpoints {
+                    println!("Breakpoint at line {}", line);
+                }
+            }
+            "b" | "break" => match argument.parse() {
+                Ok(line) => vm.debugger_mut().unwrap().set_breakpoint(line),
+                Err(_) => println!("Expected a line number."),
+            },
+            "d" | "delete" => match argument.parse() {
+                Ok(line) => {
+                    if !vm.debugger_mut().unwrap().clear_breakpoint(line) {
+                        println!("No breakpoint at line {}.", line);
+                    }
+                }
+                Err(_) => println!("Expected a line number."),
+            },
+            "bt" | "backtrace" => {
+                for (i, frame) in vm.call_stack().iter().enumerate() {
+                    let marker = if i == self.frame { ">" } else { " " };
+                    match &frame.function {
+                        Some(name) => println!("{} #{} {}() line {}", marker, i, name, frame.line),
+                        None => println!("{} #{} script line {}", marker, i, frame.line),
+                    }
+                }
+            }
+            "f" | "frame" => match argument.parse() {
+                Ok(frame) if frame < vm.call_stack().len() => {
+                    self.frame = frame;
+                    self.list(vm.call_stack()[frame].line, 0);
+                }
+                _ => println!("Expected a frame number below {}.", vm.call_stack().len()),
+            },
+            "locals" => {
+                for (name, value) in vm.locals(self.frame) {
+                    println!("{} = {:?}", name, value);
+                }
+                for (name, value) in vm.upvalues(self.frame) {
+                    println!("{} = {:?} (captured)", name, value);
+                }
+            }
+            "globals" => {
+                for (name, value) in vm.globals() {
+                    println!("{} = {:?}", name, value);
+                }
+            }
+            "stack" => {
+                let _ = vm.write_stack(&mut io::stdout());
+            }
+            "p" | "print" => match evaluate(vm, self.frame, argument) {
+                Ok(value) => println!("{:?}", value),
+                Err(message) => println!("Error: {}", message),
+            },
+            "l" | "list" => self.list(vm.call_stack()[self.frame].line, 5),
+            "h" | "help" => println!("{}", HELP),
+            _ => println!("Unknown command '{}', try 'help'.", command),
+        }
+        None
+    }
+}
+
+impl DebugHandler for Console {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+        self.frame = 0;
+        self.show_location(vm, reason);
+
+        let mut line = String::new();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().unwrap();
+
+            line.clear();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                println!();
+                return Resume::Stop;
+            }
+
+            if let Some(resume) = self.command(vm, &line) {
+                self.last_resume = resume;
+                return resume;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+    use vm::Debugger;
+
+    #[test]
+    fn evaluates_globals_without_a_program() {
+        let mut vm = VM::new();
+        vm.set_global("x", 20);
+
+        let value = evaluate(&mut vm, 0, "x * 2 + 2;").unwrap();
+        assert_eq!(i64::try_from(value), Ok(42));
+        assert_eq!(
+            evaluate(&mut vm, 0, "1 +").unwrap_err(),
+            "Expect expression."
+        );
+        assert_eq!(
+            evaluate(&mut vm, 0, "y").unwrap_err(),
+            "Undefined variable 'y'."
+        );
+    }
+
+    // Evaluates each expression in the frame next to it.
+    struct Evaluate(&'static [(usize, &'static str)], Rc<RefCell<Vec<String>>>);
+
+    const EXPRESSIONS: &[(usize, &str)] = &[(0, "a + b"), (0, "this"), (1, "a")];
+
+    impl DebugHandler for Evaluate {
+        fn paused(&mut self, vm: &mut VM, _reason: PauseReason) -> Resume {
+            for &(frame, expression) in self.0 {
+                let result = match evaluate(vm, frame, expression) {
+                    Ok(value) => format!("{:?}", value),
+                    Err(message) => message,
+                };
+                self.1.borrow_mut().push(result);
+            }
+            Resume::Continue
+        }
+    }
+
+    #[test]
+    fn evaluates_locals_of_the_selected_frame() {
+        let source =
+            "fun f(a) {\n  var b = 2;\n  return a + b;\n}\n{\n  var a = \"outer\";\n  f(1);\n}";
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let mut debugger = Debugger::new(Box::new(Evaluate(EXPRESSIONS, results.clone())));
+        debugger.set_breakpoint(3);
+
+        let mut vm = VM::new();
+        vm.set_debugger(Some(debugger));
+        vm.interpret(compiler::compile(source).unwrap()).unwrap();
+
+        assert_eq!(
+            *results.borrow(),
+            vec!["3", "Can't use 'this' outside of a class.", "outer"]
+        );
+    }
+
+    #[test]
+    fn evaluates_this_in_methods() {
+        let source =
+            "class P {\n  init(x) { this.x = x; }\n  get(y) {\n    return this.x + y;\n  }\n}\n\
+                      P(5).get(1);";
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let expressions = &[(0, "this.x + y"), (0, "this"), (1, "this")];
+        let mut debugger = Debugger::new(Box::new(Evaluate(expressions, results.clone())));
+        debugger.set_breakpoint(4);
+
+        let mut vm = VM::new();
+        vm.set_debugger(Some(debugger));
+        vm.interpret(compiler::compile(source).unwrap()).unwrap();
+
+        // Frame 1 is the script, which has no `this`.
+        assert_eq!(
+            *results.borrow(),
+            vec!["6", "P instance", "Can't use 'this' outside of a class."]
+        );
+    }
+
+    #[test]
+    fn evaluates_captured_variables() {
+        let source = "fun f(a) {\n  fun g() {\n    return a;\n  }\n  return g();\n}\nf(7);";
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let mut debugger = Debugger::new(Box::new(Evaluate(EXPRESSIONS, results.clone())));
+        debugger.set_breakpoint(3);
+
+        let mut vm = VM::new();
+        vm.set_debugger(Some(debugger));
+        vm.interpret(compiler::compile(source).unwrap()).unwrap();
+
+        // g captures `a` but there is no `b`, frame 1 is f with its local `a`.
+        assert_eq!(
+            *results.borrow(),
+            vec![
+                "Undefined variable 'b'.",
+                "Can't use 'this' outside of a class.",
+                "7"
+            ]
+        );
+    }
+}
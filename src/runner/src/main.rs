@@ -1,45 +1,259 @@
 use std::{
     env, fs,
     io::{self, Write},
+    ops::RangeInclusive,
+    path::Path,
     process,
 };
 
-use vm::{InterpretResult};
+use common::{chunk::Chunk, object::Function};
+use compiler::CompileOptions;
+use vm::{Debugger, VM};
+
+mod dap;
+mod debugger;
+
+const USAGE: &str = "Usage: runner [--trace[=FIRST-LAST]] [--dump-ast] [--no-fold] \
+    [[run] <file.lox|file.loxc> | compile <file.lox> [out.loxc] | \
+    disassemble [--json] <file.lox|file.loxc> | debug <file.lox|file.loxc> | dap | fmt [--check] <file.lox>... | lint <file.lox>...]";
+
+// `--trace` traces every instruction to stderr, `--trace=FIRST-LAST` only
+// those compiled from lines FIRST to LAST. `--dump-ast` prints the syntax
+// tree of a file instead of running it. `--no-fold` compiles expressions of
+// literals as written instead of evaluating them.
+#[derive(Default)]
+struct Options {
+    trace: bool,
+    trace_lines: Option<RangeInclusive<i32>>,
+    dump_ast: bool,
+    compile: CompileOptions,
+}
 
 fn main() {
     let args: Vec<_> = env::args().collect();
 
-    if args.len() == 1 {
-        repl();
-    } else if args.len() == 2 {
-        run_file(&args[1]);
+    let mut options = Options::default();
+    let mut rest = Vec::new();
+    for arg in &args[1..] {
+        if arg == "--trace" {
+            options.trace = true;
+        } else if let Some(lines) = arg.strip_prefix("--trace=") {
+            options.trace = true;
+            options.trace_lines = Some(parse_lines(lines).unwrap_or_else(|| usage()));
+        } else if arg == "--dump-ast" {
+            options.dump_ast = true;
+        } else if arg == "--no-fold" {
+            options.compile.fold_constants = false;
+        } else {
+            rest.push(arg.as_str());
+        }
+    }
+
+    match rest[..] {
+        [] => repl(&options),
+        ["compile", input] => {
+            compile_file(input, &Path::new(input).with_extension("loxc"), &options)
+        }
+        ["compile", input, output] => compile_file(input, Path::new(output), &options),
+        ["disassemble", path] => disassemble_file(path, false, &options),
+        ["disassemble", "--json", path] => disassemble_file(path, true, &options),
+        ["debug", path] => debug_file(path, &options),
+        ["dap"] => serve_dap(),
+        ["fmt", "--check", ref paths @ ..] if !paths.is_empty() => format_files(paths, true),
+        ["fmt", ref paths @ ..] if !paths.is_empty() => format_files(paths, false),
+        ["lint", ref paths @ ..] if !paths.is_empty() => lint_files(paths),
+        ["run", path] | [path] if options.dump_ast => dump_ast(path),
+        ["run", path] | [path] => run_file(path, &options),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(64);
+}
+
+fn parse_lines(lines: &str) -> Option<RangeInclusive<i32>> {
+    let (first, last) = lines.split_once('-')?;
+    Some(first.parse().ok()?..=last.parse().ok()?)
+}
+
+fn new_vm(options: &Options) -> VM {
+    let mut vm = VM::new();
+    if options.trace {
+        vm.set_trace_output(Some(Box::new(io::stderr())));
+        vm.set_trace_lines(options.trace_lines.clone());
+    }
+    vm
+}
+
+// `.loxc` files are loaded as compiled bytecode, anything else as source.
+fn load_function(path: &str, options: &Options) -> Function {
+    if Path::new(path).extension().is_some_and(|e| e == "loxc") {
+        load_bytecode(path)
     } else {
-        eprintln!("Wrong number of arguments");
-        process::exit(64);
+        compile_source(path, options)
+    }
+}
+
+fn run_file(path: &str, options: &Options) {
+    let function = load_function(path, options);
+
+    if new_vm(options).interpret(function).is_err() {
+        process::exit(70);
+    }
+}
+
+// Runs the file under the command line debugger, paused before the first
+// instruction.
+fn debug_file(path: &str, options: &Options) {
+    let function = load_function(path, options);
+    // Bytecode has no source to list, the debugger shows instructions instead.
+    let source = match Path::new(path).extension() {
+        Some(extension) if extension == "loxc" => String::new(),
+        _ => fs::read_to_string(path).unwrap_or_default(),
+    };
+
+    let mut debugger = Debugger::new(Box::new(debugger::Console::new(&source)));
+    debugger.stop_on_entry(true);
+    println!("Type 'help' for a list of commands.");
+
+    let mut vm = new_vm(options);
+    vm.set_debugger(Some(debugger));
+    if vm.interpret(function).is_err() {
+        process::exit(70);
+    }
+}
+
+// Speaks the Debug Adapter Protocol over stdin and stdout for editors.
+fn serve_dap() {
+    let input = Box::new(io::stdin().lock());
+    if let Err(error) = dap::serve(input, Box::new(io::stdout())) {
+        eprintln!("Debug adapter failed: {}", error);
+        process::exit(74);
     }
 }
 
-fn run_file(path: &str) {
+// Rewrites the files in the canonical layout. With `check` they are left
+// alone and the exit status tells whether any needs formatting.
+fn format_files(paths: &[&str], check: bool) {
+    let mut unformatted = false;
+    for path in paths {
+        let source = fs::read_to_string(path).expect("Something went wrong reading the file");
+        let formatted = match compiler::format(&source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                process::exit(65);
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", path);
+            unformatted = true;
+        } else if let Err(error) = fs::write(path, formatted) {
+            eprintln!("Could not write '{}': {}", path, error);
+            process::exit(74);
+        }
+    }
+
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+// Prints the warnings of each file, they don't change the exit status.
+fn lint_files(paths: &[&str]) {
+    for path in paths {
+        let source = fs::read_to_string(path).expect("Something went wrong reading the file");
+        match compiler::lint(&source) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("{}: {}", path, warning);
+                }
+            }
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                process::exit(65);
+            }
+        }
+    }
+}
+
+// Prints the syntax tree of a source file, one node per line.
+fn dump_ast(path: &str) {
+    let source = fs::read_to_string(path).expect("Something went wrong reading the file");
+
+    match compiler::parse(&source) {
+        Ok(program) => print!("{}", program),
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            process::exit(65);
+        }
+    }
+}
+
+fn disassemble_file(path: &str, json: bool, options: &Options) {
+    let listings = vm::debug::disassemble_function(&load_function(path, options));
+
+    if json {
+        println!("{}", vm::debug::to_json(&listings));
+    } else {
+        for listing in &listings {
+            println!("{}\n", listing);
+        }
+    }
+}
+
+fn compile_file(input: &str, output: &Path, options: &Options) {
+    let function = compile_source(input, options);
+
+    if let Err(error) = fs::write(output, function.chunk.serialize()) {
+        eprintln!("Could not write '{}': {}", output.display(), error);
+        process::exit(74);
+    }
+}
+
+fn compile_source(path: &str, options: &Options) -> Function {
     let source = fs::read_to_string(path).expect("Something went wrong reading the file");
 
-    let chunk = compiler::compile(&source);
-    if let Err(_) = chunk {
-        println!("Failed");
-        return;
+    match compiler::compile_with_options(&source, options.compile, &mut io::stderr()) {
+        Ok(function) => function,
+        Err(_) => process::exit(65),
     }
+}
 
-    let result = vm::run(&chunk.unwrap());
+fn load_bytecode(path: &str) -> Function {
+    let bytes = fs::read(path).expect("Something went wrong reading the file");
+
+    let function = match Chunk::deserialize(&bytes) {
+        Ok(chunk) => Function {
+            chunk,
+            ..Function::new()
+        },
+        Err(error) => {
+            eprintln!("Could not load '{}': {}", path, error);
+            process::exit(65);
+        }
+    };
 
-    match result {
-        InterpretResult::CompileError => process::exit(65),
-        InterpretResult::RuntimeError => process::exit(70),
-        _ => (),
+    // Bytecode may come from anywhere, check it before the VM trusts it.
+    if let Err(error) = vm::verify(&function) {
+        eprintln!("Invalid bytecode in '{}': {}", path, error);
+        process::exit(65);
     }
+
+    function
 }
 
-fn repl() {
+fn repl(options: &Options) {
     let mut line = String::new();
-    let mut vm = vm::VM::new();
+    let mut vm = new_vm(options);
 
     loop {
         print!("> ");
@@ -50,19 +264,17 @@ fn repl() {
             break;
         }
 
-        let chunk = compiler::compile(&line);
-        if let Err(_) = chunk {
-            println!("Failed");
-            return;
-        }
-
-        let result = vm.run(&chunk.unwrap());
+        let function =
+            match compiler::compile_with_options(&line, options.compile, &mut io::stderr()) {
+                Ok(function) => function,
+                Err(_) => {
+                    line.clear();
+                    continue;
+                }
+            };
 
-        match result {
-            InterpretResult::CompileError => process::exit(65),
-            InterpretResult::RuntimeError => process::exit(70),
-            _ => (),
-        }
+        // Runtime errors are reported by the VM, the REPL keeps going.
+        let _ = vm.interpret(function);
 
         line.clear();
     }
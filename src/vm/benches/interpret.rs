@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vm::VM;
+
+const FIB: &str = "
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+var result = fib(20);
+";
+
+const LOOP: &str = "
+var sum = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+    if (i / 2 * 2 == i) sum = sum + i; else sum = sum - 1;
+}
+";
+
+const METHODS: &str = "
+class Counter {
+    init() { this.count = 0; }
+    increment() { this.count = this.count + 1; return this; }
+}
+var counter = Counter();
+for (var i = 0; i < 20000; i = i + 1) counter.increment();
+";
+
+fn interpret(source: &str) {
+    let function = compiler::compile(source).unwrap();
+    let mut vm = VM::new();
+    vm.interpret(function).unwrap();
+}
+
+fn benchmarks(c: &mut Criterion) {
+    c.bench_function("fib", |b| b.iter(|| interpret(FIB)));
+    c.bench_function("loop", |b| b.iter(|| interpret(LOOP)));
+    c.bench_function("methods", |b| b.iter(|| interpret(METHODS)));
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
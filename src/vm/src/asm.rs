@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+
+use byteorder::{BigEndian, ByteOrder};
+use common::{
+    chunk::{Chunk, LocalVariable},
+    object::Function,
+    opcode::OpCode,
+    value::Value,
+};
+
+use crate::error::AssembleError;
+
+// The inverse of `debug`, for building chunks without the compiler. One
+// statement per line, `;` starts a comment:
+//
+//   .const <value>                     appends nil, true, false, an integer,
+//                                      a float or a "string" constant
+//   .function <name> <arity> <upvalues>
+//     ...
+//   .end                               appends a function constant
+//   .line <line>                       source line of the instructions below
+//   .local <name> <slot> <start> <end> debug info for a local variable
+//   .upvalue <name>                    debug info for the next upvalue
+//   <label>:                           names the next instruction's offset
+//   OP_NAME <operands>
+//
+// Operands are numbers, except that jumps take a label and `OP_CLOSURE`
+// takes its constant followed by `local N` or `upvalue N` for each capture.
+// Operand widths follow the opcode, so `OP_CONSTANT_LONG 1` takes three
+// bytes. Only the encoding is checked, run the result through `verify`
+// before executing it.
+
+const MAX_BYTE: usize = u8::MAX as usize;
+const MAX_LONG: usize = 0xff_ffff;
+
+pub fn assemble(source: &str) -> Result<Chunk, AssembleError> {
+    let mut statements = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let tokens = tokenize(line).map_err(|message| AssembleError {
+            line: index + 1,
+            message,
+        })?;
+        if !tokens.is_empty() {
+            statements.push((index + 1, tokens));
+        }
+    }
+
+    let mut assembler = Assembler {
+        statements: statements.into_iter(),
+        last_line: source.lines().count(),
+    };
+    assembler.body(None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => string.push('\n'),
+                        Some('t') => string.push('\t'),
+                        Some('"') => string.push('"'),
+                        Some('\\') => string.push('\\'),
+                        Some(c) => return Err(format!("Invalid escape '\\{}'.", c)),
+                        None => return Err("Unterminated string.".to_string()),
+                    },
+                    Some(c) => string.push(c),
+                    None => return Err("Unterminated string.".to_string()),
+                }
+            }
+            tokens.push(Token::Str(string));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Assembler {
+    statements: std::vec::IntoIter<(usize, Vec<Token>)>,
+    // Reported when the source ends inside a function.
+    last_line: usize,
+}
+
+// The chunk of the function being assembled.
+struct Body {
+    chunk: Chunk,
+    line: i32,
+    labels: HashMap<String, usize>,
+    // Jumps are patched once all labels are known.
+    jumps: Vec<PendingJump>,
+}
+
+struct PendingJump {
+    offset: usize,
+    label: String,
+    source_line: usize,
+}
+
+impl Assembler {
+    // Assembles statements up to the `.end` of function `name`, or to the end
+    // of the source for the top-level script.
+    fn body(&mut self, name: Option<&str>) -> Result<Chunk, AssembleError> {
+        let mut body = Body {
+            chunk: Chunk::new(),
+            line: 1,
+            labels: HashMap::new(),
+            jumps: Vec::new(),
+        };
+
+        while let Some((line, tokens)) = self.statements.next() {
+            let error = |message: String| AssembleError { line, message };
+
+            let (first, rest) = match &tokens[..] {
+                [Token::Word(first), rest @ ..] => (first.as_str(), rest),
+                _ => return Err(error("Expected an instruction or directive.".to_string())),
+            };
+
+            match first {
+                ".end" if name.is_some() => {
+                    expect_operands(first, rest, 0).map_err(error)?;
+                    return body.finish();
+                }
+                ".end" => return Err(error("Unexpected '.end'.".to_string())),
+                ".const" => {
+                    expect_operands(first, rest, 1).map_err(error)?;
+                    let value = constant(&rest[0]).map_err(error)?;
+                    body.chunk.add_constant(value);
+                }
+                ".function" => {
+                    expect_operands(first, rest, 3).map_err(error)?;
+                    let name = word(&rest[0]).map_err(error)?;
+                    let arity = number(&rest[1], MAX_BYTE).map_err(error)?;
+                    let upvalue_count = number(&rest[2], MAX_BYTE).map_err(error)?;
+
+                    let chunk = self.body(Some(name))?;
+                    body.chunk.add_constant(Value::new_obj_function(Function {
+                        arity,
+                        upvalue_count,
+                        chunk,
+                        name: Some(name.to_string()),
+                    }));
+                }
+                ".line" => {
+                    expect_operands(first, rest, 1).map_err(error)?;
+                    let text = word(&rest[0]).map_err(error)?;
+                    body.line = text
+                        .parse()
+                        .map_err(|_| error(format!("Invalid line '{}'.", text)))?;
+                }
+                ".local" => {
+                    expect_operands(first, rest, 4).map_err(error)?;
+                    body.chunk.locals.push(LocalVariable {
+                        name: word(&rest[0]).map_err(error)?.to_string(),
+                        slot: number(&rest[1], MAX_LONG).map_err(error)?,
+                        start: number(&rest[2], usize::MAX).map_err(error)?,
+                        end: number(&rest[3], usize::MAX).map_err(error)?,
+                    });
+                }
+                ".upvalue" => {
+                    expect_operands(first, rest, 1).map_err(error)?;
+                    let name = word(&rest[0]).map_err(error)?;
+                    body.chunk.upvalues.push(name.to_string());
+                }
+                label if label.ends_with(':') && rest.is_empty() => {
+                    let label = &label[..label.len() - 1];
+                    let offset = body.chunk.code.len();
+                    if body.labels.insert(label.to_string(), offset).is_some() {
+                        return Err(error(format!("Label '{}' is already defined.", label)));
+                    }
+                }
+                name => match OpCode::from_name(name) {
+                    Some(opcode) => body.instruction(line, opcode, rest)?,
+                    None => return Err(error(format!("Unknown instruction '{}'.", name))),
+                },
+            }
+        }
+
+        match name {
+            Some(name) => Err(AssembleError {
+                line: self.last_line,
+                message: format!("Missing '.end' for function '{}'.", name),
+            }),
+            None => body.finish(),
+        }
+    }
+}
+
+impl Body {
+    fn instruction(
+        &mut self,
+        line: usize,
+        opcode: OpCode,
+        operands: &[Token],
+    ) -> Result<(), AssembleError> {
+        let error = |message: String| AssembleError { line, message };
+        let offset = self.chunk.code.len();
+        self.write(opcode as usize, 1);
+
+        let mut operands = operands.iter();
+        let mut next = || {
+            operands
+                .next()
+                .ok_or_else(|| error(format!("Missing operand for {}.", opcode.name())))
+        };
+
+        match opcode {
+            OpCode::OpConstant
+            | OpCode::OpGetGlobal
+            | OpCode::OpDefineGlobal
+            | OpCode::OpSetGlobal
+            | OpCode::OpClass
+            | OpCode::OpGetProperty
+            | OpCode::OpSetProperty
+            | OpCode::OpMethod
+            | OpCode::OpGetSuper
+            | OpCode::OpGetLocal
+            | OpCode::OpSetLocal
+            | OpCode::OpGetUpvalue
+            | OpCode::OpSetUpvalue
+            | OpCode::OpCall => {
+                let operand = number(next()?, MAX_BYTE).map_err(error)?;
+                self.write(operand, 1);
+            }
+            OpCode::OpConstantLong
+            | OpCode::OpGetGlobalLong
+            | OpCode::OpDefineGlobalLong
+            | OpCode::OpSetGlobalLong
+            | OpCode::OpClassLong
+            | OpCode::OpGetPropertyLong
+            | OpCode::OpSetPropertyLong
+            | OpCode::OpMethodLong
+            | OpCode::OpGetSuperLong
+            | OpCode::OpGetLocalLong
+            | OpCode::OpSetLocalLong => {
+                let operand = number(next()?, MAX_LONG).map_err(error)?;
+                self.write(operand, 3);
+            }
+            OpCode::OpInvoke | OpCode::OpSuperInvoke => {
+                let constant = number(next()?, MAX_BYTE).map_err(error)?;
+                let arg_count = number(next()?, MAX_BYTE).map_err(error)?;
+                self.write(constant, 1);
+                self.write(arg_count, 1);
+            }
+            OpCode::OpInvokeLong | OpCode::OpSuperInvokeLong => {
+                let constant = number(next()?, MAX_LONG).map_err(error)?;
+                let arg_count = number(next()?, MAX_BYTE).map_err(error)?;
+                self.write(constant, 3);
+                self.write(arg_count, 1);
+            }
+            OpCode::OpJump | OpCode::OpJumpIfFalse | OpCode::OpLoop => {
+                let label = word(next()?).map_err(error)?;
+                self.jumps.push(PendingJump {
+                    offset,
+                    label: label.to_string(),
+                    source_line: line,
+                });
+                self.write(0xffff, 2);
+            }
+            OpCode::OpClosure | OpCode::OpClosureLong => {
+                let width = if opcode == OpCode::OpClosure { 1 } else { 3 };
+                let constant = number(next()?, if width == 1 { MAX_BYTE } else { MAX_LONG });
+                self.write(constant.map_err(error)?, width);
+
+                while let Some(kind) = operands.next() {
+                    let is_local = match word(kind).map_err(error)? {
+                        "local" => 1,
+                        "upvalue" => 0,
+                        other => {
+                            return Err(error(format!(
+                                "Expected 'local' or 'upvalue', got '{}'.",
+                                other
+                            )))
+                        }
+                    };
+                    let index = operands
+                        .next()
+                        .ok_or_else(|| error("Missing capture index.".to_string()))?;
+                    let index = number(index, MAX_BYTE).map_err(error)?;
+                    self.write(is_local, 1);
+                    self.write(index, 1);
+                }
+            }
+            OpCode::OpReturn
+            | OpCode::OpNil
+            | OpCode::OpTrue
+            | OpCode::OpFalse
+            | OpCode::OpPop
+            | OpCode::OpEqual
+            | OpCode::OpGreater
+            | OpCode::OpLess
+            | OpCode::OpAdd
+            | OpCode::OpSubtract
+            | OpCode::OpMultiply
+            | OpCode::OpDivide
+            | OpCode::OpNot
+            | OpCode::OpNegate
+            | OpCode::OpPrint
+            | OpCode::OpCloseUpvalue
+            | OpCode::OpInherit => {}
+        }
+
+        if operands.next().is_some() {
+            return Err(error(format!("Too many operands for {}.", opcode.name())));
+        }
+        Ok(())
+    }
+
+    // Writes `value` as `width` bytes, little-endian like the compiler's long
+    // operands. Jump offsets are patched big-endian in `finish`.
+    fn write(&mut self, value: usize, width: usize) {
+        for i in 0..width {
+            self.chunk.write_chunk((value >> (8 * i)) as u8, self.line);
+        }
+    }
+
+    fn finish(mut self) -> Result<Chunk, AssembleError> {
+        for jump in &self.jumps {
+            let error = |message: String| AssembleError {
+                line: jump.source_line,
+                message,
+            };
+
+            let target = *self
+                .labels
+                .get(&jump.label)
+                .ok_or_else(|| error(format!("Undefined label '{}'.", jump.label)))?;
+            let next = jump.offset + 3;
+            let distance = if self.chunk.code[jump.offset] == OpCode::OpLoop as u8 {
+                next.checked_sub(target).ok_or_else(|| {
+                    error(format!("OP_LOOP can't jump forward to '{}'.", jump.label))
+                })?
+            } else {
+                target.checked_sub(next).ok_or_else(|| {
+                    error(format!(
+                        "Label '{}' is behind the jump, use OP_LOOP.",
+                        jump.label
+                    ))
+                })?
+            };
+            if distance > u16::MAX as usize {
+                return Err(error(format!("Jump to '{}' is too far.", jump.label)));
+            }
+
+            BigEndian::write_u16(&mut self.chunk.code[jump.offset + 1..next], distance as u16);
+        }
+
+        Ok(self.chunk)
+    }
+}
+
+fn expect_operands(directive: &str, operands: &[Token], count: usize) -> Result<(), String> {
+    if operands.len() != count {
+        return Err(format!("{} takes {} operand(s).", directive, count));
+    }
+    Ok(())
+}
+
+fn word(token: &Token) -> Result<&str, String> {
+    match token {
+        Token::Word(word) => Ok(word),
+        Token::Str(string) => Err(format!("Unexpected string \"{}\".", string)),
+    }
+}
+
+fn number(token: &Token, max: usize) -> Result<usize, String> {
+    let word = word(token)?;
+    let number: usize = word
+        .parse()
+        .map_err(|_| format!("Invalid operand '{}'.", word))?;
+    if number > max {
+        return Err(format!("Operand {} is larger than {}.", number, max));
+    }
+    Ok(number)
+}
+
+fn constant(token: &Token) -> Result<Value, String> {
+    let word = match token {
+        Token::Str(string) => return Ok(Value::new_obj_string(string.clone())),
+        Token::Word(word) => word.as_str(),
+    };
+
+    match word {
+        "nil" => Ok(Value::new_nil()),
+        "true" => Ok(Value::new_bool(true)),
+        "false" => Ok(Value::new_bool(false)),
+        _ => {
+            if let Ok(number) = word.parse() {
+                Ok(Value::new_number(number))
+            } else if let Ok(float) = word.parse() {
+                Ok(Value::new_float(float))
+            } else {
+                Err(format!("Invalid constant '{}'.", word))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::{self, Operand};
+    use rstest::rstest;
+
+    // Writes a listing for `chunk` from its disassembly, naming each jump
+    // target `L<offset>`.
+    fn listing(chunk: &Chunk) -> String {
+        let mut listing = String::new();
+        for constant in &chunk.constants.values {
+            if constant.is_function() {
+                let function = constant.as_function();
+                listing += &format!(
+                    ".function {} {} {}\n",
+                    function.name.as_ref().unwrap(),
+                    function.arity,
+                    function.upvalue_count
+                );
+                listing += &self::listing(&function.chunk);
+                listing += ".end\n";
+            } else if constant.is_string() {
+                listing += &format!(".const {:?}\n", constant.as_string());
+            } else {
+                listing += &format!(".const {:?}\n", constant);
+            }
+        }
+
+        for local in &chunk.locals {
+            listing += &format!(
+                ".local {} {} {} {}\n",
+                local.name, local.slot, local.start, local.end
+            );
+        }
+
+        for name in &chunk.upvalues {
+            listing += &format!(".upvalue {}\n", name);
+        }
+
+        let instructions = debug::disassemble(chunk);
+        let targets: Vec<usize> = instructions
+            .iter()
+            .flat_map(|instruction| &instruction.operands)
+            .filter_map(|operand| match operand {
+                Operand::Jump(target) => Some(*target),
+                _ => None,
+            })
+            .collect();
+
+        let mut line = None;
+        for instruction in &instructions {
+            if targets.contains(&instruction.offset) {
+                listing += &format!("L{}:\n", instruction.offset);
+            }
+            if line != Some(instruction.line) {
+                listing += &format!(".line {}\n", instruction.line);
+                line = Some(instruction.line);
+            }
+
+            listing += instruction.opcode.name();
+            for operand in &instruction.operands {
+                listing += &match operand {
+                    Operand::Constant(n) | Operand::Slot(n) | Operand::ArgCount(n) => {
+                        format!(" {}", n)
+                    }
+                    Operand::Jump(target) => format!(" L{}", target),
+                    Operand::Capture {
+                        is_local: true,
+                        index,
+                    } => format!(" local {}", index),
+                    Operand::Capture {
+                        is_local: false,
+                        index,
+                    } => format!(" upvalue {}", index),
+                };
+            }
+            listing += "\n";
+        }
+        listing
+    }
+
+    #[test]
+    fn round_trips_compiled_samples() {
+        let samples = format!("{}/../runner/samples", env!("CARGO_MANIFEST_DIR"));
+        for entry in std::fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let function = compiler::compile(&source).unwrap();
+
+            let chunk = assemble(&listing(&function.chunk)).unwrap();
+
+            assert_eq!(
+                chunk.serialize(),
+                function.chunk.serialize(),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn assembles_a_runnable_loop() {
+        let chunk = assemble(
+            r#"
+            .const "n"
+            .const 3
+            .const 0
+            .const 1
+                OP_CONSTANT 1       ; var n = 3;
+                OP_DEFINE_GLOBAL 0
+            .line 2
+            loop:
+                OP_GET_GLOBAL 0     ; while (n > 0)
+                OP_CONSTANT 2
+                OP_GREATER
+                OP_JUMP_IF_FALSE done
+                OP_POP
+            .line 3
+                OP_GET_GLOBAL 0     ;   n = n - 1;
+                OP_CONSTANT 3
+                OP_SUBTRACT
+                OP_SET_GLOBAL 0
+                OP_POP
+                OP_LOOP loop
+            done:
+                OP_POP
+                OP_NIL
+                OP_RETURN
+            "#,
+        )
+        .unwrap();
+        let function = Function {
+            chunk,
+            ..Function::new()
+        };
+
+        let listing = debug::disassemble_function(&function)[0].to_string();
+        assert!(
+            listing.contains("0009    | OP_JUMP_IF_FALSE -> 0024\n"),
+            "{}",
+            listing
+        );
+        assert!(
+            listing.contains("0021    | OP_LOOP -> 0004\n"),
+            "{}",
+            listing
+        );
+        assert_eq!(crate::verify(&function), Ok(()));
+
+        let mut vm = crate::VM::new();
+        vm.interpret(function).unwrap();
+        assert_eq!(i64::try_from(vm.get_global("n").unwrap()), Ok(0));
+    }
+
+    #[rstest]
+    #[case("OP_FOO", "[line 1] Unknown instruction 'OP_FOO'.")]
+    #[case("OP_CONSTANT", "[line 1] Missing operand for OP_CONSTANT.")]
+    #[case("OP_RETURN 1", "[line 1] Too many operands for OP_RETURN.")]
+    #[case("OP_GET_LOCAL 256", "[line 1] Operand 256 is larger than 255.")]
+    #[case("OP_CALL x", "[line 1] Invalid operand 'x'.")]
+    #[case("OP_JUMP nowhere", "[line 1] Undefined label 'nowhere'.")]
+    #[case("a:\nOP_JUMP a", "[line 2] Label 'a' is behind the jump, use OP_LOOP.")]
+    #[case("OP_LOOP a\nOP_NIL\na:", "[line 1] OP_LOOP can't jump forward to 'a'.")]
+    #[case("a:\na:", "[line 2] Label 'a' is already defined.")]
+    #[case(".const \"open", "[line 1] Unterminated string.")]
+    #[case(".const what", "[line 1] Invalid constant 'what'.")]
+    #[case(
+        "OP_CLOSURE 0 global 1",
+        "[line 1] Expected 'local' or 'upvalue', got 'global'."
+    )]
+    #[case(
+        ".function f 0 0\nOP_RETURN",
+        "[line 2] Missing '.end' for function 'f'."
+    )]
+    #[case(".end", "[line 1] Unexpected '.end'.")]
+    fn rejects_malformed_listings(#[case] source: &str, #[case] message: &str) {
+        assert_eq!(assemble(source).unwrap_err().to_string(), message);
+    }
+}
@@ -1,121 +1,345 @@
+use std::fmt;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
-use common::{chunk::{Chunk}, opcode::{OpCode}};
+use common::{chunk::Chunk, object::Function, opcode::OpCode};
+use serde::{Serialize, Serializer};
 
-pub fn disassemble_chunk(chunk: &Chunk, name: String) {
-    println!("== {} ==", name);
+/// One decoded instruction. The disassembler expects well-formed bytecode,
+/// run untrusted chunks through `verify` first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Instruction {
+    pub offset: usize,
+    // Length in bytes, including operands.
+    pub length: usize,
+    pub line: i32,
+    #[serde(serialize_with = "serialize_opcode")]
+    pub opcode: OpCode,
+    pub operands: Vec<Operand>,
+    // The referenced constant as it would be printed, if there is one.
+    pub constant: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operand {
+    Constant(usize),
+    // A local slot or upvalue index.
+    Slot(usize),
+    ArgCount(usize),
+    // The instruction offset the jump lands on.
+    Jump(usize),
+    // One of the variables captured by `OP_CLOSURE`.
+    Capture { is_local: bool, index: usize },
+}
 
-    let mut offset: i32 = 0;
-    while offset < chunk.code.len() as i32 {
-        offset = disassemble_instruction(chunk, offset);
+/// The instructions of a single function.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Disassembly {
+    pub name: String,
+    pub instructions: Vec<Instruction>,
+}
+
+fn serialize_opcode<S: Serializer>(opcode: &OpCode, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(opcode.name())
+}
+
+pub fn disassemble(chunk: &Chunk) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let instruction = disassemble_instruction(chunk, offset);
+        offset += instruction.length;
+        instructions.push(instruction);
     }
+    instructions
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: i32) -> i32 {
-    print!("{:#04} ", offset);
-    if offset > 0 && chunk.lines[offset as usize] == chunk.lines[(offset - 1) as usize] {
-        print!("    | ");
-    } else {
-        print!("{:#4} ", chunk.lines[offset as usize]);
+/// Disassembles `function` followed by every function nested in its
+/// constants.
+pub fn disassemble_function(function: &Function) -> Vec<Disassembly> {
+    let mut listings = vec![Disassembly {
+        name: match &function.name {
+            Some(name) => format!("<fn {}>", name),
+            None => "<script>".to_string(),
+        },
+        instructions: disassemble(&function.chunk),
+    }];
+
+    for constant in &function.chunk.constants.values {
+        if constant.is_function() {
+            listings.extend(disassemble_function(constant.as_function()));
+        }
     }
+    listings
+}
 
-    let instruction = chunk.code[offset as usize];
-    match OpCode::try_from(instruction).unwrap() {
-        OpCode::OpReturn => simple_instruction(String::from("OP_RETURN"), offset),
-        OpCode::OpConstant => constant_instruction(String::from("OP_CONSTANT"), chunk, offset),
-        OpCode::OpConstantLong => {
-            long_constant_instruction(String::from("OP_CONSTANT_LONG"), chunk, offset)
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> Instruction {
+    let opcode = OpCode::try_from(chunk.code[offset]).unwrap();
+    let mut instruction = Instruction {
+        offset,
+        length: 1,
+        line: chunk.lines[offset],
+        opcode,
+        operands: Vec::new(),
+        constant: None,
+    };
+
+    match opcode {
+        OpCode::OpConstant
+        | OpCode::OpGetGlobal
+        | OpCode::OpDefineGlobal
+        | OpCode::OpSetGlobal
+        | OpCode::OpClass
+        | OpCode::OpGetProperty
+        | OpCode::OpSetProperty
+        | OpCode::OpMethod
+        | OpCode::OpGetSuper => {
+            constant_operand(&mut instruction, chunk, false);
+        }
+        OpCode::OpConstantLong
+        | OpCode::OpGetGlobalLong
+        | OpCode::OpDefineGlobalLong
+        | OpCode::OpSetGlobalLong
+        | OpCode::OpClassLong
+        | OpCode::OpGetPropertyLong
+        | OpCode::OpSetPropertyLong
+        | OpCode::OpMethodLong
+        | OpCode::OpGetSuperLong => {
+            constant_operand(&mut instruction, chunk, true);
         }
-        OpCode::OpNil => simple_instruction(String::from("OP_NIL"), offset),
-        OpCode::OpTrue => simple_instruction(String::from("OP_TRUE"), offset),
-        OpCode::OpFalse => simple_instruction(String::from("OP_FALSE"), offset),
-        OpCode::OpGetLocal => byte_instruction(String::from("OP_GET_LOCAL"), chunk, offset),
-        OpCode::OpGetLocalLong => {
-            long_byte_instruction(String::from("OP_GET_LOCAL_LONG"), chunk, offset)
+        OpCode::OpGetLocal | OpCode::OpSetLocal | OpCode::OpGetUpvalue | OpCode::OpSetUpvalue => {
+            let slot = read_operand(&mut instruction, chunk, false);
+            instruction.operands.push(Operand::Slot(slot));
         }
-        OpCode::OpSetLocal => byte_instruction(String::from("OP_SET_LOCAL"), chunk, offset),
-        OpCode::OpSetLocalLong => {
-            long_byte_instruction(String::from("OP_SET_LOCAL_LONG"), chunk, offset)
+        OpCode::OpGetLocalLong | OpCode::OpSetLocalLong => {
+            let slot = read_operand(&mut instruction, chunk, true);
+            instruction.operands.push(Operand::Slot(slot));
         }
-        OpCode::OpGetGlobal => constant_instruction(String::from("OP_GET_GLOBAL"), chunk, offset),
-        OpCode::OpGetGlobalLong => {
-            long_constant_instruction(String::from("OP_GET_GLOBAL_LONG"), chunk, offset)
+        OpCode::OpCall => {
+            let arg_count = read_operand(&mut instruction, chunk, false);
+            instruction.operands.push(Operand::ArgCount(arg_count));
         }
-        OpCode::OpDefineGlobal => {
-            constant_instruction(String::from("OP_DEFINE_GLOBAL"), chunk, offset)
+        OpCode::OpInvoke | OpCode::OpSuperInvoke => {
+            constant_operand(&mut instruction, chunk, false);
+            let arg_count = read_operand(&mut instruction, chunk, false);
+            instruction.operands.push(Operand::ArgCount(arg_count));
         }
-        OpCode::OpDefineGlobalLong => {
-            long_constant_instruction(String::from("OP_DEFINE_GLOBAL_LONG"), chunk, offset)
+        OpCode::OpInvokeLong | OpCode::OpSuperInvokeLong => {
+            constant_operand(&mut instruction, chunk, true);
+            let arg_count = read_operand(&mut instruction, chunk, false);
+            instruction.operands.push(Operand::ArgCount(arg_count));
         }
-        OpCode::OpSetGlobal => constant_instruction(String::from("OP_SET_GLOBAL"), chunk, offset),
-        OpCode::OpSetGlobalLong => {
-            long_constant_instruction(String::from("OP_SET_GLOBAL_LONG"), chunk, offset)
+        OpCode::OpJump | OpCode::OpJumpIfFalse | OpCode::OpLoop => {
+            let jump = BigEndian::read_u16(&chunk.code[offset + 1..offset + 3]) as usize;
+            instruction.length = 3;
+            let target = if opcode == OpCode::OpLoop {
+                offset + 3 - jump
+            } else {
+                offset + 3 + jump
+            };
+            instruction.operands.push(Operand::Jump(target));
         }
-        OpCode::OpEqual => simple_instruction(String::from("OP_EQUAL"), offset),
-        OpCode::OpGreater => simple_instruction(String::from("OP_GREATER"), offset),
-        OpCode::OpLess => simple_instruction(String::from("OP_LESS"), offset),
-        OpCode::OpAdd => simple_instruction(String::from("OP_ADD"), offset),
-        OpCode::OpSubtract => simple_instruction(String::from("OP_SUBTRACT"), offset),
-        OpCode::OpMultiply => simple_instruction(String::from("OP_MULTIPLY"), offset),
-        OpCode::OpDivide => simple_instruction(String::from("OP_DIVIDE"), offset),
-        OpCode::OpNot => simple_instruction(String::from("OP_NOT"), offset),
-        OpCode::OpNegate => simple_instruction(String::from("OP_NEGATE"), offset),
-        OpCode::OpPrint => simple_instruction(String::from("OP_PRINT"), offset),
-        OpCode::OpJumpIfFalse => {
-            jump_instruction(String::from("OP_JUMP_IF_FALSE"), 1, chunk, offset)
+        OpCode::OpClosure | OpCode::OpClosureLong => {
+            let index = constant_operand(&mut instruction, chunk, opcode == OpCode::OpClosureLong);
+            let function = chunk.constants.values[index].as_function();
+            for _ in 0..function.upvalue_count {
+                let start = offset + instruction.length;
+                instruction.operands.push(Operand::Capture {
+                    is_local: chunk.code[start] == 1,
+                    index: chunk.code[start + 1] as usize,
+                });
+                instruction.length += 2;
+            }
         }
-        OpCode::OpJump => jump_instruction(String::from("OP_JUMP"), 1, chunk, offset),
-        OpCode::OpLoop => jump_instruction(String::from("OP_LOOP"), -1, chunk, offset),
-        OpCode::OpPop => simple_instruction(String::from("OP_POP"), offset)
+        OpCode::OpReturn
+        | OpCode::OpNil
+        | OpCode::OpTrue
+        | OpCode::OpFalse
+        | OpCode::OpPop
+        | OpCode::OpEqual
+        | OpCode::OpGreater
+        | OpCode::OpLess
+        | OpCode::OpAdd
+        | OpCode::OpSubtract
+        | OpCode::OpMultiply
+        | OpCode::OpDivide
+        | OpCode::OpNot
+        | OpCode::OpNegate
+        | OpCode::OpPrint
+        | OpCode::OpCloseUpvalue
+        | OpCode::OpInherit => {}
     }
+
+    instruction
 }
 
-fn constant_instruction(name: String, chunk: &Chunk, offset: i32) -> i32 {
-    let constant = chunk.code[(offset + 1) as usize];
-    print!("{} {:#04} '", name, constant);
-    chunk.constants.values[constant as usize].print_value();
-    println!("'");
-    offset + 2
+// Reads a one or three byte operand following the instruction so far.
+fn read_operand(instruction: &mut Instruction, chunk: &Chunk, long: bool) -> usize {
+    let start = instruction.offset + instruction.length;
+    if long {
+        instruction.length += 3;
+        LittleEndian::read_u24(&chunk.code[start..start + 3]) as usize
+    } else {
+        instruction.length += 1;
+        chunk.code[start] as usize
+    }
 }
 
-fn long_constant_instruction(name: String, chunk: &Chunk, offset: i32) -> i32 {
-    let mut buf = [0_u8; 4];
-    buf[..3].copy_from_slice(&chunk.code[(offset + 1) as usize..(offset + 4) as usize]);
-    let constant = LittleEndian::read_u32(&buf);
-    print!("{} {:#04} '", name, constant);
-    chunk.constants.values[constant as usize].print_value();
-    println!("'");
-    offset + 4
+fn constant_operand(instruction: &mut Instruction, chunk: &Chunk, long: bool) -> usize {
+    let index = read_operand(instruction, chunk, long);
+    instruction.operands.push(Operand::Constant(index));
+    instruction.constant = Some(format!("{:?}", chunk.constants.values[index]));
+    index
 }
 
-fn simple_instruction(name: String, offset: i32) -> i32 {
-    println!("{}", name);
-    offset + 1
+// Writes one line of the listing. The alternate form `{:#}` prints `|` in
+// place of the line number, for instructions on the same line as the last.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04} ", self.offset)?;
+        if f.alternate() {
+            write!(f, "   | ")?;
+        } else {
+            write!(f, "{:4} ", self.line)?;
+        }
+        write!(f, "{}", self.opcode.name())?;
+
+        let mut captures = Vec::new();
+        for operand in &self.operands {
+            match operand {
+                Operand::Constant(index) => write!(
+                    f,
+                    " {:04} '{}'",
+                    index,
+                    self.constant.as_deref().unwrap_or_default()
+                )?,
+                Operand::Slot(slot) => write!(f, " {:04}", slot)?,
+                Operand::ArgCount(count) => write!(f, " ({} args)", count)?,
+                Operand::Jump(target) => write!(f, " -> {:04}", target)?,
+                Operand::Capture { is_local, index } => {
+                    let kind = if *is_local { "local" } else { "upvalue" };
+                    captures.push(format!("{} {}", kind, index));
+                }
+            }
+        }
+        if !captures.is_empty() {
+            write!(f, " [{}]", captures.join(", "))?;
+        }
+
+        Ok(())
+    }
 }
 
-fn byte_instruction(name: String, chunk: &Chunk, offset: i32) -> i32 {
-    let slot = chunk.code[(offset + 1) as usize];
-    print!("{} {:#04} '", name, slot);
-    offset + 2
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "== {} ==", self.name)?;
+
+        let mut previous_line = None;
+        for instruction in &self.instructions {
+            if previous_line == Some(instruction.line) {
+                write!(f, "\n{:#}", instruction)?;
+            } else {
+                write!(f, "\n{}", instruction)?;
+            }
+            previous_line = Some(instruction.line);
+        }
+        Ok(())
+    }
 }
 
-fn long_byte_instruction(name: String, chunk: &Chunk, offset: i32) -> i32 {
-    let mut buf = [0_u8; 4];
-    buf[..3].copy_from_slice(&chunk.code[(offset + 1) as usize..(offset + 4) as usize]);
-    let slot = LittleEndian::read_u32(&buf);
-    print!("{} {:#04} '", name, slot);
-    offset + 4
+pub fn to_json(listings: &[Disassembly]) -> String {
+    serde_json::to_string_pretty(listings).unwrap()
 }
 
-fn jump_instruction(name: String, sign: i32, chunk: &Chunk, offset: i32) -> i32 {
-    let mut buf = [0_u8; 4];
-    buf[..2].copy_from_slice(&chunk.code[(offset + 1) as usize..(offset + 3) as usize]);
-    let jump = BigEndian::read_u16(&buf);
-    println!(
-        "{} {:#04} -> {}",
-        name,
-        offset,
-        offset + 3 + sign * (jump as i32)
-    );
-    offset + 3
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::CompileOptions;
+    use std::io;
+
+    fn compile_unfolded(source: &str) -> Function {
+        let options = CompileOptions {
+            fold_constants: false,
+        };
+        compiler::compile_with_options(source, options, &mut io::sink()).unwrap()
+    }
+
+    #[test]
+    fn decodes_operands() {
+        let function =
+            compiler::compile("fun f(a) { var b = a; fun g() { return b; } return g; }").unwrap();
+        let f = function.chunk.constants.values[1].as_function();
+
+        let instructions = disassemble(&f.chunk);
+
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|instruction| instruction.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "0000    1 OP_GET_LOCAL 0001",
+                "0002    1 OP_CLOSURE 0000 '<fn g>' [local 2]",
+                "0006    1 OP_GET_LOCAL 0003",
+                "0008    1 OP_RETURN",
+                "0009    1 OP_NIL",
+                "0010    1 OP_RETURN",
+            ]
+        );
+        assert_eq!(
+            instructions[1].operands,
+            vec![
+                Operand::Constant(0),
+                Operand::Capture {
+                    is_local: true,
+                    index: 2
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_nested_functions() {
+        let function = compile_unfolded("fun f() {\n  while (false) print \"x\";\n}\nf();");
+
+        let listings = disassemble_function(&function);
+
+        assert_eq!(
+            listings.iter().map(|l| l.name.as_str()).collect::<Vec<_>>(),
+            vec!["<script>", "<fn f>"]
+        );
+        assert_eq!(
+            listings[1].to_string(),
+            "== <fn f> ==\n\
+             0000    2 OP_FALSE\n\
+             0001    | OP_JUMP_IF_FALSE -> 0011\n\
+             0004    | OP_POP\n\
+             0005    | OP_CONSTANT 0000 'x'\n\
+             0007    | OP_PRINT\n\
+             0008    | OP_LOOP -> 0000\n\
+             0011    | OP_POP\n\
+             0012    3 OP_NIL\n\
+             0013    | OP_RETURN"
+        );
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let function = compiler::compile("print 1;").unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&disassemble_function(&function))).unwrap();
+
+        assert_eq!(
+            json[0]["instructions"][0],
+            serde_json::json!({
+                "offset": 0,
+                "length": 2,
+                "line": 1,
+                "opcode": "OP_CONSTANT",
+                "operands": [{ "constant": 0 }],
+                "constant": "1",
+            })
+        );
+    }
 }
@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+
+use crate::vm::VM;
+
+/// Why the VM paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    // Before the first instruction, see `Debugger::stop_on_entry`.
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// How a paused VM carries on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    // Run until the next breakpoint.
+    Continue,
+    // Pause before the next instruction.
+    StepInstruction,
+    // Pause at the start of the next line, entering calls.
+    StepLine,
+    // Pause at the start of the next line in this frame or a caller.
+    StepOver,
+    // Pause once the current frame has returned.
+    StepOut,
+    // Abort the program with a runtime error.
+    Stop,
+}
+
+/// Receives control whenever the VM pauses. The handler can inspect the VM,
+/// change breakpoints through `VM::debugger_mut` and call into the VM with
+/// `VM::call_function`. Nothing pauses again until it returns.
+pub trait DebugHandler {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> Resume;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    None,
+    Instruction,
+    Line,
+    // Frame counts when stepping started.
+    Over(usize),
+    Out(usize),
+}
+
+/// Line breakpoints and stepping state for a `VM`, see `VM::set_debugger`.
+pub struct Debugger {
+    breakpoints: HashSet<i32>,
+    stop_on_entry: bool,
+    step: Step,
+    // Line of the last instruction executed in each frame, to tell when a
+    // frame starts a new line.
+    frame_lines: Vec<Option<i32>>,
+    // Taken out while the handler runs.
+    handler: Option<Box<dyn DebugHandler>>,
+}
+
+impl Debugger {
+    pub fn new(handler: Box<dyn DebugHandler>) -> Self {
+        Debugger {
+            breakpoints: HashSet::new(),
+            stop_on_entry: false,
+            step: Step::None,
+            frame_lines: Vec::new(),
+            handler: Some(handler),
+        }
+    }
+
+    // Pauses before the first instruction, giving the handler a chance to
+    // set breakpoints.
+    pub fn stop_on_entry(&mut self, stop: bool) {
+        self.stop_on_entry = stop;
+    }
+
+    pub fn set_breakpoint(&mut self, line: i32) {
+        self.breakpoints.insert(line);
+    }
+
+    pub fn clear_breakpoint(&mut self, line: i32) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<i32> {
+        let mut lines: Vec<_> = self.breakpoints.iter().copied().collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    // Called before each instruction, with the number of frames and the line
+    // of the instruction. Returns the handler if the VM should pause.
+    pub(crate) fn check(
+        &mut self,
+        depth: usize,
+        line: i32,
+    ) -> Option<(Box<dyn DebugHandler>, PauseReason)> {
+        // The handler is evaluating something, don't pause inside it.
+        self.handler.as_ref()?;
+
+        self.frame_lines.resize(depth, None);
+        let new_line = self.frame_lines[depth - 1] != Some(line);
+        self.frame_lines[depth - 1] = Some(line);
+
+        let reason = if self.stop_on_entry {
+            self.stop_on_entry = false;
+            PauseReason::Entry
+        } else if new_line && self.breakpoints.contains(&line) {
+            PauseReason::Breakpoint
+        } else {
+            let step = match self.step {
+                Step::None => false,
+                Step::Instruction => true,
+                Step::Line => new_line,
+                Step::Over(frames) => new_line && depth <= frames,
+                Step::Out(frames) => depth < frames,
+            };
+            if !step {
+                return None;
+            }
+            PauseReason::Step
+        };
+
+        self.step = Step::None;
+        Some((self.handler.take().unwrap(), reason))
+    }
+
+    // Hands the handler back after a pause. Returns false if the program
+    // should stop.
+    pub(crate) fn resume(
+        &mut self,
+        handler: Box<dyn DebugHandler>,
+        resume: Resume,
+        depth: usize,
+    ) -> bool {
+        self.handler = Some(handler);
+        self.step = match resume {
+            Resume::Continue => Step::None,
+            Resume::StepInstruction => Step::Instruction,
+            Resume::StepLine => Step::Line,
+            Resume::StepOver => Step::Over(depth),
+            Resume::StepOut => Step::Out(depth),
+            Resume::Stop => return false,
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, io, rc::Rc};
+
+    const SOURCE: &str =
+        "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nvar x = add(1, 2);\nprint x;";
+
+    type Pauses = Rc<RefCell<Vec<(PauseReason, i32, Vec<String>)>>>;
+
+    // Records each pause with its line and locals, then answers with the
+    // next of `resumes`, or continues once they run out.
+    struct Script {
+        resumes: Vec<Resume>,
+        pauses: Pauses,
+    }
+
+    impl DebugHandler for Script {
+        fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+            let line = vm.call_stack()[0].line;
+            let locals = vm
+                .locals(0)
+                .iter()
+                .map(|(name, value)| format!("{}={:?}", name, value))
+                .collect();
+            self.pauses.borrow_mut().push((reason, line, locals));
+
+            if self.resumes.is_empty() {
+                Resume::Continue
+            } else {
+                self.resumes.remove(0)
+            }
+        }
+    }
+
+    fn debug(breakpoints: &[i32], resumes: Vec<Resume>) -> (Vec<(PauseReason, i32)>, Pauses) {
+        let pauses = Pauses::default();
+        let mut debugger = Debugger::new(Box::new(Script {
+            resumes,
+            pauses: pauses.clone(),
+        }));
+        debugger.stop_on_entry(breakpoints.is_empty());
+        for line in breakpoints {
+            debugger.set_breakpoint(*line);
+        }
+
+        let mut vm = VM::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.set_debugger(Some(debugger));
+        vm.interpret(compiler::compile(SOURCE).unwrap()).unwrap();
+
+        let lines = pauses
+            .borrow()
+            .iter()
+            .map(|(reason, line, _)| (*reason, *line))
+            .collect();
+        (lines, pauses)
+    }
+
+    #[test]
+    fn pauses_on_breakpoints_with_locals() {
+        let (lines, pauses) = debug(&[2, 3], vec![]);
+
+        assert_eq!(
+            lines,
+            vec![(PauseReason::Breakpoint, 2), (PauseReason::Breakpoint, 3)]
+        );
+        assert_eq!(pauses.borrow()[0].2, vec!["a=1", "b=2"]);
+        assert_eq!(pauses.borrow()[1].2, vec!["a=1", "b=2", "sum=3"]);
+    }
+
+    #[test]
+    fn steps_into_calls_line_by_line() {
+        let (lines, _) = debug(&[], vec![Resume::StepLine; 5]);
+
+        let steps: Vec<_> = lines.iter().map(|(_, line)| *line).collect();
+        assert_eq!(lines[0].0, PauseReason::Entry);
+        assert_eq!(steps, vec![4, 5, 2, 3, 6]);
+    }
+
+    #[test]
+    fn steps_over_and_out_of_calls() {
+        let (lines, _) = debug(&[], vec![Resume::StepLine, Resume::StepOver]);
+        assert_eq!(
+            lines.iter().map(|(_, line)| *line).collect::<Vec<_>>(),
+            vec![4, 5, 6]
+        );
+
+        let (lines, _) = debug(&[2], vec![Resume::StepOut]);
+        assert_eq!(
+            lines,
+            vec![(PauseReason::Breakpoint, 2), (PauseReason::Step, 5)]
+        );
+    }
+
+    #[test]
+    fn steps_single_instructions() {
+        let (lines, _) = debug(&[], vec![Resume::StepInstruction; 2]);
+
+        assert_eq!(
+            lines,
+            vec![
+                (PauseReason::Entry, 4),
+                (PauseReason::Step, 4),
+                (PauseReason::Step, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn stopping_aborts_the_program() {
+        let mut debugger = Debugger::new(Box::new(Script {
+            resumes: vec![Resume::Stop],
+            pauses: Pauses::default(),
+        }));
+        debugger.stop_on_entry(true);
+
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+        vm.set_debugger(Some(debugger));
+        let error = vm
+            .interpret(compiler::compile(SOURCE).unwrap())
+            .unwrap_err();
+
+        assert_eq!(error.message, "Stopped by the debugger.");
+    }
+
+    // Calls functions with the paused frame's locals, the second one fails.
+    struct Evaluate {
+        results: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl DebugHandler for Evaluate {
+        fn paused(&mut self, vm: &mut VM, _reason: PauseReason) -> Resume {
+            let args: Vec<_> = vm.locals(0).into_iter().map(|(_, value)| value).collect();
+            for body in ["return sum * b;", "return a + nil;"] {
+                let source = format!("fun f(a, b, sum) {{ {} }}", body);
+                let script = compiler::compile(&source).unwrap();
+                let function = script.chunk.constants.values[1].as_function().clone();
+
+                let result = match vm.call_function(function, &args) {
+                    Ok(value) => format!("{:?}", value),
+                    Err(error) => error.message,
+                };
+                self.results.borrow_mut().push(result);
+            }
+            Resume::Continue
+        }
+    }
+
+    #[test]
+    fn calls_while_paused_leave_the_program_intact() {
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let mut debugger = Debugger::new(Box::new(Evaluate {
+            results: results.clone(),
+        }));
+        debugger.set_breakpoint(3);
+
+        let mut vm = VM::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.set_debugger(Some(debugger));
+        vm.interpret(compiler::compile(SOURCE).unwrap()).unwrap();
+
+        assert_eq!(
+            *results.borrow(),
+            vec!["6".to_string(), "Operands must be numbers.".to_string()]
+        );
+        assert_eq!(i64::try_from(vm.get_global("x").unwrap()), Ok(3));
+    }
+}
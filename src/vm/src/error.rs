@@ -0,0 +1,86 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    // `None` for the top-level script.
+    pub function: Option<String>,
+    pub offset: usize,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub message: String,
+    // Offset and line of the failing instruction in the innermost frame.
+    pub offset: usize,
+    pub line: i32,
+    // Innermost call first.
+    pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(message: String) -> Self {
+        RuntimeError {
+            message,
+            offset: 0,
+            line: 0,
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        for frame in &self.trace {
+            match &frame.function {
+                Some(name) => write!(f, "\n[line {}] in {}()", frame.line, name)?,
+                None => write!(f, "\n[line {}] in script", frame.line)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Why a chunk was rejected by `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    // `None` for the top-level script.
+    pub function: Option<String>,
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(
+                f,
+                "[offset {}] in {}(): {}",
+                self.offset, name, self.message
+            ),
+            None => write!(f, "[offset {}] in script: {}", self.offset, self.message),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Why `assemble` rejected a listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
@@ -1,18 +1,30 @@
+// The only unsafe code allocates and collects through the heap, see
+// `VM::alloc` and `VM::collect_garbage`.
+#![deny(unsafe_code)]
+
+pub mod asm;
 pub mod debug;
+mod debugger;
+mod error;
+mod native;
 mod stack;
+mod verify;
 pub mod vm;
 
-use common::chunk::Chunk;
+use common::object::Function;
 
 use vm::RunResult;
 
+pub use asm::assemble;
+pub use debugger::{DebugHandler, Debugger, PauseReason, Resume};
+pub use error::{AssembleError, RuntimeError, TraceFrame, VerifyError};
+pub use native::NativeFn;
+pub use verify::verify;
 pub use vm::RunResult as InterpretResult;
 pub use vm::VM as VM;
 
-pub fn run(chunk: &Chunk) -> RunResult {
+pub fn run(function: Function) -> RunResult {
     let mut vm = vm::VM::new();
 
-    let result = vm.run(chunk);
-
-    result
+    vm.interpret(function)
 }
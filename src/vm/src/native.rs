@@ -0,0 +1,16 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::gc::Handle;
+
+use crate::{error::RuntimeError, vm::VM};
+
+// Arguments and results are handles, like every value the host sees.
+pub type NativeFn = fn(&mut VM, &[Handle]) -> Result<Handle, RuntimeError>;
+
+pub fn clock(_vm: &mut VM, _args: &[Handle]) -> Result<Handle, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError::new(e.to_string()))?;
+
+    Ok(Handle::from(now.as_secs_f64()))
+}
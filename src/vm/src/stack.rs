@@ -1,4 +1,6 @@
-use common::{value::Value};
+use std::io::{self, Write};
+
+use common::value::Value;
 
 #[derive(Default)]
 pub struct Stack {
@@ -23,6 +25,11 @@ impl Stack {
         &self.stack[index]
     }
 
+    // Like `get_at`, for slots that may have been popped.
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.stack.get(index)
+    }
+
     pub fn set_at(&mut self, index: usize, value: Value) {
         self.stack[index] = value;
     }
@@ -31,20 +38,40 @@ impl Stack {
         self.stack.pop()
     }
 
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    // Only the tests check this, the VM itself goes by `len`.
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.stack.iter()
+    }
+
+    pub fn top(&self, count: usize) -> &[Value] {
+        &self.stack[self.stack.len() - count..]
+    }
+
     pub fn peek(&self, distance: usize) -> &Value {
-        &self.stack[(self.stack.len() - 1 - distance) as usize]
+        &self.stack[self.stack.len() - 1 - distance]
     }
 
-    pub fn print_stack(&self) {
-        print!("[ ");
-        for v in self.stack.iter().enumerate() {
-            if v.0 == self.stack.len() - 1 {
-                v.1.print_value();
-            } else {
-                v.1.print_value();
-                print!(", ");
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "[ ")?;
+        for (i, value) in self.stack.iter().enumerate() {
+            if i > 0 {
+                write!(out, ", ")?;
             }
+            write!(out, "{:?}", value)?;
         }
-        println!(" ]");
+        writeln!(out, " ]")
     }
 }
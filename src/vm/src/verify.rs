@@ -0,0 +1,545 @@
+use std::collections::BTreeSet;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use common::{chunk::Chunk, object::Function, opcode::OpCode, value::Value};
+
+use crate::error::VerifyError;
+
+/// Checks that `function` and every function nested in its constants can be
+/// run without the VM reading out of bounds or misinterpreting a constant:
+/// opcodes are valid, operands are complete, constant, local and upvalue
+/// indices are in range, jumps land on instructions, the stack depth is the
+/// same along every path to an instruction and captured locals are closed
+/// rather than popped. The types of values on the stack aren't tracked, the
+/// VM checks those as it runs and reports a runtime error when they are
+/// wrong.
+pub fn verify(function: &Function) -> Result<(), VerifyError> {
+    Verifier::new(function).verify()?;
+
+    for constant in &function.chunk.constants.values {
+        if constant.is_function() {
+            verify(constant.as_function())?;
+        }
+    }
+
+    Ok(())
+}
+
+// What the verifier needs to know about one decoded instruction.
+struct Instruction {
+    offset: usize,
+    length: usize,
+    // Values popped and pushed.
+    pops: usize,
+    pushes: usize,
+    // Local slots and stack slots captured as upvalues, checked against the
+    // stack depth once it is known.
+    slots: Vec<usize>,
+    // The slots among them that are captured.
+    captures: Vec<usize>,
+    // Closes the upvalues of what it pops, or of the whole frame.
+    closes: bool,
+    jump: Option<usize>,
+    // False for instructions that never fall through to the next one.
+    falls_through: bool,
+}
+
+struct Verifier<'a> {
+    function: &'a Function,
+    chunk: &'a Chunk,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(function: &'a Function) -> Self {
+        Verifier {
+            function,
+            chunk: &function.chunk,
+        }
+    }
+
+    fn verify(&self) -> Result<(), VerifyError> {
+        if self.chunk.lines.len() != self.chunk.code.len() {
+            return Err(self.error(0, "Line table doesn't match the code length.".to_string()));
+        }
+
+        let mut instructions = Vec::new();
+        // Maps each byte offset to the instruction starting there.
+        let mut starts = vec![None; self.chunk.code.len()];
+        let mut offset = 0;
+        while offset < self.chunk.code.len() {
+            let instruction = self.decode(offset)?;
+            starts[offset] = Some(instructions.len());
+            offset += instruction.length;
+            instructions.push(instruction);
+        }
+
+        for instruction in &instructions {
+            if let Some(target) = instruction.jump {
+                if target >= starts.len() || starts[target].is_none() {
+                    return Err(self.error(
+                        instruction.offset,
+                        format!("Jump to {} doesn't land on an instruction.", target),
+                    ));
+                }
+            }
+        }
+
+        self.check_stack(&instructions, &starts)
+    }
+
+    // Propagates stack depths and captured slots from the entry point,
+    // which starts with the callee or receiver in slot zero followed by the
+    // arguments. A slot captured along any path to an instruction counts as
+    // captured there.
+    fn check_stack(
+        &self,
+        instructions: &[Instruction],
+        starts: &[Option<usize>],
+    ) -> Result<(), VerifyError> {
+        let mut states: Vec<Option<(usize, BTreeSet<usize>)>> = vec![None; instructions.len()];
+        let mut worklist = Vec::new();
+
+        if instructions.is_empty() {
+            return Err(self.error(0, "Chunk is empty.".to_string()));
+        }
+        states[0] = Some((self.function.arity + 1, BTreeSet::new()));
+        worklist.push(0);
+
+        while let Some(index) = worklist.pop() {
+            let instruction = &instructions[index];
+            let (depth, mut captured) = states[index].clone().unwrap();
+
+            // Slot zero belongs to the frame and is never popped.
+            if depth < instruction.pops + 1 {
+                return Err(self.error(instruction.offset, "Stack underflow.".to_string()));
+            }
+            for &slot in &instruction.slots {
+                if slot >= depth {
+                    return Err(self.error(
+                        instruction.offset,
+                        format!("Stack slot {} is out of range.", slot),
+                    ));
+                }
+            }
+
+            // The VM would leave the upvalue pointing at whatever takes the
+            // slot next, or past the top of the stack.
+            let popped = captured.split_off(&(depth - instruction.pops));
+            if let (Some(slot), false) = (popped.iter().next(), instruction.closes) {
+                return Err(self.error(
+                    instruction.offset,
+                    format!(
+                        "Captured stack slot {} is popped without being closed.",
+                        slot
+                    ),
+                ));
+            }
+            captured.extend(&instruction.captures);
+
+            let after = depth - instruction.pops + instruction.pushes;
+            let mut successors = Vec::with_capacity(2);
+            if instruction.falls_through {
+                let next = instruction.offset + instruction.length;
+                match starts.get(next) {
+                    Some(_) => successors.push(index + 1),
+                    None => {
+                        return Err(self.error(
+                            instruction.offset,
+                            "Execution runs past the end of the chunk.".to_string(),
+                        ));
+                    }
+                }
+            }
+            if let Some(target) = instruction.jump {
+                successors.push(starts[target].unwrap());
+            }
+
+            for successor in successors {
+                match &mut states[successor] {
+                    None => {
+                        states[successor] = Some((after, captured.clone()));
+                        worklist.push(successor);
+                    }
+                    Some((existing, _)) if *existing != after => {
+                        return Err(self.error(
+                            instructions[successor].offset,
+                            format!(
+                                "Inconsistent stack depth, reached with both {} and {}.",
+                                existing, after
+                            ),
+                        ));
+                    }
+                    Some((_, existing)) => {
+                        if !captured.is_subset(existing) {
+                            existing.extend(&captured);
+                            worklist.push(successor);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, offset: usize) -> Result<Instruction, VerifyError> {
+        let byte = self.chunk.code[offset];
+        let opcode = OpCode::try_from(byte)
+            .map_err(|_| self.error(offset, format!("Unknown opcode {}.", byte)))?;
+
+        let mut instruction = Instruction {
+            offset,
+            length: 1,
+            pops: 0,
+            pushes: 0,
+            slots: Vec::new(),
+            captures: Vec::new(),
+            closes: false,
+            jump: None,
+            falls_through: true,
+        };
+
+        match opcode {
+            OpCode::OpReturn => {
+                instruction.pops = 1;
+                instruction.closes = true;
+                instruction.falls_through = false;
+            }
+            OpCode::OpConstant => {
+                self.constant(&mut instruction, false)?;
+                instruction.pushes = 1;
+            }
+            OpCode::OpConstantLong => {
+                self.constant(&mut instruction, true)?;
+                instruction.pushes = 1;
+            }
+            OpCode::OpNil | OpCode::OpTrue | OpCode::OpFalse => instruction.pushes = 1,
+            OpCode::OpAdd
+            | OpCode::OpSubtract
+            | OpCode::OpMultiply
+            | OpCode::OpDivide
+            | OpCode::OpEqual
+            | OpCode::OpGreater
+            | OpCode::OpLess => {
+                instruction.pops = 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpNegate | OpCode::OpNot => {
+                instruction.pops = 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpPrint | OpCode::OpPop => instruction.pops = 1,
+            OpCode::OpCloseUpvalue => {
+                instruction.pops = 1;
+                instruction.closes = true;
+            }
+            OpCode::OpDefineGlobal | OpCode::OpDefineGlobalLong => {
+                self.name(&mut instruction, opcode == OpCode::OpDefineGlobalLong)?;
+                instruction.pops = 1;
+            }
+            OpCode::OpGetGlobal | OpCode::OpGetGlobalLong => {
+                self.name(&mut instruction, opcode == OpCode::OpGetGlobalLong)?;
+                instruction.pushes = 1;
+            }
+            OpCode::OpSetGlobal | OpCode::OpSetGlobalLong => {
+                self.name(&mut instruction, opcode == OpCode::OpSetGlobalLong)?;
+                instruction.pops = 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpGetLocal | OpCode::OpGetLocalLong => {
+                let slot = self.index(&mut instruction, opcode == OpCode::OpGetLocalLong)?;
+                instruction.slots.push(slot);
+                instruction.pushes = 1;
+            }
+            OpCode::OpSetLocal | OpCode::OpSetLocalLong => {
+                let slot = self.index(&mut instruction, opcode == OpCode::OpSetLocalLong)?;
+                instruction.slots.push(slot);
+                instruction.pops = 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpGetUpvalue | OpCode::OpSetUpvalue => {
+                let slot = self.index(&mut instruction, false)?;
+                if slot >= self.function.upvalue_count {
+                    return Err(self.error(offset, format!("Upvalue {} is out of range.", slot)));
+                }
+                if opcode == OpCode::OpSetUpvalue {
+                    instruction.pops = 1;
+                }
+                instruction.pushes = 1;
+            }
+            OpCode::OpJumpIfFalse | OpCode::OpJump | OpCode::OpLoop => {
+                let operand = BigEndian::read_u16(self.operands(offset, offset + 1, 2)?) as usize;
+                instruction.length = 3;
+                let next = offset + 3;
+                let target = if opcode == OpCode::OpLoop {
+                    next.checked_sub(operand).ok_or_else(|| {
+                        self.error(offset, "Loop jumps before the chunk.".to_string())
+                    })?
+                } else {
+                    next + operand
+                };
+                instruction.jump = Some(target);
+                if opcode == OpCode::OpJumpIfFalse {
+                    instruction.pops = 1;
+                    instruction.pushes = 1;
+                } else {
+                    instruction.falls_through = false;
+                }
+            }
+            OpCode::OpCall => {
+                let arg_count = self.index(&mut instruction, false)?;
+                instruction.pops = arg_count + 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpClosure | OpCode::OpClosureLong => {
+                let constant = self.constant(&mut instruction, opcode == OpCode::OpClosureLong)?;
+                if !constant.is_function() {
+                    return Err(
+                        self.error(offset, "Closure constant isn't a function.".to_string())
+                    );
+                }
+
+                let upvalue_count = constant.as_function().upvalue_count;
+                let start = offset + instruction.length;
+                let upvalues = self.operands(offset, start, upvalue_count * 2)?;
+                for pair in upvalues.chunks(2) {
+                    let (is_local, index) = (pair[0], pair[1] as usize);
+                    match is_local {
+                        1 => {
+                            instruction.slots.push(index);
+                            instruction.captures.push(index);
+                        }
+                        0 if index < self.function.upvalue_count => {}
+                        0 => {
+                            return Err(
+                                self.error(offset, format!("Upvalue {} is out of range.", index))
+                            );
+                        }
+                        _ => {
+                            return Err(
+                                self.error(offset, "Malformed upvalue operand.".to_string())
+                            );
+                        }
+                    }
+                }
+                instruction.length += upvalue_count * 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpClass | OpCode::OpClassLong => {
+                self.name(&mut instruction, opcode == OpCode::OpClassLong)?;
+                instruction.pushes = 1;
+            }
+            OpCode::OpGetProperty | OpCode::OpGetPropertyLong => {
+                self.name(&mut instruction, opcode == OpCode::OpGetPropertyLong)?;
+                instruction.pops = 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpSetProperty | OpCode::OpSetPropertyLong => {
+                self.name(&mut instruction, opcode == OpCode::OpSetPropertyLong)?;
+                instruction.pops = 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpMethod | OpCode::OpMethodLong => {
+                self.name(&mut instruction, opcode == OpCode::OpMethodLong)?;
+                instruction.pops = 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpGetSuper | OpCode::OpGetSuperLong => {
+                self.name(&mut instruction, opcode == OpCode::OpGetSuperLong)?;
+                instruction.pops = 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpInherit => {
+                instruction.pops = 2;
+                instruction.pushes = 1;
+            }
+            OpCode::OpInvoke | OpCode::OpInvokeLong => {
+                self.name(&mut instruction, opcode == OpCode::OpInvokeLong)?;
+                let arg_count = self.index(&mut instruction, false)?;
+                instruction.pops = arg_count + 1;
+                instruction.pushes = 1;
+            }
+            OpCode::OpSuperInvoke | OpCode::OpSuperInvokeLong => {
+                self.name(&mut instruction, opcode == OpCode::OpSuperInvokeLong)?;
+                let arg_count = self.index(&mut instruction, false)?;
+                instruction.pops = arg_count + 2;
+                instruction.pushes = 1;
+            }
+        }
+
+        Ok(instruction)
+    }
+
+    // Reads a one or three byte operand following the instruction so far.
+    fn index(&self, instruction: &mut Instruction, long: bool) -> Result<usize, VerifyError> {
+        let start = instruction.offset + instruction.length;
+        let index = if long {
+            instruction.length += 3;
+            LittleEndian::read_u24(self.operands(instruction.offset, start, 3)?) as usize
+        } else {
+            instruction.length += 1;
+            self.operands(instruction.offset, start, 1)?[0] as usize
+        };
+        Ok(index)
+    }
+
+    fn constant(
+        &self,
+        instruction: &mut Instruction,
+        long: bool,
+    ) -> Result<&'a Value, VerifyError> {
+        let index = self.index(instruction, long)?;
+        self.chunk.constants.values.get(index).ok_or_else(|| {
+            self.error(
+                instruction.offset,
+                format!("Constant {} is out of range.", index),
+            )
+        })
+    }
+
+    // A constant naming a global, property, method or class.
+    fn name(&self, instruction: &mut Instruction, long: bool) -> Result<(), VerifyError> {
+        if !self.constant(instruction, long)?.is_string() {
+            return Err(self.error(
+                instruction.offset,
+                "Name constant isn't a string.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // The `count` operand bytes at `start` of the instruction at `offset`.
+    fn operands(&self, offset: usize, start: usize, count: usize) -> Result<&'a [u8], VerifyError> {
+        self.chunk
+            .code
+            .get(start..start + count)
+            .ok_or_else(|| self.error(offset, "Instruction is missing operands.".to_string()))
+    }
+
+    fn error(&self, offset: usize, message: String) -> VerifyError {
+        VerifyError {
+            function: self.function.name.clone(),
+            offset,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn script(code: &[u8], constants: Vec<Value>) -> Function {
+        let mut function = Function::new();
+        for byte in code {
+            function.chunk.write_chunk(*byte, 1);
+        }
+        for constant in constants {
+            function.chunk.add_constant(constant);
+        }
+        function
+    }
+
+    #[test]
+    fn accepts_compiled_samples() {
+        let samples = format!("{}/../runner/samples", env!("CARGO_MANIFEST_DIR"));
+        for entry in std::fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let function = compiler::compile(&source).unwrap();
+
+            assert_eq!(verify(&function), Ok(()), "{}", path.display());
+        }
+    }
+
+    #[rstest]
+    #[case(&[200], "Unknown opcode 200.")]
+    #[case(&[OpCode::OpConstant as u8], "Instruction is missing operands.")]
+    #[case(&[OpCode::OpConstant as u8, 1, OpCode::OpReturn as u8], "Constant 1 is out of range.")]
+    #[case(&[OpCode::OpGetGlobal as u8, 0, OpCode::OpReturn as u8], "Name constant isn't a string.")]
+    #[case(&[OpCode::OpGetLocal as u8, 1, OpCode::OpReturn as u8], "Stack slot 1 is out of range.")]
+    #[case(&[OpCode::OpGetUpvalue as u8, 0, OpCode::OpReturn as u8], "Upvalue 0 is out of range.")]
+    #[case(&[OpCode::OpPop as u8, OpCode::OpNil as u8, OpCode::OpReturn as u8], "Stack underflow.")]
+    #[case(&[OpCode::OpNil as u8], "Execution runs past the end of the chunk.")]
+    #[case(
+        &[OpCode::OpJump as u8, 0, 1, OpCode::OpConstant as u8, 0, OpCode::OpReturn as u8],
+        "Jump to 4 doesn't land on an instruction."
+    )]
+    #[case(
+        &[OpCode::OpLoop as u8, 0, 4, OpCode::OpNil as u8, OpCode::OpReturn as u8],
+        "Loop jumps before the chunk."
+    )]
+    #[case(
+        &[
+            OpCode::OpTrue as u8,
+            OpCode::OpJumpIfFalse as u8, 0, 1,
+            OpCode::OpNil as u8,
+            OpCode::OpReturn as u8,
+        ],
+        "Inconsistent stack depth, reached with both 2 and 3."
+    )]
+    fn rejects_malformed_chunks(#[case] code: &[u8], #[case] message: &str) {
+        let function = script(code, vec![Value::Number(1)]);
+
+        assert_eq!(verify(&function).unwrap_err().message, message);
+    }
+
+    #[rstest]
+    #[case(&[OpCode::OpClass as u8, 0, OpCode::OpNil as u8, OpCode::OpInherit as u8], "Only classes can inherit.")]
+    #[case(&[OpCode::OpNil as u8, OpCode::OpClass as u8, 0, OpCode::OpInherit as u8], "Superclass must be a class.")]
+    #[case(&[OpCode::OpNil as u8, OpCode::OpNil as u8, OpCode::OpGetSuper as u8, 0], "Superclass must be a class.")]
+    #[case(&[OpCode::OpNil as u8, OpCode::OpNil as u8, OpCode::OpSuperInvoke as u8, 0, 0], "Superclass must be a class.")]
+    #[case(&[OpCode::OpNil as u8, OpCode::OpNil as u8, OpCode::OpMethod as u8, 0], "Methods can only be defined on classes.")]
+    #[case(&[OpCode::OpClass as u8, 0, OpCode::OpNil as u8, OpCode::OpMethod as u8, 0], "Method must be a closure.")]
+    fn leaves_operand_types_to_the_vm(#[case] code: &[u8], #[case] message: &str) {
+        let code = [code, &[OpCode::OpReturn as u8]].concat();
+        let function = script(&code, vec![Value::from("A")]);
+        assert_eq!(verify(&function), Ok(()));
+
+        let mut vm = crate::VM::new();
+        vm.set_error_output(Box::new(std::io::sink()));
+        assert_eq!(vm.interpret(function).unwrap_err().message, message);
+    }
+
+    #[test]
+    fn rejects_popping_captured_slots() {
+        let source = "class A { init(x) { this.x = x; } m() { return this.x; } }\n\
+                      class B < A { m() { return super.m() + 1; } }\n\
+                      print B(2).m();";
+        let mut function = compiler::compile(source).unwrap();
+        // Makes the closure at offset 26 capture the class below the method
+        // instead of `super`, OP_METHOD then pops it.
+        assert_eq!(function.chunk.code[28..30], [1, 1]);
+        function.chunk.code[29] = 2;
+
+        let error = verify(&function).unwrap_err();
+        assert_eq!(error.offset, 30);
+        assert_eq!(
+            error.message,
+            "Captured stack slot 2 is popped without being closed."
+        );
+
+        // Unverified, the VM reports the dangling upvalue.
+        let mut vm = crate::VM::new();
+        vm.set_error_output(Box::new(std::io::sink()));
+        assert_eq!(
+            vm.interpret(function).unwrap_err().message,
+            "Upvalue refers past the top of the stack."
+        );
+    }
+
+    #[test]
+    fn reports_the_failing_function() {
+        let mut inner = script(&[OpCode::OpPop as u8, OpCode::OpReturn as u8], vec![]);
+        inner.name = Some("inner".to_string());
+        let function = script(
+            &[OpCode::OpClosure as u8, 0, OpCode::OpReturn as u8],
+            vec![Value::new_obj_function(inner)],
+        );
+
+        let error = verify(&function).unwrap_err();
+
+        assert_eq!(error.to_string(), "[offset 0] in inner(): Stack underflow.");
+    }
+}
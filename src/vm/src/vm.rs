@@ -1,52 +1,399 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    ops::RangeInclusive,
+    rc::Rc,
+};
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
-use common::{chunk::Chunk, opcode::OpCode, value::Value};
+use common::{
+    chunk::Chunk,
+    gc::{Gc, Handle, Heap},
+    intern::{StringKey, StringMap},
+    object::{BoundMethod, Class, Closure, Function, Instance, Native, Object, Upvalue},
+    opcode::OpCode,
+    value::Value,
+};
 
-use crate::{debug, stack::Stack};
+use crate::{
+    debug,
+    debugger::{Debugger, Resume},
+    error::{RuntimeError, TraceFrame},
+    native::{self, NativeFn},
+    stack::Stack,
+};
 
-const DEBUG_TRACE_EXECUTION: bool = false;
-pub const STACK_INITIAL_SIZE: usize = 256;
+pub const FRAMES_MAX: usize = 64;
+pub const STACK_INITIAL_SIZE: usize = FRAMES_MAX * 256;
 
-pub enum RunResult {
-    Ok,
-    CompileError,
-    RuntimeError, // Unknown,
+pub type RunResult = Result<(), RuntimeError>;
+
+pub struct CallFrame {
+    pub closure: Gc<Object>,
+    // Offset of the next byte to execute in the closure's chunk.
+    pub ip: usize,
+    pub slot_base: usize,
 }
 
+impl CallFrame {
+    pub fn closure(&self) -> &Closure {
+        self.closure.as_closure()
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.closure().function.as_function().chunk
+    }
+
+    pub fn offset(&self) -> usize {
+        self.ip
+    }
+}
+
+// Values handed to the host are wrapped in a `Handle`, everything that can
+// point into the heap without one stays private.
 pub struct VM {
-    pub stack: Stack,
-    pub globals: HashMap<String, Value>,
+    frames: Vec<CallFrame>,
+    stack: Stack,
+    globals: StringMap<Value>,
+    open_upvalues: Vec<Gc<Object>>,
+    heap: Heap,
+    natives: Vec<NativeFn>,
+    instruction_start: usize,
+    output: Box<dyn Write>,
+    error_output: Box<dyn Write>,
+    trace_output: Option<Box<dyn Write>>,
+    trace_lines: Option<RangeInclusive<i32>>,
+    debugger: Option<Debugger>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new() -> Self {
-        VM {
+        let mut vm = VM {
+            frames: Vec::with_capacity(FRAMES_MAX),
             stack: Stack::new(Some(STACK_INITIAL_SIZE)),
-            globals: HashMap::new(),
+            globals: StringMap::default(),
+            open_upvalues: Vec::new(),
+            heap: Heap::new(),
+            instruction_start: 0,
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            trace_output: None,
+            trace_lines: None,
+            debugger: None,
+            natives: Vec::new(),
+        };
+
+        vm.define_native("clock", 0, native::clock);
+
+        vm
+    }
+
+    // Installs `function` as a global named `name`, callable from Lox code
+    // with exactly `arity` arguments.
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.natives.push(function);
+        let native = Native::new(name.to_owned(), arity, self.natives.len() - 1);
+        self.globals
+            .insert(StringKey::new(name), Value::new_obj_native(native));
+    }
+
+    // Where `print` statements write to.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Where runtime errors are reported to.
+    pub fn set_error_output(&mut self, error_output: Box<dyn Write>) {
+        self.error_output = error_output;
+    }
+
+    // Traces every executed instruction together with the stack to `output`,
+    // or turns tracing off when `None`.
+    pub fn set_trace_output(&mut self, output: Option<Box<dyn Write>>) {
+        self.trace_output = output;
+    }
+
+    // Limits tracing to instructions compiled from these source lines.
+    pub fn set_trace_lines(&mut self, lines: Option<RangeInclusive<i32>>) {
+        self.trace_lines = lines;
+    }
+
+    // After a collection, the next one happens once the heap has grown by
+    // this factor. Defaults to 2.
+    pub fn set_gc_growth_factor(&mut self, growth_factor: usize) {
+        self.heap.set_growth_factor(growth_factor);
+    }
+
+    // Collects garbage on every allocation, to find objects that aren't
+    // rooted while they are still in use.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    // Hands control to `debugger` at breakpoints and while stepping, or
+    // removes the debugger when `None`.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    pub fn interpret(&mut self, function: Function) -> RunResult {
+        let function = Value::new_obj_function(function);
+        let closure = self.alloc(Object::Closure(Closure::new(function.as_rc().clone())));
+        self.stack.push(closure.clone());
+
+        let result = self.call_value(closure, 0).and_then(|_| self.run(0));
+        match result {
+            Ok(()) => {
+                // The script's implicit `nil` return value.
+                self.stack.pop();
+                Ok(())
+            }
+            Err(error) => {
+                self.reset_stack();
+                // Nothing sensible left to report to if the error output fails.
+                let _ = writeln!(self.error_output, "{}", error);
+                Err(error)
+            }
         }
     }
 
-    pub fn run(&mut self, chunk: &Chunk) -> RunResult {
-        let mut ip = &chunk.code[0];
+    pub fn get_global(&self, name: &str) -> Option<Handle> {
+        let value = self.globals.get(&StringKey::new(name))?;
+        Some(self.heap.pin(value.clone()))
+    }
+
+    // All globals, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Handle)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.as_str().to_string(), self.heap.pin(value.clone())))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.globals.insert(StringKey::new(name), value.into());
+    }
+
+    // Calls the global function, class or native `name` and returns its
+    // result. Unlike `interpret`, errors are only returned, not reported.
+    pub fn call_global(&mut self, name: &str, args: &[Handle]) -> Result<Handle, RuntimeError> {
+        let callee = match self.globals.get(&StringKey::new(name)) {
+            Some(callee) => callee.clone(),
+            None => return Err(self.runtime_error(format!("Undefined variable '{}'.", name))),
+        };
+        let args = self.host_values(args)?;
+
+        let base_frames = self.frames.len();
+        let base_stack = self.stack.len();
+        let instruction_start = self.instruction_start;
+        self.stack.push(callee.clone());
+        for arg in &args {
+            self.stack.push(arg.clone());
+        }
+
+        let mut result = self.call_value(callee, args.len());
+        if result.is_ok() && self.frames.len() > base_frames {
+            result = self.run(base_frames);
+        }
+        self.instruction_start = instruction_start;
+        if let Err(error) = result {
+            // Like `call_function`, only what the call pushed is unwound.
+            let _ = self.close_upvalues(base_stack);
+            self.frames.truncate(base_frames);
+            self.stack.truncate(base_stack);
+            return Err(error);
+        }
+
+        Ok(self.heap.pin(self.stack.pop().unwrap()))
+    }
+
+    // Calls `function` with `args` on top of whatever is running, for example
+    // to evaluate code while paused in the debugger. A runtime error unwinds
+    // the call but leaves the running program as it was.
+    pub fn call_function(
+        &mut self,
+        function: Function,
+        args: &[Handle],
+    ) -> Result<Handle, RuntimeError> {
+        let args = self.host_values(args)?;
+        self.call_compiled(function, None, &args)
+    }
+
+    // Like `call_function`, for a function compiled as a method of some class
+    // so that `receiver` is its `this`.
+    pub fn call_method(
+        &mut self,
+        function: Function,
+        receiver: &Handle,
+        args: &[Handle],
+    ) -> Result<Handle, RuntimeError> {
+        let receiver = self.host_values(std::slice::from_ref(receiver))?.pop();
+        let args = self.host_values(args)?;
+        self.call_compiled(function, receiver.as_ref(), &args)
+    }
+
+    // The values of handles passed in by the host.
+    fn host_values(&mut self, handles: &[Handle]) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(handles.len());
+        for handle in handles {
+            match self.heap.value_of(handle) {
+                Some(value) => values.push(value),
+                None => return Err(self.runtime_error("Value belongs to another VM.".to_string())),
+            }
+        }
+        Ok(values)
+    }
+
+    fn call_compiled(
+        &mut self,
+        function: Function,
+        receiver: Option<&Value>,
+        args: &[Value],
+    ) -> Result<Handle, RuntimeError> {
+        let base_frames = self.frames.len();
+        let base_stack = self.stack.len();
+        let instruction_start = self.instruction_start;
+
+        let function = Value::new_obj_function(function);
+        let closure = self.alloc(Object::Closure(Closure::new(function.as_rc().clone())));
+        // Slot 0 of the frame, which methods see as `this`.
+        self.stack
+            .push(receiver.cloned().unwrap_or_else(|| closure.clone()));
+        for arg in args {
+            self.stack.push(arg.clone());
+        }
+
+        let result = self
+            .call_value(closure, args.len())
+            .and_then(|_| self.run(base_frames));
+        self.instruction_start = instruction_start;
+        match result {
+            Ok(()) => Ok(self.heap.pin(self.stack.pop().unwrap())),
+            Err(mut error) => {
+                error
+                    .trace
+                    .truncate(error.trace.len().saturating_sub(base_frames));
+                // The call already failed, a second error closing its
+                // upvalues adds nothing.
+                let _ = self.close_upvalues(base_stack);
+                self.frames.truncate(base_frames);
+                self.stack.truncate(base_stack);
+                Err(error)
+            }
+        }
+    }
+
+    // The frames of the running program, innermost first, with the offset of
+    // the instruction each one is executing.
+    pub fn call_stack(&self) -> Vec<TraceFrame> {
+        let mut trace = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            // Callers have already moved past their call instruction. A frame
+            // paused by the debugger hasn't, but `call_function` leaves those
+            // out of its errors.
+            let offset = if i == self.frames.len() - 1 {
+                self.instruction_start
+            } else {
+                frame.offset().saturating_sub(1)
+            };
+            let function = frame.closure().function.as_function();
+            trace.push(TraceFrame {
+                function: function.name.clone(),
+                offset,
+                line: function.chunk.lines[offset],
+            });
+        }
+        trace
+    }
+
+    // The named locals in scope in `frame`, counted like `call_stack`, in
+    // slot order. Shadowed locals are included before the ones hiding them.
+    pub fn locals(&self, frame: usize) -> Vec<(String, Handle)> {
+        let index = match self.frames.len().checked_sub(frame + 1) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let offset = self.call_stack()[frame].offset;
+        let slot_base = self.frames[index].slot_base;
+
+        let mut locals: Vec<_> = self.frames[index]
+            .chunk()
+            .locals
+            .iter()
+            .filter(|local| local.start <= offset && offset < local.end)
+            .filter(|local| slot_base + local.slot < self.stack.len())
+            .collect();
+        locals.sort_by_key(|local| local.slot);
+
+        locals
+            .into_iter()
+            .map(|local| {
+                let value = self.stack.get_at(slot_base + local.slot).clone();
+                (local.name.clone(), self.heap.pin(value))
+            })
+            .collect()
+    }
+
+    // Executes until the frame count drops back to `base_frames`, leaving the
+    // returned value on top of the stack.
+    fn run(&mut self, base_frames: usize) -> RunResult {
+        let mut ip = self.frame().ip;
+        let result = self.execute(base_frames, &mut ip);
+        if result.is_err() {
+            self.frame_mut().ip = ip;
+        }
+        result
+    }
+
+    // The running frame's function and ip are kept in locals rather than read
+    // through `frames` for every byte. `ip` is only written back to the frame
+    // before anything that looks at other frames: calls, the debugger and
+    // errors.
+    fn execute(&mut self, base_frames: usize, ip: &mut usize) -> RunResult {
+        let mut function = self.frame().closure().function.clone();
         loop {
-            if DEBUG_TRACE_EXECUTION {
-                print!("    ");
-                self.stack.print_stack();
-                debug::disassemble_instruction(chunk, unsafe {
-                    (ip as *const u8).offset_from((&chunk.code[0]) as *const u8) as i32
-                });
+            let chunk = &function.as_function().chunk;
+            self.instruction_start = *ip;
+            if self.trace_output.is_some() && self.trace().is_err() {
+                return Err(self.runtime_error("Unable to write trace output.".to_string()));
+            }
+
+            if self.debugger.is_some() {
+                self.frame_mut().ip = *ip;
+                if !self.debug_hook() {
+                    return Err(self.runtime_error("Stopped by the debugger.".to_string()));
+                }
             }
 
-            let instruction = self.read_byte(&mut ip);
+            let instruction = read_byte(&chunk.code, ip);
+            let instruction = match OpCode::try_from(instruction) {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    let message = format!("Unknown opcode {}.", instruction);
+                    return Err(self.runtime_error(message));
+                }
+            };
 
-            match OpCode::try_from(instruction).unwrap() {
+            match instruction {
                 OpCode::OpConstant => {
-                    let constant = self.read_constant(&mut ip, chunk);
+                    let constant = read_constant(chunk, ip);
                     self.stack.push(constant);
                 }
                 OpCode::OpConstantLong => {
-                    let constant = self.read_long_constant(&mut ip, chunk);
+                    let constant = read_long_constant(chunk, ip);
                     self.stack.push(constant);
                 }
                 OpCode::OpNil => {
@@ -62,75 +409,111 @@ impl VM {
                     self.stack.pop();
                 }
                 OpCode::OpGetLocal => {
-                    let slot = self.read_byte(&mut ip);
-                    self.stack.push(self.stack.get_at(slot as usize).clone());
+                    let slot = self.frame().slot_base + read_byte(&chunk.code, ip) as usize;
+                    self.stack.push(self.stack.get_at(slot).clone());
                 }
                 OpCode::OpGetLocalLong => {
-                    let slot = self.read_long(&mut ip);
-                    self.stack.push(self.stack.get_at(slot as usize).clone());
+                    let slot = self.frame().slot_base + read_long(&chunk.code, ip) as usize;
+                    self.stack.push(self.stack.get_at(slot).clone());
                 }
                 OpCode::OpSetLocal => {
-                    let slot = self.read_byte(&mut ip);
-                    self.stack.set_at(slot as usize, self.peek(0).clone());
+                    let slot = self.frame().slot_base + read_byte(&chunk.code, ip) as usize;
+                    self.stack.set_at(slot, self.peek(0).clone());
                 }
                 OpCode::OpSetLocalLong => {
-                    let slot = self.read_long(&mut ip);
-                    self.stack.push(self.stack.get_at(slot as usize).clone());
+                    let slot = self.frame().slot_base + read_long(&chunk.code, ip) as usize;
+                    self.stack.set_at(slot, self.peek(0).clone());
                 }
-                OpCode::OpGetGlobal => {
-                    let v = self.read_constant(&mut ip, chunk);
-                    let name = v.as_string();
-                    let value = match self.globals.get(name) {
-                        Some(v) => v,
-                        None => {
-                            self.runtime_error(format!("Undefined variable '{}'.", "name"));
-                            return RunResult::RuntimeError;
-                        }
+                OpCode::OpGetUpvalue => {
+                    let slot = read_byte(&chunk.code, ip) as usize;
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    let upvalue = upvalue.as_upvalue().borrow();
+                    let value = match &upvalue.closed {
+                        Some(value) => Some(value.clone()),
+                        None => self.stack.get(upvalue.location).cloned(),
                     };
-
-                    self.stack.push(value.clone());
+                    drop(upvalue);
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.upvalue_past_top()),
+                    }
                 }
-                OpCode::OpGetGlobalLong => {
-                    let v = self.read_long_constant(&mut ip, chunk);
-                    let name = v.as_string();
-                    let value = match self.globals.get(name) {
-                        Some(val) => val,
+                OpCode::OpSetUpvalue => {
+                    let slot = read_byte(&chunk.code, ip) as usize;
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.as_upvalue().borrow_mut();
+                    match upvalue.closed {
+                        Some(_) => upvalue.closed = Some(value),
+                        None if upvalue.location < self.stack.len() => {
+                            self.stack.set_at(upvalue.location, value)
+                        }
                         None => {
-                            self.runtime_error(format!("Undefined variable '{}'.", name));
-                            return RunResult::RuntimeError;
+                            drop(upvalue);
+                            return Err(self.upvalue_past_top());
                         }
-                    };
-
-                    self.stack.push(value.clone());
+                    }
+                }
+                OpCode::OpGetProperty => {
+                    let name = read_constant(chunk, ip);
+                    self.get_property(name.as_string())?;
+                }
+                OpCode::OpGetPropertyLong => {
+                    let name = read_long_constant(chunk, ip);
+                    self.get_property(name.as_string())?;
+                }
+                OpCode::OpSetProperty => {
+                    let name = read_constant(chunk, ip);
+                    self.set_property(name.as_string())?;
+                }
+                OpCode::OpSetPropertyLong => {
+                    let name = read_long_constant(chunk, ip);
+                    self.set_property(name.as_string())?;
+                }
+                OpCode::OpGetSuper => {
+                    let name = read_constant(chunk, ip);
+                    let superclass = self.pop_superclass()?;
+                    self.bind_method(superclass, name.as_string())?;
+                }
+                OpCode::OpGetSuperLong => {
+                    let name = read_long_constant(chunk, ip);
+                    let superclass = self.pop_superclass()?;
+                    self.bind_method(superclass, name.as_string())?;
+                }
+                OpCode::OpGetGlobal => {
+                    let name = StringKey::from_value(&read_constant(chunk, ip));
+                    self.get_global_variable(name)?;
+                }
+                OpCode::OpGetGlobalLong => {
+                    let name = StringKey::from_value(&read_long_constant(chunk, ip));
+                    self.get_global_variable(name)?;
                 }
                 OpCode::OpDefineGlobal => {
-                    let v = self.read_constant(&mut ip, chunk);
-                    let name = v.as_string().to_owned();
+                    let name = StringKey::from_value(&read_constant(chunk, ip));
                     self.globals.insert(name, self.peek(0).clone());
 
                     self.stack.pop();
                 }
                 OpCode::OpDefineGlobalLong => {
-                    let v = self.read_long_constant(&mut ip, chunk);
-                    let name = v.as_string().to_owned();
+                    let name = StringKey::from_value(&read_long_constant(chunk, ip));
                     self.globals.insert(name, self.peek(0).clone());
 
                     self.stack.pop();
                 }
                 OpCode::OpSetGlobal => {
-                    let name = self.read_constant(&mut ip, chunk).as_string().to_owned();
+                    let name = StringKey::from_value(&read_constant(chunk, ip));
                     if !self.globals.contains_key(&name) {
-                        self.runtime_error(format!("Undefined variable '{}'.", name));
-                        return RunResult::RuntimeError;
+                        let message = format!("Undefined variable '{}'.", name.as_str());
+                        return Err(self.runtime_error(message));
                     }
 
                     self.globals.insert(name, self.peek(0).clone());
                 }
                 OpCode::OpSetGlobalLong => {
-                    let name = self.read_long_constant(&mut ip, chunk).as_string().to_owned();
+                    let name = StringKey::from_value(&read_long_constant(chunk, ip));
                     if !self.globals.contains_key(&name) {
-                        self.runtime_error(format!("Undefined variable '{}'.", name));
-                        return RunResult::RuntimeError;
+                        let message = format!("Undefined variable '{}'.", name.as_str());
+                        return Err(self.runtime_error(message));
                     }
 
                     self.globals.insert(name, self.peek(0).clone());
@@ -142,53 +525,55 @@ impl VM {
                 }
                 OpCode::OpGreater => {
                     if !self.peek(0).is_number() || !self.peek(1).is_number() {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
                     }
 
-                    self.binary_op(|a, b| Value::new_bool(a.as_number() > b.as_number()));
+                    self.binary_op(|a, b| Value::new_bool(a > b), |a, b| Value::new_bool(a > b));
                 }
                 OpCode::OpLess => {
                     if !self.peek(0).is_number() || !self.peek(1).is_number() {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
                     }
 
-                    self.binary_op(|a, b| Value::new_bool(a.as_number() < b.as_number()));
+                    self.binary_op(|a, b| Value::new_bool(a < b), |a, b| Value::new_bool(a < b));
                 }
                 OpCode::OpAdd => {
                     if self.peek(0).is_string() && self.peek(1).is_string() {
                         self.concatenate()
                     } else if self.peek(0).is_number() && self.peek(1).is_number() {
-                        self.binary_op(|a, b| Value::new_number(a.as_number() + b.as_number()));
+                        self.arithmetic_op(i64::checked_add, |a, b| a + b)?;
                     } else {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
                     }
                 }
                 OpCode::OpSubtract => {
                     if !self.peek(0).is_number() || !self.peek(1).is_number() {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
                     }
 
-                    self.binary_op(|a, b| Value::new_number(a.as_number() - b.as_number()));
+                    self.arithmetic_op(i64::checked_sub, |a, b| a - b)?;
                 }
                 OpCode::OpMultiply => {
                     if !self.peek(0).is_number() || !self.peek(1).is_number() {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
                     }
 
-                    self.binary_op(|a, b| Value::new_number(a.as_number() * b.as_number()));
+                    self.arithmetic_op(i64::checked_mul, |a, b| a * b)?;
                 }
                 OpCode::OpDivide => {
                     if !self.peek(0).is_number() || !self.peek(1).is_number() {
-                        self.runtime_error("Operands must be numbers.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operands must be numbers.".to_string()));
+                    }
+
+                    if self.peek(0).is_int()
+                        && self.peek(1).is_int()
+                        && self.peek(0).as_number() == 0
+                    {
+                        return Err(self.runtime_error("Division by zero.".to_string()));
                     }
 
-                    self.binary_op(|a, b| Value::new_number(a.as_number() / b.as_number()));
+                    // Only `i64::MIN / -1` is left to overflow.
+                    self.arithmetic_op(i64::checked_div, |a, b| a / b)?;
                 }
                 OpCode::OpNot => {
                     let popped = self.stack.pop().unwrap();
@@ -196,49 +581,579 @@ impl VM {
                 }
                 OpCode::OpNegate => {
                     if !self.peek(0).is_number() {
-                        self.runtime_error("Operand must be a number.".to_string());
-                        return RunResult::RuntimeError;
+                        return Err(self.runtime_error("Operand must be a number.".to_string()));
+                    }
+                    let value_to_negate = self.stack.pop().unwrap();
+                    if value_to_negate.is_int() {
+                        let negated = match value_to_negate.as_number().checked_neg() {
+                            Some(negated) => negated,
+                            None => return Err(self.runtime_error("Integer overflow.".to_string())),
+                        };
+                        self.stack.push(Value::new_number(negated));
+                    } else {
+                        self.stack
+                            .push(Value::new_float(-value_to_negate.as_float()));
                     }
-                    let value_to_negate = self.stack.pop().unwrap().as_number();
-                    self.stack.push(Value::new_number(-value_to_negate));
                 }
                 OpCode::OpPrint => {
-                    self.stack.pop().unwrap().print_value();
-                    println!();
+                    let value = self.stack.pop().unwrap();
+                    if writeln!(self.output, "{:?}", value).is_err() {
+                        return Err(self.runtime_error("Unable to write output.".to_string()));
+                    }
                 }
                 OpCode::OpJumpIfFalse => {
-                    let offset = self.read_short(&mut ip);
+                    let offset = read_short(&chunk.code, ip) as usize;
                     if self.peek(0).is_falsey() {
-                        let ptr = ip as *const u8;
-                        ip = unsafe { &mut ptr.offset(offset as isize).as_ref().unwrap() };
+                        self.jump_forward(&chunk.code, ip, offset)?;
                     }
                 }
                 OpCode::OpJump => {
-                    let offset = self.read_short(&mut ip);
-                    let ptr = ip as *const u8;
-                    ip = unsafe { ptr.offset(offset as isize).as_ref().unwrap() };
+                    let offset = read_short(&chunk.code, ip) as usize;
+                    self.jump_forward(&chunk.code, ip, offset)?;
                 }
                 OpCode::OpLoop => {
-                    let offset = self.read_short(&mut ip);
-                    let ptr = ip as *const u8;
-                    ip = unsafe { ptr.offset(-(offset as isize)).as_ref().unwrap() };
+                    let offset = read_short(&chunk.code, ip) as usize;
+                    match ip.checked_sub(offset) {
+                        Some(target) => *ip = target,
+                        None => {
+                            let message = "Loop jumps before the chunk.".to_string();
+                            return Err(self.runtime_error(message));
+                        }
+                    }
+                }
+                OpCode::OpCall => {
+                    let arg_count = read_byte(&chunk.code, ip) as usize;
+                    self.frame_mut().ip = *ip;
+                    self.call_value(self.peek(arg_count).clone(), arg_count)?;
+                    (function, *ip) = self.top_frame();
+                }
+                OpCode::OpInvoke => {
+                    let method = read_constant(chunk, ip);
+                    let arg_count = read_byte(&chunk.code, ip) as usize;
+                    self.frame_mut().ip = *ip;
+                    self.invoke(method.as_string(), arg_count)?;
+                    (function, *ip) = self.top_frame();
+                }
+                OpCode::OpInvokeLong => {
+                    let method = read_long_constant(chunk, ip);
+                    let arg_count = read_byte(&chunk.code, ip) as usize;
+                    self.frame_mut().ip = *ip;
+                    self.invoke(method.as_string(), arg_count)?;
+                    (function, *ip) = self.top_frame();
+                }
+                OpCode::OpSuperInvoke => {
+                    let method = read_constant(chunk, ip);
+                    let arg_count = read_byte(&chunk.code, ip) as usize;
+                    let superclass = self.pop_superclass()?;
+                    self.frame_mut().ip = *ip;
+                    self.invoke_from_class(superclass, method.as_string(), arg_count)?;
+                    (function, *ip) = self.top_frame();
+                }
+                OpCode::OpSuperInvokeLong => {
+                    let method = read_long_constant(chunk, ip);
+                    let arg_count = read_byte(&chunk.code, ip) as usize;
+                    let superclass = self.pop_superclass()?;
+                    self.frame_mut().ip = *ip;
+                    self.invoke_from_class(superclass, method.as_string(), arg_count)?;
+                    (function, *ip) = self.top_frame();
+                }
+                OpCode::OpClosure => {
+                    let function = read_constant(chunk, ip);
+                    self.closure(function, &chunk.code, ip);
+                }
+                OpCode::OpClosureLong => {
+                    let function = read_long_constant(chunk, ip);
+                    self.closure(function, &chunk.code, ip);
+                }
+                OpCode::OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1)?;
+                    self.stack.pop();
+                }
+                OpCode::OpClass => {
+                    let name = read_constant(chunk, ip).as_string().to_owned();
+                    let class = self.alloc(Object::Class(RefCell::new(Class::new(name))));
+                    self.stack.push(class);
+                }
+                OpCode::OpClassLong => {
+                    let name = read_long_constant(chunk, ip).as_string().to_owned();
+                    let class = self.alloc(Object::Class(RefCell::new(Class::new(name))));
+                    self.stack.push(class);
+                }
+                OpCode::OpInherit => {
+                    if !self.peek(1).is_class() {
+                        return Err(self.runtime_error("Superclass must be a class.".to_string()));
+                    }
+                    if !self.peek(0).is_class() {
+                        return Err(self.runtime_error("Only classes can inherit.".to_string()));
+                    }
+
+                    let methods = self.peek(1).as_obj().as_class().borrow().methods.clone();
+                    self.peek(0)
+                        .as_obj()
+                        .as_class()
+                        .borrow_mut()
+                        .methods
+                        .extend(methods);
+                    self.stack.pop(); // Subclass.
+                }
+                OpCode::OpMethod => {
+                    let name = read_constant(chunk, ip);
+                    self.define_method(name.as_string())?;
+                }
+                OpCode::OpMethodLong => {
+                    let name = read_long_constant(chunk, ip);
+                    self.define_method(name.as_string())?;
                 }
                 OpCode::OpReturn => {
-                    // Exit interpreter.
-                    return RunResult::Ok;
+                    let result = self.stack.pop().unwrap();
+                    self.close_upvalues(self.frame().slot_base)?;
+                    let frame = self.frames.pop().unwrap();
+
+                    self.stack.truncate(frame.slot_base);
+                    self.stack.push(result);
+
+                    if self.frames.len() == base_frames {
+                        return Ok(());
+                    }
+                    (function, *ip) = self.top_frame();
                 }
             }
         }
     }
 
-    fn binary_op(&mut self, callback: fn(Value, Value) -> Value) {
+    // The chunk running in `frame`, counted like `call_stack`.
+    pub fn chunk(&self, frame: usize) -> Option<&Chunk> {
+        let index = self.frames.len().checked_sub(frame + 1)?;
+        Some(self.frames[index].chunk())
+    }
+
+    // Prints the value stack, bottom first.
+    pub fn write_stack(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.stack.write_to(out)
+    }
+
+    // The named upvalues of the closure running in `frame`, counted like
+    // `call_stack`.
+    pub fn upvalues(&self, frame: usize) -> Vec<(String, Handle)> {
+        let index = match self.frames.len().checked_sub(frame + 1) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let frame = &self.frames[index];
+
+        frame
+            .chunk()
+            .upvalues
+            .iter()
+            .zip(&frame.closure().upvalues)
+            .map(|(name, upvalue)| {
+                let upvalue = upvalue.as_upvalue().borrow();
+                let value = match &upvalue.closed {
+                    Some(value) => value.clone(),
+                    None => self.stack.get_at(upvalue.location).clone(),
+                };
+                (name.clone(), self.heap.pin(value))
+            })
+            .collect()
+    }
+
+    fn trace(&mut self) -> io::Result<()> {
+        let frame = self.frames.last().unwrap();
+        let line = frame.chunk().lines[self.instruction_start];
+        if let Some(lines) = &self.trace_lines {
+            if !lines.contains(&line) {
+                return Ok(());
+            }
+        }
+
+        let output = self.trace_output.as_mut().unwrap();
+        write!(output, "    ")?;
+        self.stack.write_to(output)?;
+        writeln!(
+            output,
+            "{}",
+            debug::disassemble_instruction(frame.chunk(), self.instruction_start)
+        )
+    }
+
+    // Gives the debugger a chance to pause before the instruction at
+    // `instruction_start`. Returns false if the program should stop.
+    fn debug_hook(&mut self) -> bool {
+        let depth = self.frames.len();
+        let line = self.frame().chunk().lines[self.instruction_start];
+        let (mut handler, reason) = match self.debugger.as_mut().unwrap().check(depth, line) {
+            Some(pause) => pause,
+            None => return true,
+        };
+
+        let resume = handler.paused(self, reason);
+        match self.debugger.as_mut() {
+            Some(debugger) => debugger.resume(handler, resume, depth),
+            // The handler removed the debugger.
+            None => resume != Resume::Stop,
+        }
+    }
+
+    // Like the check in `OpLoop`, a forward jump has to stay inside the chunk.
+    fn jump_forward(&mut self, code: &[u8], ip: &mut usize, offset: usize) -> RunResult {
+        let target = *ip + offset;
+        if target >= code.len() {
+            return Err(self.runtime_error("Jump runs past the end of the chunk.".to_string()));
+        }
+        *ip = target;
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    // What `execute` keeps in locals for the top frame.
+    fn top_frame(&self) -> (Rc<Object>, usize) {
+        let frame = self.frame();
+        (frame.closure().function.clone(), frame.ip)
+    }
+
+    fn get_global_variable(&mut self, name: StringKey) -> Result<(), RuntimeError> {
+        match self.globals.get(&name) {
+            Some(value) => {
+                self.stack.push(value.clone());
+                Ok(())
+            }
+            None => {
+                let message = format!("Undefined variable '{}'.", name.as_str());
+                Err(self.runtime_error(message))
+            }
+        }
+    }
+
+    fn call(&mut self, closure: Gc<Object>, arg_count: usize) -> Result<(), RuntimeError> {
+        let arity = closure.as_closure().function.as_function().arity;
+        if arg_count != arity {
+            return Err(self.runtime_error(format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow.".to_string()));
+        }
+
+        let frame = CallFrame {
+            ip: 0,
+            closure,
+            slot_base: self.stack.len() - arg_count - 1,
+        };
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), RuntimeError> {
+        if callee.is_bound_method() {
+            let bound_method = callee.as_obj().as_bound_method();
+            let receiver_slot = self.stack.len() - arg_count - 1;
+            self.stack
+                .set_at(receiver_slot, bound_method.receiver.clone());
+            return self.call(bound_method.method, arg_count);
+        } else if callee.is_class() {
+            let class = callee.as_gc();
+            let instance = self.alloc(Object::Instance(RefCell::new(Instance::new(class))));
+            let receiver_slot = self.stack.len() - arg_count - 1;
+            self.stack.set_at(receiver_slot, instance);
+
+            let initializer = class.as_class().borrow().methods.get("init").cloned();
+            if let Some(initializer) = initializer {
+                return self.call(initializer.as_gc(), arg_count);
+            } else if arg_count != 0 {
+                return Err(
+                    self.runtime_error(format!("Expected 0 arguments but got {}.", arg_count))
+                );
+            }
+
+            return Ok(());
+        } else if callee.is_closure() {
+            return self.call(callee.as_gc(), arg_count);
+        } else if callee.is_native() {
+            return self.call_native(callee.as_obj().as_native(), arg_count);
+        }
+
+        Err(self.runtime_error("Can only call functions and classes.".to_string()))
+    }
+
+    fn call_native(&mut self, native: &Native, arg_count: usize) -> Result<(), RuntimeError> {
+        if arg_count != native.arity {
+            return Err(self.runtime_error(format!(
+                "Expected {} arguments but got {}.",
+                native.arity, arg_count
+            )));
+        }
+
+        let args: Vec<_> = self
+            .stack
+            .top(arg_count)
+            .iter()
+            .map(|arg| self.heap.pin(arg.clone()))
+            .collect();
+        let function = self.natives[native.id];
+        let result = match function(self, &args) {
+            Ok(result) => self.host_values(&[result])?.pop().unwrap(),
+            Err(error) => return Err(self.runtime_error(error.message)),
+        };
+        self.stack.truncate(self.stack.len() - arg_count - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: Gc<Object>,
+        name: &str,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
+        let method = class.as_class().borrow().methods.get(name).cloned();
+        match method {
+            Some(method) => self.call(method.as_gc(), arg_count),
+            None => Err(self.runtime_error(format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    fn invoke(&mut self, name: &str, arg_count: usize) -> Result<(), RuntimeError> {
+        let receiver = self.peek(arg_count).clone();
+        if !receiver.is_instance() {
+            return Err(self.runtime_error("Only instances have methods.".to_string()));
+        }
+
+        let instance = receiver.as_obj().as_instance();
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            let receiver_slot = self.stack.len() - arg_count - 1;
+            self.stack.set_at(receiver_slot, value.clone());
+            return self.call_value(value, arg_count);
+        }
+
+        let class = instance.borrow().class;
+        self.invoke_from_class(class, name, arg_count)
+    }
+
+    fn bind_method(&mut self, class: Gc<Object>, name: &str) -> Result<(), RuntimeError> {
+        let method = class.as_class().borrow().methods.get(name).cloned();
+        let method = match method {
+            Some(method) => method,
+            None => {
+                return Err(self.runtime_error(format!("Undefined property '{}'.", name)));
+            }
+        };
+
+        let bound = BoundMethod::new(self.peek(0).clone(), method.as_gc());
+        let bound = self.alloc(Object::BoundMethod(bound));
+        self.stack.pop();
+        self.stack.push(bound);
+        Ok(())
+    }
+
+    fn get_property(&mut self, name: &str) -> Result<(), RuntimeError> {
+        if !self.peek(0).is_instance() {
+            return Err(self.runtime_error("Only instances have properties.".to_string()));
+        }
+
+        let instance = self.peek(0).as_gc();
+        let field = instance.as_instance().borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            self.stack.pop(); // Instance.
+            self.stack.push(value);
+            return Ok(());
+        }
+
+        let class = instance.as_instance().borrow().class;
+        self.bind_method(class, name)
+    }
+
+    fn set_property(&mut self, name: &str) -> Result<(), RuntimeError> {
+        if !self.peek(1).is_instance() {
+            return Err(self.runtime_error("Only instances have fields.".to_string()));
+        }
+
+        let value = self.stack.pop().unwrap();
+        let instance = self.stack.pop().unwrap();
+        instance
+            .as_obj()
+            .as_instance()
+            .borrow_mut()
+            .fields
+            .insert(name.to_owned(), value.clone());
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Compiled code can't get these types wrong, hand-assembled code can.
+    fn define_method(&mut self, name: &str) -> Result<(), RuntimeError> {
+        if !self.peek(1).is_class() {
+            return Err(self.runtime_error("Methods can only be defined on classes.".to_string()));
+        }
+        if !self.peek(0).is_closure() {
+            return Err(self.runtime_error("Method must be a closure.".to_string()));
+        }
+
+        let method = self.peek(0).clone();
+        self.peek(1)
+            .as_obj()
+            .as_class()
+            .borrow_mut()
+            .methods
+            .insert(name.to_owned(), method);
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn pop_superclass(&mut self) -> Result<Gc<Object>, RuntimeError> {
+        if !self.peek(0).is_class() {
+            return Err(self.runtime_error("Superclass must be a class.".to_string()));
+        }
+        Ok(self.stack.pop().unwrap().as_gc())
+    }
+
+    // Reads the closure's upvalue operands from `code`.
+    fn closure(&mut self, function: Value, code: &[u8], ip: &mut usize) {
+        let mut closure = Closure::new(function.as_rc().clone());
+        for _ in 0..function.as_function().upvalue_count {
+            let is_local = read_byte(code, ip) == 1;
+            let index = read_byte(code, ip) as usize;
+            let upvalue = if is_local {
+                self.capture_upvalue(self.frame().slot_base + index)
+            } else {
+                self.frame().closure().upvalues[index]
+            };
+            closure.upvalues.push(upvalue);
+        }
+
+        // Every captured upvalue is also reachable from the open upvalues or
+        // the enclosing closure, so a collection here can't free them.
+        let closure = self.alloc(Object::Closure(closure));
+        self.stack.push(closure);
+    }
+
+    fn capture_upvalue(&mut self, location: usize) -> Gc<Object> {
+        for upvalue in &self.open_upvalues {
+            if upvalue.as_upvalue().borrow().location == location {
+                return *upvalue;
+            }
+        }
+
+        let upvalue = self.alloc(Object::Upvalue(RefCell::new(Upvalue::new(location))));
+        let upvalue = upvalue.as_gc();
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // Allocates `object` on the heap, first collecting garbage if the heap
+    // has grown enough. Anything the caller still needs has to be reachable
+    // from the roots at this point.
+    #[allow(unsafe_code)]
+    fn alloc(&mut self, object: Object) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        // Safety: the object is only freed by `collect_garbage`, which keeps
+        // everything the VM can still reach, and values only leave the VM
+        // through handles.
+        Value::new_obj_gc(unsafe { self.heap.alloc(object) })
+    }
+
+    // Frees every heap object not reachable from the stack, globals, call
+    // frames or open upvalues. Compiled constants are reference counted and
+    // never point into the heap, so they aren't roots.
+    #[allow(unsafe_code)]
+    pub fn collect_garbage(&mut self) {
+        for value in self.stack.iter() {
+            self.heap.mark_value(value);
+        }
+        for value in self.globals.values() {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+
+        // Safety: these are all the roots of the running program, values
+        // held by the host are pinned by their handles.
+        unsafe { self.heap.collect() };
+    }
+
+    // Upvalues whose slot was popped without being closed, which only an
+    // unverified chunk can do, are closed over nil and reported.
+    fn close_upvalues(&mut self, last: usize) -> Result<(), RuntimeError> {
+        let stack = &self.stack;
+        let mut past_top = false;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.as_upvalue().borrow_mut();
+            if upvalue.location < last {
+                return true;
+            }
+
+            let value = stack.get(upvalue.location).cloned();
+            past_top |= value.is_none();
+            upvalue.closed = Some(value.unwrap_or(Value::Nil));
+            false
+        });
+
+        if past_top {
+            return Err(self.upvalue_past_top());
+        }
+        Ok(())
+    }
+
+    fn upvalue_past_top(&mut self) -> RuntimeError {
+        self.runtime_error("Upvalue refers past the top of the stack.".to_string())
+    }
+
+    // Operates on integers when both operands are integers, otherwise both
+    // operands are promoted to floats.
+    fn binary_op(&mut self, int_op: fn(i64, i64) -> Value, float_op: fn(f64, f64) -> Value) {
         let b = self.stack.pop().unwrap();
         let a = self.stack.pop().unwrap();
-        self.stack.push(callback(a, b));
+        if a.is_int() && b.is_int() {
+            self.stack.push(int_op(a.as_number(), b.as_number()));
+        } else {
+            self.stack.push(float_op(a.as_float(), b.as_float()));
+        }
+    }
+
+    // Like `binary_op`, but integer results that don't fit in an `i64` are a
+    // runtime error.
+    fn arithmetic_op(
+        &mut self,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> RunResult {
+        let (a, b) = (self.peek(1), self.peek(0));
+        let value = if a.is_int() && b.is_int() {
+            match int_op(a.as_number(), b.as_number()) {
+                Some(n) => Value::new_number(n),
+                None => return Err(self.runtime_error("Integer overflow.".to_string())),
+            }
+        } else {
+            Value::new_float(float_op(a.as_float(), b.as_float()))
+        };
+
+        self.stack.pop();
+        self.stack.pop();
+        self.stack.push(value);
+        Ok(())
     }
 
     fn reset_stack(&mut self) {
         self.stack = Stack::new(Some(STACK_INITIAL_SIZE));
+        self.frames.clear();
+        self.open_upvalues.clear();
     }
 
     fn peek(&self, distance: usize) -> &Value {
@@ -259,49 +1174,730 @@ impl VM {
         self.stack.push(value);
     }
 
-    fn runtime_error(&self, message: String) {
-        println!("{}", message);
+    fn runtime_error(&mut self, message: String) -> RuntimeError {
+        let mut error = RuntimeError::new(message);
+        error.trace = self.call_stack();
+
+        if let Some(frame) = error.trace.first() {
+            error.offset = frame.offset;
+            error.line = frame.line;
+        }
+
+        error
+    }
+}
+
+fn read_byte(code: &[u8], ip: &mut usize) -> u8 {
+    let current_byte = code[*ip];
+    *ip += 1;
+    current_byte
+}
+
+fn read_short(code: &[u8], ip: &mut usize) -> u16 {
+    let mut buf = [0_u8; 4];
+    for byte in buf.iter_mut().take(2) {
+        *byte = read_byte(code, ip);
     }
+    BigEndian::read_u16(&buf)
+}
 
-    fn read_byte(&mut self, ip: &mut &u8) -> u8 {
-        unsafe { self.read_byte_unsafe(ip) }
+fn read_long(code: &[u8], ip: &mut usize) -> u32 {
+    let mut buf = [0_u8; 4];
+    for byte in buf.iter_mut().take(3) {
+        *byte = read_byte(code, ip);
     }
+    LittleEndian::read_u32(&buf)
+}
 
-    unsafe fn read_byte_unsafe(&mut self, ip: &mut &u8) -> u8 {
-        let current_byte = *ip;
+fn read_constant(chunk: &Chunk, ip: &mut usize) -> Value {
+    let constant_address = read_byte(&chunk.code, ip);
+    chunk.constants.values[constant_address as usize].clone()
+}
+
+fn read_long_constant(chunk: &Chunk, ip: &mut usize) -> Value {
+    let constant_address = read_long(&chunk.code, ip);
+    chunk.constants.values[constant_address as usize].clone()
+}
 
-        let ptr = *ip as *const u8;
-        *ip = &*ptr.offset(1).as_ref().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
 
-        *current_byte
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn evaluate(expression: &str) -> Handle {
+        let source = format!("var result = {};", expression);
+        let function = compiler::compile(&source).unwrap();
+
+        let mut vm = VM::new();
+        assert!(vm.interpret(function).is_ok());
+
+        vm.get_global("result").unwrap()
+    }
+
+    // Runs `source` in a fresh VM, returning what it printed.
+    fn run(source: &str) -> (String, RunResult) {
+        let function = compiler::compile(source).unwrap();
+
+        let output = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_error_output(Box::new(io::sink()));
+        let result = vm.interpret(function);
+
+        (output.contents(), result)
+    }
+
+    #[rstest]
+    #[case("1 + 2", Value::Number(3))]
+    #[case("7 / 2", Value::Number(3))]
+    #[case("-4", Value::Number(-4))]
+    #[case("1.5", Value::Float(1.5))]
+    #[case("1 + 2.5", Value::Float(3.5))]
+    #[case("2.5 - 1", Value::Float(1.5))]
+    #[case("1.5 * 2", Value::Float(3.0))]
+    #[case("7 / 2.0", Value::Float(3.5))]
+    #[case("-0.5", Value::Float(-0.5))]
+    #[case("1 == 1.0", Value::Boolean(true))]
+    #[case("1 == 1.5", Value::Boolean(false))]
+    #[case("2 < 2.5", Value::Boolean(true))]
+    #[case("2.5 >= 3", Value::Boolean(false))]
+    fn mixed_arithmetic(#[case] expression: &str, #[case] expected: Value) {
+        let value = evaluate(expression);
+
+        assert!(
+            value.type_name() == expected.type_name()
+                && format!("{:?}", value) == format!("{:?}", expected),
+            "Expected `{}` to evaluate to {:?}, got {:?}",
+            expression,
+            expected,
+            value,
+        );
+    }
+
+    #[test]
+    fn integer_division_by_zero_is_a_runtime_error() {
+        let function = compiler::compile("print 1 / 0;").unwrap();
+
+        let mut vm = VM::new();
+
+        assert!(vm.interpret(function).is_err());
+    }
+
+    // The compiler leaves these unfolded.
+    #[rstest]
+    #[case("print 1 / 0;", "Division by zero.")]
+    #[case("print -\"a\";", "Operand must be a number.")]
+    #[case("print 1 + \"a\";", "Operands must be numbers.")]
+    #[case("print 9223372036854775807 + 1;", "Integer overflow.")]
+    #[case("print nil < 1;", "Operands must be numbers.")]
+    fn unfolded_operators_fail_at_runtime(#[case] source: &str, #[case] message: &str) {
+        let (output, result) = run(source);
+
+        assert_eq!(result.unwrap_err().message, message);
+        assert_eq!(output, "");
     }
 
-    fn read_short(&mut self, ip: &mut &u8) -> u16 {
-        let mut buf = [0_u8; 4];
-        for i in 0..2 {
-            buf[i] = self.read_byte(ip);
+    #[rstest]
+    #[case("9223372036854775807 + 1")]
+    #[case("-9223372036854775807 - 2")]
+    #[case("4611686018427387904 * 2")]
+    #[case("(-9223372036854775807 - 1) / -1")]
+    #[case("-(-9223372036854775807 - 1)")]
+    fn integer_overflow_is_a_runtime_error(#[case] expression: &str) {
+        let (output, result) = run(&format!("print 1;\nprint {};", expression));
+
+        let error = result.unwrap_err();
+        assert_eq!(error.message, "Integer overflow.");
+        assert_eq!(error.line, 2);
+        assert_eq!(output, "1\n");
+    }
+
+    #[test]
+    fn float_arithmetic_does_not_overflow() {
+        let value = evaluate("9223372036854775807 + 1.0");
+
+        assert_eq!(f64::try_from(value), Ok(9223372036854775807.0));
+    }
+
+    #[test]
+    fn traces_instructions_on_selected_lines() {
+        let function = compiler::compile("var a = 1;\nprint a;\nvar b = 2;").unwrap();
+
+        let trace = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.set_trace_output(Some(Box::new(trace.clone())));
+        vm.set_trace_lines(Some(2..=2));
+        assert!(vm.interpret(function).is_ok());
+
+        assert_eq!(
+            trace.contents(),
+            concat!(
+                "    [ <script> ]\n",
+                "0004    2 OP_GET_GLOBAL 0002 'a'\n",
+                "    [ <script>, 1 ]\n",
+                "0006    2 OP_PRINT\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_opcode_is_a_runtime_error() {
+        let mut function = Function::new();
+        function.chunk.write_chunk(255, 1);
+
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Unknown opcode 255.");
+    }
+
+    #[test]
+    fn loops_before_the_chunk_are_runtime_errors() {
+        let mut function = Function::new();
+        for byte in [OpCode::OpNil as u8, OpCode::OpLoop as u8, 0, 5] {
+            function.chunk.write_chunk(byte, 1);
         }
-        BigEndian::read_u16(&buf)
+
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Loop jumps before the chunk.");
     }
 
-    fn read_long(&mut self, ip: &mut &u8) -> u32 {
-        let mut buf = [0_u8; 4];
-        for i in 0..3 {
-            buf[i] = self.read_byte(ip);
+    #[rstest]
+    #[case(&[OpCode::OpJump as u8, 0, 5, OpCode::OpReturn as u8])]
+    #[case(&[OpCode::OpFalse as u8, OpCode::OpJumpIfFalse as u8, 0, 1, OpCode::OpReturn as u8])]
+    fn jumps_past_the_chunk_are_runtime_errors(#[case] code: &[u8]) {
+        let mut function = Function::new();
+        for &byte in code {
+            function.chunk.write_chunk(byte, 1);
         }
-        LittleEndian::read_u32(&buf)
+
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Jump runs past the end of the chunk.");
+    }
+
+    #[test]
+    fn runtime_error_reports_call_stack() {
+        let source = "fun inner() {\n  return 1 + nil;\n}\nfun outer() {\n  inner();\n}\nouter();";
+        let function = compiler::compile(source).unwrap();
+
+        let mut vm = VM::new();
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Operands must be numbers.");
+        assert_eq!(error.line, 2);
+        assert_eq!(
+            error
+                .trace
+                .iter()
+                .map(|frame| (frame.function.as_deref(), frame.line))
+                .collect::<Vec<_>>(),
+            vec![(Some("inner"), 2), (Some("outer"), 5), (None, 7)],
+        );
+        assert_eq!(
+            error.to_string(),
+            "Operands must be numbers.\n[line 2] in inner()\n[line 5] in outer()\n[line 7] in script",
+        );
+    }
+
+    #[rstest]
+    #[case("fun add(a, b) { return a + b; } print add(1, 2);", "3\n")]
+    #[case("fun f() { print \"in f\"; } print f();", "in f\nnil\n")]
+    #[case("fun f() { return; print \"unreachable\"; } print f();", "nil\n")]
+    #[case(
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);",
+        "55\n"
+    )]
+    #[case(
+        "fun outer() { fun inner(x) { return x * 2; } return inner(3) + 1; } print outer();",
+        "7\n"
+    )]
+    fn functions_return_values(#[case] source: &str, #[case] expected: &str) {
+        let (output, result) = run(source);
+
+        assert!(result.is_ok());
+        assert_eq!(output, expected);
     }
 
-    fn read_constant(&mut self, ip: &mut &u8, chunk: &Chunk) -> Value {
-        chunk.constants.values[self.read_byte(ip) as usize].clone()
+    #[rstest]
+    #[case("fun f(a) {} f(1, 2);", "Expected 1 arguments but got 2.")]
+    #[case("fun f(a, b) {} f(1);", "Expected 2 arguments but got 1.")]
+    #[case("var f = 1; f();", "Can only call functions and classes.")]
+    #[case("\"f\"();", "Can only call functions and classes.")]
+    fn bad_calls_are_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let (_, result) = run(source);
+
+        assert_eq!(result.unwrap_err().message, message);
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows_the_frames() {
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+
+        let function = compiler::compile("fun f(n) { return f(n + 1); }\nf(0);").unwrap();
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Stack overflow.");
+        assert_eq!(error.trace.len(), FRAMES_MAX);
+        assert!(vm.frames.is_empty());
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn closures_outlive_the_frame_they_capture_from() {
+        let (output, result) = run(concat!(
+            "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }\n",
+            "var a = counter(); var b = counter();\n",
+            "print a(); print a(); print b();",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "1\n2\n1\n");
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let (output, result) = run(concat!(
+            "var get; var set;\n",
+            "fun make() { var x = \"before\"; fun g() { return x; } fun s(v) { x = v; }\n",
+            "  get = g; set = s; }\n",
+            "make(); set(\"after\"); print get();",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "after\n");
+    }
+
+    #[test]
+    fn upvalues_are_closed_when_their_scope_ends() {
+        let (output, result) = run(concat!(
+            "var fs = nil;\n",
+            "{ var x = \"first\"; fun f() { return x; } fs = f; x = \"closed\"; }\n",
+            "{ var y = \"other\"; print fs(); }\n",
+            "fun outer() { var x = \"outer\"; fun middle() { fun inner() { return x; }\n",
+            "  return inner; } return middle; }\n",
+            "print outer()()();",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "closed\nouter\n");
+    }
+
+    #[test]
+    fn instances_hold_fields_and_bound_methods() {
+        let (output, result) = run(concat!(
+            "class Point { init(x) { this.x = x; } sum() { return this.x + this.y; } }\n",
+            "var p = Point(1); p.y = p.x + 1;\n",
+            "var sum = p.sum; p.x = 10;\n",
+            "print p; print p.y; print sum();",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "Point instance\n2\n12\n");
+    }
+
+    #[test]
+    fn initializers_return_the_instance() {
+        let (output, result) = run(concat!(
+            "class C { init(early) { this.a = 1; if (early) return; this.a = 2; } }\n",
+            "var c = C(true); print c.a;\n",
+            "print c.init(false) == c; print c.a;",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "1\ntrue\n2\n");
+    }
+
+    #[rstest]
+    #[case("var a = 1; print a.x;", "Only instances have properties.")]
+    #[case("var a = \"s\"; a.x = 1;", "Only instances have fields.")]
+    #[case("var a = nil; a.m();", "Only instances have methods.")]
+    #[case("class C {} print C().x;", "Undefined property 'x'.")]
+    #[case("class C {} C().m();", "Undefined property 'm'.")]
+    #[case("class C {} C(1);", "Expected 0 arguments but got 1.")]
+    #[case("class C { init(a) {} } C();", "Expected 1 arguments but got 0.")]
+    fn bad_property_access_is_a_runtime_error(#[case] source: &str, #[case] message: &str) {
+        let (_, result) = run(source);
+
+        assert_eq!(result.unwrap_err().message, message);
+    }
+
+    #[rstest]
+    #[case("print this;", "Can't use 'this' outside of a class.")]
+    #[case("fun f() { return this; }", "Can't use 'this' outside of a class.")]
+    #[case(
+        "class C { init() { return 1; } }",
+        "Can't return a value from an initializer."
+    )]
+    fn bad_this_and_return_fail_to_compile(#[case] source: &str, #[case] message: &str) {
+        let errors = compiler::compile(source).unwrap_err();
+
+        assert_eq!(errors[0].message, message);
+    }
+
+    #[test]
+    fn subclasses_inherit_and_call_super_methods() {
+        let (output, result) = run(concat!(
+            "class A { init(n) { this.n = n; } name() { return \"A\"; } describe() { return this.name() + this.n; } }\n",
+            "class B < A { init() { super.init(\"1\"); } name() { return \"B\" + super.name(); } }\n",
+            "class C < B { name() { var up = super.name; return \"C\" + up(); } }\n",
+            "print B().describe(); print C().describe();",
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(output, "BA1\nCBA1\n");
+    }
+
+    #[rstest]
+    #[case("var A = 1; class B < A {}", "Superclass must be a class.")]
+    #[case("fun A() {} class B < A {}", "Superclass must be a class.")]
+    #[case(
+        "class A {} class B < A { m() { super.m(); } } B().m();",
+        "Undefined property 'm'."
+    )]
+    fn bad_inheritance_is_a_runtime_error(#[case] source: &str, #[case] message: &str) {
+        let (_, result) = run(source);
+
+        assert_eq!(result.unwrap_err().message, message);
+    }
+
+    #[rstest]
+    #[case("class A < A {}", "A class can't inherit from itself.")]
+    #[case("print super.m;", "Can't use 'super' outside of a class.")]
+    #[case(
+        "class A { m() { super.m(); } }",
+        "Can't use 'super' in a class with no superclass."
+    )]
+    fn bad_super_fails_to_compile(#[case] source: &str, #[case] message: &str) {
+        let errors = compiler::compile(source).unwrap_err();
+
+        assert_eq!(errors[0].message, message);
+    }
+
+    #[test]
+    fn vm_can_run_again_after_runtime_error() {
+        let mut vm = VM::new();
+
+        let function = compiler::compile("var a = 1; a();").unwrap();
+        assert!(vm.interpret(function).is_err());
+        assert!(vm.stack.is_empty());
+
+        let function = compiler::compile("var b = a + 1;").unwrap();
+        assert!(vm.interpret(function).is_ok());
+        assert_eq!(i64::try_from(vm.get_global("b").unwrap()), Ok(2));
+    }
+
+    #[test]
+    fn print_writes_to_output() {
+        let output = SharedBuffer::default();
+        let error_output = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_error_output(Box::new(error_output.clone()));
+
+        let function = compiler::compile("print 1; print \"two\"; print nil;").unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        assert_eq!(output.contents(), "1\ntwo\nnil\n");
+        assert_eq!(error_output.contents(), "");
+    }
+
+    #[test]
+    fn runtime_errors_write_to_error_output() {
+        let output = SharedBuffer::default();
+        let error_output = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_error_output(Box::new(error_output.clone()));
+
+        let function = compiler::compile("print 1;\nprint -nil;").unwrap();
+        assert!(vm.interpret(function).is_err());
+
+        assert_eq!(output.contents(), "1\n");
+        assert_eq!(
+            error_output.contents(),
+            "Operand must be a number.\n[line 2] in script\n"
+        );
+    }
+
+    #[test]
+    fn native_functions_are_callable() {
+        let mut vm = VM::new();
+        vm.define_native("add", 2, |_vm, args| {
+            let a = i64::try_from(args[0].clone()).unwrap();
+            let b = i64::try_from(args[1].clone()).unwrap();
+            Ok(Handle::from(a + b))
+        });
+
+        let function = compiler::compile("var result = add(1, 2) * 2;").unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        assert_eq!(i64::try_from(vm.get_global("result").unwrap()), Ok(6));
+        assert!(vm.stack.is_empty());
     }
 
-    fn read_long_constant(&mut self, ip: &mut &u8, chunk: &Chunk) -> Value {
-        let mut buf = [0_u8; 4];
-        for i in 0..3 {
-            buf[i] = self.read_byte(ip);
+    #[test]
+    fn native_function_errors_become_runtime_errors() {
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+        vm.define_native("fail", 0, |_vm, _args| {
+            Err(RuntimeError::new("Native failure.".to_string()))
+        });
+
+        let function = compiler::compile("print 1;\nfail();").unwrap();
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Native failure.");
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn native_function_arity_is_checked() {
+        let mut vm = VM::new();
+        vm.set_error_output(Box::new(io::sink()));
+
+        let function = compiler::compile("clock(1);").unwrap();
+        let error = vm.interpret(function).unwrap_err();
+
+        assert_eq!(error.message, "Expected 0 arguments but got 1.");
+    }
+
+    #[test]
+    fn host_can_get_and_set_globals() {
+        let mut vm = VM::new();
+        vm.set_global("greeting", "hello");
+        vm.set_global("count", 2_i64);
+
+        let function =
+            compiler::compile("var message = greeting + \" lox\"; count = count * 2;").unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        let message: String = vm.get_global("message").unwrap().try_into().unwrap();
+        let count: i64 = vm.get_global("count").unwrap().try_into().unwrap();
+        assert_eq!(message, "hello lox");
+        assert_eq!(count, 4);
+        assert!(vm.get_global("missing").is_none());
+    }
+
+    #[test]
+    fn host_can_call_global_functions() {
+        let mut vm = VM::new();
+        let function = compiler::compile(
+            "fun add(a, b) { return a + b; } class Point { init(x) { this.x = x; } }",
+        )
+        .unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        let sum = vm.call_global("add", &[1_i64.into(), 2.5.into()]).unwrap();
+        assert_eq!(f64::try_from(sum), Ok(3.5));
+
+        let point = vm.call_global("Point", &[3_i64.into()]).unwrap();
+        assert_eq!(format!("{:?}", point), "Point instance");
+
+        let error = vm.call_global("add", &[1_i64.into()]).unwrap_err();
+        assert_eq!(error.message, "Expected 2 arguments but got 1.");
+
+        let error = vm.call_global("missing", &[]).unwrap_err();
+        assert_eq!(error.message, "Undefined variable 'missing'.");
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn handles_only_go_back_into_their_vm() {
+        let source = "class A {} var a = A(); fun id(x) { return x; }";
+        let mut vm = VM::new();
+        assert!(vm.interpret(compiler::compile(source).unwrap()).is_ok());
+        let mut other = VM::new();
+        assert!(other.interpret(compiler::compile(source).unwrap()).is_ok());
+
+        let a = vm.get_global("a").unwrap();
+        let error = other
+            .call_global("id", std::slice::from_ref(&a))
+            .unwrap_err();
+        assert_eq!(error.message, "Value belongs to another VM.");
+        assert_eq!(
+            format!("{:?}", vm.call_global("id", &[a]).unwrap()),
+            "A instance"
+        );
+    }
+
+    #[test]
+    fn natives_can_call_back_into_lox() {
+        let mut vm = VM::new();
+        vm.define_native("twice", 0, |vm, _args| {
+            let once = i64::try_from(vm.call_global("next", &[])?)
+                .map_err(|e| RuntimeError::new(e.to_string()))?;
+            let twice = i64::try_from(vm.call_global("next", &[])?)
+                .map_err(|e| RuntimeError::new(e.to_string()))?;
+            Ok(Handle::from(once + twice))
+        });
+
+        let function = compiler::compile(
+            "var n = 0; fun next() { n = n + 1; return n; } var result = twice() * 10;",
+        )
+        .unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        assert_eq!(i64::try_from(vm.get_global("result").unwrap()), Ok(30));
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn natives_can_recover_from_errors_in_lox() {
+        let mut vm = VM::new();
+        vm.define_native("attempt", 1, |vm, args| {
+            let name =
+                String::try_from(args[0].clone()).map_err(|e| RuntimeError::new(e.to_string()))?;
+            match vm.call_global(&name, &[]) {
+                Ok(value) => Ok(value),
+                Err(error) => Ok(Handle::from(error.message)),
+            }
+        });
+
+        let function = compiler::compile(concat!(
+            "fun fails() { var captured = 1; fun f() { return captured; } return 1 + nil; }\n",
+            "fun run() { var local = \"kept\"; var message = attempt(\"fails\");\n",
+            "  return local + \" \" + message; }\n",
+            "var result = run();",
+        ))
+        .unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        let result: String = vm.get_global("result").unwrap().try_into().unwrap();
+        assert_eq!(result, "kept Operands must be numbers.");
+        assert!(vm.stack.is_empty());
+        assert!(vm.open_upvalues.is_empty());
+    }
+
+    #[test]
+    fn handles_keep_values_alive() {
+        let mut vm = VM::new();
+        let function =
+            compiler::compile("class A { init() { this.s = \"x\"; } } var a = A();").unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        let a = vm.get_global("a").unwrap();
+        assert!(vm.interpret(compiler::compile("a = nil;").unwrap()).is_ok());
+        vm.collect_garbage();
+        // The instance, its class and the class's `init`.
+        assert_eq!(vm.heap.object_count(), 3);
+
+        let instance = vm.heap.value_of(&a).unwrap();
+        let s = instance.as_obj().as_instance().borrow().fields["s"].clone();
+        drop(vm);
+        assert_eq!(format!("{:?} {:?}", a, s), "A instance x");
+    }
+
+    #[test]
+    fn dropped_handles_no_longer_root_their_values() {
+        let mut vm = VM::new();
+        let function = compiler::compile("class A {} var a = A(); a = nil;").unwrap();
+        assert!(vm.interpret(function).is_ok());
+
+        let class = vm.get_global("A").unwrap();
+        vm.collect_garbage();
+        assert_eq!(vm.heap.object_count(), 1);
+
+        vm.set_global("A", ());
+        vm.collect_garbage();
+        assert_eq!(vm.heap.object_count(), 1);
+
+        drop(class);
+        vm.collect_garbage();
+        assert_eq!(vm.heap.object_count(), 0);
+    }
+
+    #[rstest]
+    #[case("closures.lox", "1\n2\noutside\nupdated\n")]
+    #[case(
+        "inheritance.lox",
+        "Fry until golden brown.\nPipe full of custard and coat with chocolate.\nDoughnut filled with custard, topped with chocolate\n"
+    )]
+    fn programs_survive_stress_gc(#[case] sample: &str, #[case] expected: &str) {
+        let path = format!(
+            "{}/../runner/samples/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            sample
+        );
+        let source = std::fs::read_to_string(path).unwrap();
+        let function = compiler::compile(&source).unwrap();
+
+        let output = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_gc_stress(true);
+        vm.set_output(Box::new(output.clone()));
+
+        assert!(vm.interpret(function).is_ok());
+        assert_eq!(output.contents(), expected);
+    }
+
+    #[test]
+    fn gc_growth_factor_delays_the_next_collection() {
+        // Enough live nodes to pass the initial threshold.
+        let live = "class Node {} var list = nil;\n\
+            for (var i = 0; i < 50000; i = i + 1) { var n = Node(); n.next = list; list = n; }";
+        let garbage = "for (var i = 0; i < 10; i = i + 1) Node();";
+
+        for (growth_factor, collects) in [(1, true), (4, false)] {
+            let mut vm = VM::new();
+            assert!(vm.interpret(compiler::compile(live).unwrap()).is_ok());
+            vm.set_gc_growth_factor(growth_factor);
+            vm.collect_garbage();
+            let count = vm.heap.object_count();
+
+            assert!(vm.interpret(compiler::compile(garbage).unwrap()).is_ok());
+            let collected = vm.heap.object_count() < count + 10;
+            assert_eq!(collected, collects, "growth factor {}", growth_factor);
         }
-        let constant_address = LittleEndian::read_u32(&buf);
-        chunk.constants.values[constant_address as usize].clone()
+    }
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let source = "class Node {}\n\
+            for (var i = 0; i < 100; i = i + 1) {\n\
+              var a = Node(); var b = Node();\n\
+              a.next = b; b.next = a;\n\
+            }\n\
+            var kept = Node(); kept.self = kept;";
+        let function = compiler::compile(source).unwrap();
+
+        let mut vm = VM::new();
+        assert!(vm.interpret(function).is_ok());
+        assert!(vm.heap.object_count() > 200);
+
+        vm.collect_garbage();
+
+        // The class and the instance held by `kept`.
+        assert_eq!(vm.heap.object_count(), 2);
+        let kept = vm.heap.value_of(&vm.get_global("kept").unwrap()).unwrap();
+        let field = kept.as_obj().as_instance().borrow().fields["self"].clone();
+        assert!(field.values_equal(&kept));
     }
 }